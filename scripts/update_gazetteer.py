@@ -0,0 +1,88 @@
+#!/usr/bin/env python3
+"""Replaces the bundled gazetteer in assets/gazetteer with a GeoNames extract.
+
+Downloads the GeoNames cities dump (cities15000 by default), the first-level
+administrative division names and the country names, and writes them in the
+tab-separated format read by src/geocode.rs:
+
+    cities.tsv     name, region, country code, latitude, longitude
+    countries.tsv  country code, country name
+
+GeoNames data is licensed under CC BY 4.0, which requires attribution; the
+generated files carry it in their header, next to the dump they came from.
+
+Usage: scripts/update_gazetteer.py [--dump cities15000|cities5000|cities1000|cities500]
+"""
+
+import argparse
+import datetime
+import io
+import pathlib
+import urllib.request
+import zipfile
+
+BASE_URL = "https://download.geonames.org/export/dump/"
+OUTPUT = pathlib.Path(__file__).resolve().parent.parent / "assets" / "gazetteer"
+ATTRIBUTION = [
+    "# Source: GeoNames (https://www.geonames.org/), {source}, downloaded {date}.",
+    "# GeoNames data is licensed under CC BY 4.0 (https://creativecommons.org/licenses/by/4.0/).",
+    "# Generated by scripts/update_gazetteer.py; do not edit by hand.",
+]
+
+
+def fetch(name):
+    with urllib.request.urlopen(BASE_URL + name) as response:
+        return response.read()
+
+
+def header(source, columns):
+    date = datetime.date.today().isoformat()
+    return [line.format(source=source, date=date) for line in ATTRIBUTION] + ["# " + "\t".join(columns)]
+
+
+def clean(text):
+    return " ".join(text.replace("\t", " ").split())
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("--dump", default="cities15000", choices=["cities15000", "cities5000", "cities1000", "cities500"])
+    args = parser.parse_args()
+
+    with zipfile.ZipFile(io.BytesIO(fetch(args.dump + ".zip"))) as archive:
+        cities = archive.read(args.dump + ".txt").decode("utf-8")
+    admin1 = {}
+    for line in fetch("admin1CodesASCII.txt").decode("utf-8").splitlines():
+        fields = line.split("\t")
+        if len(fields) >= 2:
+            admin1[fields[0]] = clean(fields[1])
+    countries = {}
+    for line in fetch("countryInfo.txt").decode("utf-8").splitlines():
+        fields = line.split("\t")
+        if line.startswith("#") or len(fields) < 5:
+            continue
+        countries[fields[0]] = clean(fields[4])
+
+    places = []
+    for line in cities.splitlines():
+        fields = line.split("\t")
+        if len(fields) < 11:
+            continue
+        code = fields[8]
+        region = admin1.get(f"{code}.{fields[10]}", "")
+        places.append((code, clean(fields[1]), region, float(fields[4]), float(fields[5])))
+    places.sort()
+
+    lines = header(args.dump, ["Name", "Region", "Country code", "Latitude", "Longitude"])
+    lines += [f"{name}\t{region}\t{code}\t{latitude:.5f}\t{longitude:.5f}" for code, name, region, latitude, longitude in places]
+    (OUTPUT / "cities.tsv").write_text("\n".join(lines) + "\n", encoding="utf-8")
+
+    lines = header("countryInfo.txt", ["ISO 3166-1 alpha-2 code", "Country name"])
+    lines += [f"{code}\t{name}" for code, name in sorted(countries.items())]
+    (OUTPUT / "countries.tsv").write_text("\n".join(lines) + "\n", encoding="utf-8")
+
+    print(f"Wrote {len(places)} places and {len(countries)} countries to {OUTPUT}")
+
+
+if __name__ == "__main__":
+    main()
@@ -1,39 +1,190 @@
-use photo_statistics::statistics::{generate_statistics, Statistics};
+use photo_statistics::bodies::BodyUsage;
+use photo_statistics::config::{Config, FileConfig};
+use photo_statistics::exiftool::shutdown_pool;
+use photo_statistics::export::{ExportFormat, TrackGrouping};
+use photo_statistics::extractor::ExtractorBackend;
+use photo_statistics::filter::StatisticsFilter;
+use photo_statistics::insights::GearInsights;
+use photo_statistics::lenses::LensReport;
+use photo_statistics::statistics::{
+    generate_filtered_body_statistics, generate_filtered_gear_insights, generate_filtered_lens_report, generate_filtered_photo_map, generate_filtered_statistics, generate_filtered_summary, generate_pivot, Dimension, PivotPreset, PivotTable,
+    Statistics,
+};
+use photo_statistics::sessions::{Session, SessionTrend};
+use photo_statistics::summary::{GroupSummary, SummaryGroup};
+use photo_statistics::worker::finish_scan;
 use rusqlite::Connection;
 use std::sync::Mutex;
 use tauri::{State, Manager};
 
+/// Configuration file read at startup, in the format of the CLI's `--config`.
+const CONFIG_PATH: &str = "photo_stats_config.json";
+
 struct AppState {
     conn: Mutex<Connection>,
+    config: FileConfig,
+}
+
+#[tauri::command]
+fn get_statistics(
+    state: State<AppState>,
+    filter: Option<StatisticsFilter>,
+) -> Result<Statistics, String> {
+    let conn = state.conn.lock().map_err(|_| "Failed to lock database connection")?;
+    generate_filtered_statistics(
+        &conn,
+        &filter.unwrap_or_default(),
+        &state.config.buckets,
+        &state.config.output,
+        &state.config.crop_factors,
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_pivot(
+    state: State<AppState>,
+    preset: Option<PivotPreset>,
+    rows: Option<Dimension>,
+    columns: Option<Dimension>,
+    filter: Option<StatisticsFilter>,
+) -> Result<PivotTable, String> {
+    let (rows, columns) = match (preset, rows.zip(columns)) {
+        (_, Some(dimensions)) => dimensions,
+        (Some(preset), None) => preset.dimensions(),
+        (None, None) => return Err("Either a preset or both rows and columns are required".to_string()),
+    };
+    let conn = state.conn.lock().map_err(|_| "Failed to lock database connection")?;
+    generate_pivot(&conn, rows, columns, &filter.unwrap_or_default(), &state.config.buckets).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_summary(
+    state: State<AppState>,
+    group_by: Option<SummaryGroup>,
+    filter: Option<StatisticsFilter>,
+) -> Result<Vec<GroupSummary>, String> {
+    let conn = state.conn.lock().map_err(|_| "Failed to lock database connection")?;
+    generate_filtered_summary(&conn, &filter.unwrap_or_default(), group_by, &state.config.crop_factors)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn export_map(
+    state: State<AppState>,
+    format: ExportFormat,
+    tracks: Option<TrackGrouping>,
+    filter: Option<StatisticsFilter>,
+) -> Result<String, String> {
+    let conn = state.conn.lock().map_err(|_| "Failed to lock database connection")?;
+    let map = generate_filtered_photo_map(&conn, &filter.unwrap_or_default(), tracks).map_err(|e| e.to_string())?;
+    map.render(format).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_body_statistics(state: State<AppState>, filter: Option<StatisticsFilter>) -> Result<Vec<BodyUsage>, String> {
+    let conn = state.conn.lock().map_err(|_| "Failed to lock database connection")?;
+    generate_filtered_body_statistics(&conn, &filter.unwrap_or_default(), &state.config.bodies).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_gear_insights(state: State<AppState>, filter: Option<StatisticsFilter>) -> Result<GearInsights, String> {
+    let conn = state.conn.lock().map_err(|_| "Failed to lock database connection")?;
+    let today = chrono::Local::now().date_naive();
+    generate_filtered_gear_insights(&conn, &filter.unwrap_or_default(), &state.config.insights, today)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_lens_report(state: State<AppState>, filter: Option<StatisticsFilter>) -> Result<LensReport, String> {
+    let conn = state.conn.lock().map_err(|_| "Failed to lock database connection")?;
+    generate_filtered_lens_report(&conn, &filter.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn detect_sessions(state: State<AppState>) -> Result<Vec<Session>, String> {
+    let conn = state.conn.lock().map_err(|_| "Failed to lock database connection")?;
+    photo_statistics::sessions::detect_sessions(&conn, &state.config.sessions).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_sessions(state: State<AppState>) -> Result<Vec<Session>, String> {
+    let conn = state.conn.lock().map_err(|_| "Failed to lock database connection")?;
+    photo_statistics::sessions::load_sessions(&conn).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn get_statistics(state: State<AppState>) -> Result<Statistics, String> {
+fn get_session_trend(state: State<AppState>) -> Result<Vec<SessionTrend>, String> {
     let conn = state.conn.lock().map_err(|_| "Failed to lock database connection")?;
-    generate_statistics(&conn).map_err(|e| e.to_string())
+    let sessions = photo_statistics::sessions::load_sessions(&conn).map_err(|e| e.to_string())?;
+    Ok(photo_statistics::sessions::session_trend(&sessions))
 }
 
 #[tauri::command]
-async fn start_scan(directory: String) -> Result<String, String> {
+fn reindex(state: State<AppState>) -> Result<String, String> {
+    let conn = state.conn.lock().map_err(|_| "Failed to lock database connection")?;
+    let config = &state.config;
+    let report = photo_statistics::reindex::reindex_catalog(&conn, &config.extra_tags)
+        .map_err(|e| e.to_string())?;
+    let finished = finish_scan(&conn, &Config::from_file(config.clone())).map_err(|e| e.to_string())?;
+    Ok(format!(
+        "Rebuilt metadata for {} photos ({} need a forced rescan). Resolved {} photo positions.",
+        report.reindexed,
+        report.missing_raw.len(),
+        finished.placed
+    ))
+}
+
+#[tauri::command]
+async fn start_scan(
+    state: State<'_, AppState>,
+    directory: String,
+    backend: Option<ExtractorBackend>,
+    force: Option<bool>,
+    prune: Option<bool>,
+) -> Result<String, String> {
     let path = std::path::Path::new(&directory);
     if !path.exists() {
         return Err(format!("Directory does not exist: {}", directory));
     }
     
     // Create config
-    let config = photo_statistics::config::Config {
+    let config = Config {
         directory: directory.clone(),
-        database_path: std::path::PathBuf::from("photo_stats_cache.db"),
+        backend: backend.or(state.config.backend).unwrap_or_default(),
+        force: force.unwrap_or(false),
+        prune: prune.unwrap_or(false),
+        ..Config::from_file(state.config.clone())
     };
 
     let files = photo_statistics::files::scan_directory(&directory);
         
-    let count = files.len();
-    
-    photo_statistics::worker::process_files_in_parallel(files, &config)
+    let result = photo_statistics::worker::process_files_in_parallel(files, &config);
+    shutdown_pool();
+    let summary = result.map_err(|e| e.to_string())?;
+
+    let mut message = format!(
+        "Processed {} photos ({} failed). {} new, {} changed, {} unchanged.",
+        summary.succeeded, summary.failed, summary.new, summary.changed, summary.unchanged
+    );
+
+    if config.prune {
+        let conn = photo_statistics::database::open_database(&config.database_path)
+            .map_err(|e| e.to_string())?;
+        let report = photo_statistics::prune::prune_missing_files(&conn, &directory, false)
+            .map_err(|e| e.to_string())?;
+        message.push_str(&format!(" Removed {} missing photos.", report.removed));
+    }
+
+    let conn = photo_statistics::database::open_database(&config.database_path)
         .map_err(|e| e.to_string())?;
-        
-    Ok(format!("Successfully processed {} photos.", count))
+    let finished = finish_scan(&conn, &config).map_err(|e| e.to_string())?;
+    message.push_str(&format!(
+        " Found {} RAW+JPEG pairs, {} cameras and {} lenses. Resolved {} photo positions.",
+        finished.pairs, finished.cameras, finished.lenses, finished.placed
+    ));
+
+    Ok(message)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -44,13 +195,17 @@ pub fn run() {
       // Ensure the database exists or handle error. 
       // For dev, we assume it might exist or we create it.
       // In a real app, we might check app_data_dir.
-      let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
-      
-      // Initialize tables if needed
-      photo_statistics::database::create_tables_if_needed(&conn).map_err(|e| e.to_string())?;
+      // Opening the database applies any pending schema migrations.
+      let conn = photo_statistics::database::open_database(std::path::Path::new(db_path))
+          .map_err(|e| e.to_string())?;
+      // The same settings as the CLI's `--config`, read once; defaults when the file is absent.
+      let config_path = std::path::Path::new(CONFIG_PATH);
+      let config = FileConfig::load_optional(config_path.exists().then_some(config_path))
+          .map_err(|e| e.to_string())?;
 
       app.manage(AppState {
           conn: Mutex::new(conn),
+          config,
       });
 
       if cfg!(debug_assertions) {
@@ -62,7 +217,20 @@ pub fn run() {
       }
       Ok(())
     })
-    .invoke_handler(tauri::generate_handler![get_statistics, start_scan])
+    .invoke_handler(tauri::generate_handler![
+      detect_sessions,
+      export_map,
+      get_body_statistics,
+      get_gear_insights,
+      get_lens_report,
+      get_pivot,
+      get_session_trend,
+      get_sessions,
+      get_statistics,
+      get_summary,
+      reindex,
+      start_scan
+    ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
 }
@@ -0,0 +1,286 @@
+//! Per-body usage and shutter wear.
+//!
+//! Several copies of one camera model are told apart by their serial number
+//! (`SerialNumber`, or else `InternalSerialNumber`). For every body the report
+//! gives the first and last use, the photos taken and the shutter count over
+//! time, from the maker-note `ShutterCount` or `ImageCount` where the camera
+//! records one. The current wear rate projects the date at which the body
+//! reaches its rated shutter life.
+
+use crate::cameras::CAMERA_SQL;
+use chrono::{Days, NaiveDate};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Shutter life settings, read from the `bodies` section of the configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BodyConfig {
+    /// Actuations the shutter is rated for
+    pub rated_shutter_life: u32,
+    /// Rated shutter life per canonical camera name, overriding
+    /// `rated_shutter_life`
+    pub rated_shutter_life_by_camera: BTreeMap<String, u32>,
+}
+
+impl Default for BodyConfig {
+    fn default() -> Self {
+        Self { rated_shutter_life: 200_000, rated_shutter_life_by_camera: BTreeMap::new() }
+    }
+}
+
+impl BodyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rated_shutter_life == 0 {
+            return Err("rated_shutter_life must be positive".to_string());
+        }
+        if let Some((camera, _)) = self.rated_shutter_life_by_camera.iter().find(|(_, life)| **life == 0) {
+            return Err(format!("rated_shutter_life_by_camera for {:?} must be positive", camera));
+        }
+        Ok(())
+    }
+
+    fn rated_life(&self, camera: &str) -> u32 {
+        self.rated_shutter_life_by_camera.get(camera).copied().unwrap_or(self.rated_shutter_life)
+    }
+}
+
+/// Shutter count of a body at the end of one month.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShutterCountPoint {
+    /// "2024-03"
+    pub month: String,
+    /// Photos in the catalog taken that month
+    pub photos: i32,
+    pub shutter_count: i64,
+    /// Derived from the photos taken rather than recorded by the camera
+    pub estimated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BodyUsage {
+    /// Canonical camera name
+    pub camera: String,
+    /// `None` for the photos of this camera without a serial number
+    pub serial_number: Option<String>,
+    pub photos: i32,
+    /// Date of the first dated photo, "2023-06-12"
+    pub first_used: Option<String>,
+    pub last_used: Option<String>,
+    /// Latest recorded shutter count, or the photos in the catalog when the
+    /// body records none
+    pub shutter_count: i64,
+    pub shutter_count_estimated: bool,
+    /// Average actuations per day between the first and last known count
+    pub shots_per_day: Option<f64>,
+    pub rated_shutter_life: u32,
+    /// Share of the rated shutter life used
+    pub rated_life_percent: f64,
+    /// Date at which the rated life is reached at the current rate; `None`
+    /// when it is already reached or the rate is unknown
+    pub projected_end_of_life: Option<String>,
+    pub history: Vec<ShutterCountPoint>,
+}
+
+/// One photo of a body: capture date and recorded shutter count.
+type BodyPhoto = (Option<NaiveDate>, Option<i64>);
+
+fn shots_per_day(from: (NaiveDate, i64), to: (NaiveDate, i64)) -> Option<f64> {
+    let days = (to.0 - from.0).num_days();
+    (days > 0 && to.1 > from.1).then(|| (to.1 - from.1) as f64 / days as f64)
+}
+
+/// Month-end shutter counts. Months without a recorded count are estimated
+/// from the photos taken: forward from the previous count, backward from the
+/// next one before the first recording, or counting from zero when the body
+/// records none.
+fn shutter_history(photos: &[BodyPhoto]) -> Vec<ShutterCountPoint> {
+    let mut months: BTreeMap<String, (i32, Option<i64>)> = BTreeMap::new();
+    for (date, count) in photos {
+        if let Some(date) = date {
+            let month = months.entry(date.format("%Y-%m").to_string()).or_default();
+            month.0 += 1;
+            month.1 = month.1.max(*count);
+        }
+    }
+
+    let mut history: Vec<ShutterCountPoint> = Vec::new();
+    let mut previous: Option<i64> = None;
+    for (month, (photos, recorded)) in &months {
+        let (shutter_count, estimated) = match (recorded, previous) {
+            (Some(count), _) => (*count, false),
+            (None, Some(before)) => (before + i64::from(*photos), true),
+            (None, None) => (0, true),
+        };
+        if recorded.is_some() || previous.is_some() {
+            previous = Some(shutter_count);
+        }
+        history.push(ShutterCountPoint { month: month.clone(), photos: *photos, shutter_count, estimated });
+    }
+
+    match history.iter().position(|point| !point.estimated) {
+        // Before the first recording, each month ends at the next month's
+        // count minus the photos taken in the next month.
+        Some(first) => {
+            for index in (0..first).rev() {
+                let next = &history[index + 1];
+                history[index].shutter_count = (next.shutter_count - i64::from(next.photos)).max(0);
+            }
+        }
+        None => {
+            let mut total = 0;
+            for point in &mut history {
+                total += i64::from(point.photos);
+                point.shutter_count = total;
+            }
+        }
+    }
+    history
+}
+
+fn body_usage(camera: String, serial_number: Option<String>, photos: &[BodyPhoto], config: &BodyConfig) -> BodyUsage {
+    let dates: Vec<NaiveDate> = photos.iter().filter_map(|(date, _)| *date).collect();
+    let first_used = dates.iter().min().copied();
+    let last_used = dates.iter().max().copied();
+
+    let mut recorded: Vec<(NaiveDate, i64)> =
+        photos.iter().filter_map(|(date, count)| date.zip(*count)).collect();
+    recorded.sort();
+    let latest_count = photos.iter().filter_map(|(_, count)| *count).max();
+
+    let (shutter_count, shutter_count_estimated) = match latest_count {
+        Some(count) => (count, false),
+        None => (photos.len() as i64, true),
+    };
+    let rate = match (recorded.first(), recorded.last()) {
+        (Some(first), Some(last)) if recorded.len() > 1 => shots_per_day(*first, *last),
+        _ if latest_count.is_none() => first_used.zip(last_used).and_then(|(first, last)| {
+            shots_per_day((first, 0), (last, photos.len() as i64))
+        }),
+        _ => None,
+    };
+
+    let rated_shutter_life = config.rated_life(&camera);
+    let remaining = i64::from(rated_shutter_life) - shutter_count;
+    let last_known = recorded.last().map(|(date, _)| *date).or(last_used);
+    let projected_end_of_life = match (rate, last_known) {
+        (Some(rate), Some(from)) if remaining > 0 => from
+            .checked_add_days(Days::new((remaining as f64 / rate).ceil() as u64))
+            .map(|date| date.format("%Y-%m-%d").to_string()),
+        _ => None,
+    };
+
+    BodyUsage {
+        camera,
+        serial_number,
+        photos: photos.len() as i32,
+        first_used: first_used.map(|date| date.format("%Y-%m-%d").to_string()),
+        last_used: last_used.map(|date| date.format("%Y-%m-%d").to_string()),
+        shutter_count,
+        shutter_count_estimated,
+        shots_per_day: rate,
+        rated_shutter_life,
+        rated_life_percent: 100.0 * shutter_count as f64 / f64::from(rated_shutter_life),
+        projected_end_of_life,
+        history: shutter_history(photos),
+    }
+}
+
+/// Reports the usage of every camera body among the photos matching
+/// `condition`, ordered by camera name and serial number.
+pub fn generate_body_statistics(
+    connection: &Connection,
+    condition: &str,
+    params: &[SqlValue],
+    config: &BodyConfig,
+) -> Result<Vec<BodyUsage>, anyhow::Error> {
+    let mut stmt = connection.prepare(&format!(
+        "SELECT {}, SerialNumber, DateTimeOriginal, ShutterCount FROM metadata
+         WHERE {} AND Model IS NOT NULL",
+        CAMERA_SQL, condition
+    ))?;
+    let mut bodies: BTreeMap<(String, Option<String>), Vec<BodyPhoto>> = BTreeMap::new();
+    for row in stmt.query_map(params_from_iter(params), |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, Option<String>>(1)?,
+            row.get::<_, Option<String>>(2)?,
+            row.get::<_, Option<i64>>(3)?,
+        ))
+    })? {
+        let (camera, serial_number, taken, shutter_count) = row?;
+        let date = taken.and_then(|taken| NaiveDate::parse_from_str(&taken[..10.min(taken.len())], "%Y-%m-%d").ok());
+        bodies.entry((camera, serial_number)).or_default().push((date, shutter_count));
+    }
+
+    Ok(bodies
+        .into_iter()
+        .map(|((camera, serial_number), photos)| body_usage(camera, serial_number, &photos, config))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::insert_metadata;
+    use crate::migrations::run_migrations;
+    use serde_json::json;
+
+    #[test]
+    fn test_shutter_history_estimates() {
+        let date = |month: u32| NaiveDate::from_ymd_opt(2024, month, 10);
+        let photos = [(date(1), None), (date(1), None), (date(2), Some(1000)), (date(3), None), (date(4), Some(1500))];
+        let history = shutter_history(&photos);
+        let counts: Vec<(&str, i64, bool)> =
+            history.iter().map(|point| (point.month.as_str(), point.shutter_count, point.estimated)).collect();
+        assert_eq!(counts, vec![("2024-01", 999, true), ("2024-02", 1000, false), ("2024-03", 1001, true), ("2024-04", 1500, false)]);
+
+        let unrecorded = shutter_history(&[(date(1), None), (date(3), None), (date(3), None)]);
+        assert_eq!(unrecorded.iter().map(|point| point.shutter_count).collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_body_statistics() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let files = [
+            ("a.nef", json!({ "Model": "Z 6", "SerialNumber": "3001", "ShutterCount": 10000, "DateTimeOriginal": "2024:01:01 10:00:00" })),
+            ("b.nef", json!({ "Model": "Z 6", "SerialNumber": "3001", "ShutterCount": 11000, "DateTimeOriginal": "2024:01:11 10:00:00" })),
+            ("c.nef", json!({ "Model": "Z 6", "InternalSerialNumber": "3002", "DateTimeOriginal": "2023:05:01 10:00:00" })),
+            ("d.nef", json!({ "Model": "Z 6", "SerialNumber": "3002", "DateTimeOriginal": "2023:05:03 10:00:00" })),
+            ("e.raf", json!({ "Model": "X-T5", "ImageCount": "250" })),
+        ];
+        for (file, metadata) in &files {
+            insert_metadata(&conn, file, 1234567890.0, 0, metadata).unwrap();
+        }
+        let config = BodyConfig {
+            rated_shutter_life: 20_000,
+            rated_shutter_life_by_camera: BTreeMap::from([("X-T5".to_string(), 300_000)]),
+        };
+
+        let bodies = generate_body_statistics(&conn, "1", &[], &config).unwrap();
+        assert_eq!(bodies.len(), 3);
+
+        let worn = &bodies[1];
+        assert_eq!((worn.camera.as_str(), worn.serial_number.as_deref()), ("Z 6", Some("3001")));
+        assert_eq!((worn.first_used.as_deref(), worn.last_used.as_deref()), (Some("2024-01-01"), Some("2024-01-11")));
+        assert_eq!((worn.shutter_count, worn.shutter_count_estimated), (11000, false));
+        assert_eq!(worn.shots_per_day, Some(100.0));
+        assert_eq!(worn.rated_life_percent, 55.0);
+        // 9000 actuations left at 100 per day.
+        assert_eq!(worn.projected_end_of_life.as_deref(), Some("2024-04-10"));
+
+        let spare = &bodies[2];
+        assert_eq!(spare.serial_number.as_deref(), Some("3002"));
+        assert_eq!((spare.photos, spare.shutter_count, spare.shutter_count_estimated), (2, 2, true));
+        assert_eq!(spare.shots_per_day, Some(1.0));
+
+        let fuji = &bodies[0];
+        assert_eq!((fuji.camera.as_str(), fuji.serial_number.as_deref()), ("X-T5", None));
+        assert_eq!((fuji.shutter_count, fuji.rated_shutter_life), (250, 300_000));
+        assert_eq!(fuji.projected_end_of_life, None);
+        assert!(fuji.history.is_empty());
+    }
+}
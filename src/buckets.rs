@@ -0,0 +1,208 @@
+//! Grouping of numeric exposure values into photographic stops and ranges.
+//!
+//! Buckets are computed from the typed `metadata` columns, so "1/250" and
+//! "0.004" land in the same shutter bucket, and a zoom lens' hundreds of
+//! focal lengths collapse into a handful of ranges. Results are ordered from
+//! the lowest to the highest value, with photos lacking the value last.
+
+use serde::{Deserialize, Serialize};
+
+/// Label of the bucket holding photos without the value.
+pub const NOT_AVAILABLE: &str = "N/A";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Bucket {
+    pub label: String,
+    pub count: i32,
+}
+
+/// Granularity of the aperture buckets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApertureStops {
+    #[default]
+    Full,
+    Third,
+}
+
+/// Bucket settings, read from the `buckets` section of the configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BucketConfig {
+    /// Focal length range boundaries in mm. `[24, 35, 70]` yields the ranges
+    /// "<24", "24-35", "35-70" and "70+"; each range includes its lower bound.
+    pub focal_ranges: Vec<f64>,
+    pub aperture_stops: ApertureStops,
+}
+
+impl Default for BucketConfig {
+    fn default() -> Self {
+        Self {
+            focal_ranges: vec![14.0, 24.0, 35.0, 70.0, 135.0, 200.0, 400.0],
+            aperture_stops: ApertureStops::Full,
+        }
+    }
+}
+
+impl BucketConfig {
+    /// Checks that the focal range boundaries are positive and increasing.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.focal_ranges.iter().any(|edge| !edge.is_finite() || *edge <= 0.0) {
+            return Err("focal_ranges must contain positive numbers".to_string());
+        }
+        if self.focal_ranges.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err("focal_ranges must be strictly increasing".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Groups `(value, count)` pairs with `classify`, which maps a value to its
+/// bucket's position and label.
+pub fn bucketize(values: &[(Option<f64>, i32)], classify: impl Fn(f64) -> (i64, String)) -> Vec<Bucket> {
+    let mut buckets: std::collections::BTreeMap<i64, Bucket> = std::collections::BTreeMap::new();
+    let mut missing = 0;
+    for (value, count) in values {
+        match value.filter(|v| v.is_finite() && *v > 0.0) {
+            Some(value) => {
+                let (position, label) = classify(value);
+                buckets.entry(position).or_insert(Bucket { label, count: 0 }).count += count;
+            }
+            None => missing += count,
+        }
+    }
+
+    let mut ordered: Vec<Bucket> = buckets.into_values().collect();
+    if missing > 0 {
+        ordered.push(Bucket { label: NOT_AVAILABLE.to_string(), count: missing });
+    }
+    ordered
+}
+
+/// Nearest full stop relative to ISO 100: 100, 200, 400, ...
+pub fn iso_stop(iso: f64) -> (i64, String) {
+    let stop = (iso / 100.0).log2().round() as i64;
+    let nominal = 100.0 * 2f64.powi(stop as i32);
+    (stop, format!("{}", nominal.round() as i64))
+}
+
+/// Nearest full shutter stop, labeled with the marked camera value.
+pub fn shutter_stop(seconds: f64) -> (i64, String) {
+    const MARKED: [&str; 19] = [
+        "1/8000", "1/4000", "1/2000", "1/1000", "1/500", "1/250", "1/125", "1/60", "1/30", "1/15", "1/8",
+        "1/4", "1/2", "1s", "2s", "4s", "8s", "15s", "30s",
+    ];
+    let stop = seconds.log2().round() as i64;
+    let label = match usize::try_from(stop + 13).ok().and_then(|index| MARKED.get(index)) {
+        Some(label) => label.to_string(),
+        None if stop > 0 => format!("{}s", 2i64.pow(stop as u32)),
+        None => format!("1/{}", 2i64.pow((-stop) as u32)),
+    };
+    (stop, label)
+}
+
+/// Nearest full or third aperture stop, labeled with the marked f-number.
+pub fn aperture_stop(f_number: f64, stops: ApertureStops) -> (i64, String) {
+    const FULL: [&str; 13] = ["1.0", "1.4", "2.0", "2.8", "4.0", "5.6", "8", "11", "16", "22", "32", "45", "64"];
+    const THIRDS: [&str; 31] = [
+        "1.0", "1.1", "1.2", "1.4", "1.6", "1.8", "2.0", "2.2", "2.5", "2.8", "3.2", "3.5", "4.0", "4.5", "5.0",
+        "5.6", "6.3", "7.1", "8", "9", "10", "11", "13", "14", "16", "18", "20", "22", "25", "29", "32",
+    ];
+    let (steps_per_stop, marked): (f64, &[&str]) = match stops {
+        ApertureStops::Full => (1.0, &FULL),
+        ApertureStops::Third => (3.0, &THIRDS),
+    };
+    // Each full stop multiplies the f-number by sqrt(2).
+    let position = (2.0 * f_number.log2() * steps_per_stop).round() as i64;
+    let label = match usize::try_from(position).ok().and_then(|index| marked.get(index)) {
+        Some(label) => label.to_string(),
+        None => format!("{:.1}", 2f64.powf(position as f64 / steps_per_stop / 2.0)),
+    };
+    (position, format!("f/{}", label))
+}
+
+fn format_mm(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:.1}", value)
+    }
+}
+
+/// The range of `boundaries` containing `focal_length`.
+pub fn focal_range(focal_length: f64, boundaries: &[f64]) -> (i64, String) {
+    let index = boundaries.iter().take_while(|edge| focal_length >= **edge).count();
+    let label = match (index.checked_sub(1).map(|i| boundaries[i]), boundaries.get(index)) {
+        (None, Some(upper)) => format!("<{} mm", format_mm(*upper)),
+        (Some(lower), Some(upper)) => format!("{}-{} mm", format_mm(lower), format_mm(*upper)),
+        (Some(lower), None) => format!("{}+ mm", format_mm(lower)),
+        (None, None) => "All".to_string(),
+    };
+    (index as i64, label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iso_stops() {
+        assert_eq!(iso_stop(100.0), (0, "100".to_string()));
+        assert_eq!(iso_stop(125.0), (0, "100".to_string()));
+        assert_eq!(iso_stop(160.0), (1, "200".to_string()));
+        assert_eq!(iso_stop(6400.0), (6, "6400".to_string()));
+        assert_eq!(iso_stop(64.0).1, "50");
+    }
+
+    #[test]
+    fn test_shutter_stops() {
+        assert_eq!(shutter_stop(0.004).1, "1/250");
+        assert_eq!(shutter_stop(1.0 / 200.0).1, "1/250");
+        assert_eq!(shutter_stop(1.0 / 60.0).1, "1/60");
+        assert_eq!(shutter_stop(25.0).1, "30s");
+        assert_eq!(shutter_stop(120.0).1, "128s");
+        assert_eq!(shutter_stop(1.0 / 16000.0).1, "1/16384");
+        assert!(shutter_stop(1.0 / 1000.0).0 < shutter_stop(1.0 / 30.0).0);
+    }
+
+    #[test]
+    fn test_aperture_stops() {
+        assert_eq!(aperture_stop(2.8, ApertureStops::Full).1, "f/2.8");
+        assert_eq!(aperture_stop(3.2, ApertureStops::Full).1, "f/2.8");
+        assert_eq!(aperture_stop(3.2, ApertureStops::Third).1, "f/3.2");
+        assert_eq!(aperture_stop(7.1, ApertureStops::Third).1, "f/7.1");
+        assert_eq!(aperture_stop(11.0, ApertureStops::Full).1, "f/11");
+        assert_eq!(aperture_stop(0.95, ApertureStops::Full).1, "f/1.0");
+        assert_eq!(aperture_stop(90.0, ApertureStops::Full).1, "f/90.5");
+    }
+
+    #[test]
+    fn test_focal_ranges() {
+        let boundaries = [14.0, 24.0, 35.0, 70.0];
+        assert_eq!(focal_range(12.0, &boundaries), (0, "<14 mm".to_string()));
+        assert_eq!(focal_range(24.0, &boundaries), (2, "24-35 mm".to_string()));
+        assert_eq!(focal_range(50.0, &boundaries), (3, "35-70 mm".to_string()));
+        assert_eq!(focal_range(200.0, &boundaries), (4, "70+ mm".to_string()));
+    }
+
+    #[test]
+    fn test_bucketize_orders_and_merges() {
+        let values = [(Some(0.004), 2), (None, 3), (Some(1.0 / 30.0), 1), (Some(1.0 / 250.0), 4)];
+        let buckets = bucketize(&values, shutter_stop);
+        assert_eq!(
+            buckets,
+            vec![
+                Bucket { label: "1/250".to_string(), count: 6 },
+                Bucket { label: "1/30".to_string(), count: 1 },
+                Bucket { label: NOT_AVAILABLE.to_string(), count: 3 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bucket_config_validation() {
+        assert!(BucketConfig::default().validate().is_ok());
+        let unordered = BucketConfig { focal_ranges: vec![35.0, 24.0], ..BucketConfig::default() };
+        assert!(unordered.validate().is_err());
+    }
+}
@@ -0,0 +1,298 @@
+//! Canonical camera names.
+//!
+//! Vendors write `Make` and `Model` inconsistently: "NIKON CORPORATION" and
+//! "NIKON Z 6_2", "Canon" and "Canon EOS R5" or just "EOS R5", trailing
+//! spaces. Every make/model combination in the catalog is mapped to one
+//! canonical name, "Nikon Z 6II" or "Canon EOS R5", using the built-in tables
+//! below and the `camera_aliases` of the configuration file. The mapping is
+//! stored in the `camera_names` table, rebuilt after every scan; the raw
+//! `Make` and `Model` columns are left untouched.
+
+use crate::errors::Result;
+use rusqlite::Connection;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Canonical name of a `metadata` row: the stored mapping of its make and
+/// model, or the raw model when the mapping has not been built yet.
+pub const CAMERA_SQL: &str = "COALESCE((SELECT camera FROM camera_names
+    WHERE camera_names.make = COALESCE(metadata.Make, '') AND camera_names.model = metadata.Model), Model)";
+
+/// Brand by the first word of `Make`, in lowercase.
+const BRANDS: &[(&str, &str)] = &[
+    ("apple", "Apple"),
+    ("canon", "Canon"),
+    ("casio", "Casio"),
+    ("dji", "DJI"),
+    ("eastman", "Kodak"),
+    ("fuji", "Fujifilm"),
+    ("fujifilm", "Fujifilm"),
+    ("google", "Google"),
+    ("gopro", "GoPro"),
+    ("hasselblad", "Hasselblad"),
+    ("huawei", "Huawei"),
+    ("kodak", "Kodak"),
+    ("konica", "Konica Minolta"),
+    ("leica", "Leica"),
+    ("minolta", "Minolta"),
+    ("nikon", "Nikon"),
+    ("olympus", "Olympus"),
+    ("om", "OM System"),
+    ("oneplus", "OnePlus"),
+    ("panasonic", "Panasonic"),
+    ("pentax", "Pentax"),
+    ("ricoh", "Ricoh"),
+    ("samsung", "Samsung"),
+    ("sigma", "Sigma"),
+    ("sony", "Sony"),
+    ("xiaomi", "Xiaomi"),
+];
+
+/// Brand of model names that identify it when `Make` is missing.
+const MODEL_PREFIXES: &[(&str, &str)] = &[
+    ("EOS", "Canon"),
+    ("PowerShot", "Canon"),
+    ("ILCE-", "Sony"),
+    ("DSC-", "Sony"),
+    ("NEX-", "Sony"),
+    ("DMC-", "Panasonic"),
+    ("DC-", "Panasonic"),
+    ("X-T", "Fujifilm"),
+    ("X-Pro", "Fujifilm"),
+    ("X100", "Fujifilm"),
+    ("GFX", "Fujifilm"),
+    ("iPhone", "Apple"),
+    ("Pixel", "Google"),
+];
+
+/// Marketing names of bodies whose `Model` is a product code, by brand and
+/// model without the brand.
+const MODELS: &[(&str, &str, &str)] = &[
+    ("Nikon", "Z 6_2", "Z 6II"),
+    ("Nikon", "Z 7_2", "Z 7II"),
+    ("Olympus", "E-M1MarkII", "E-M1 Mark II"),
+    ("Olympus", "E-M1MarkIII", "E-M1 Mark III"),
+    ("Olympus", "E-M5MarkII", "E-M5 Mark II"),
+    ("Olympus", "E-M5MarkIII", "E-M5 Mark III"),
+    ("Olympus", "E-M10MarkII", "E-M10 Mark II"),
+    ("Olympus", "E-M10MarkIII", "E-M10 Mark III"),
+    ("Olympus", "E-M10MarkIV", "E-M10 Mark IV"),
+    ("Panasonic", "DC-G9", "Lumix G9"),
+    ("Panasonic", "DC-G9M2", "Lumix G9 II"),
+    ("Panasonic", "DC-GH5", "Lumix GH5"),
+    ("Panasonic", "DC-GH6", "Lumix GH6"),
+    ("Panasonic", "DC-S1", "Lumix S1"),
+    ("Panasonic", "DC-S1R", "Lumix S1R"),
+    ("Panasonic", "DC-S5", "Lumix S5"),
+    ("Panasonic", "DC-S5M2", "Lumix S5 II"),
+    ("Panasonic", "DMC-GH4", "Lumix GH4"),
+    ("Panasonic", "DMC-GX80", "Lumix GX80"),
+    ("Panasonic", "DMC-GX85", "Lumix GX85"),
+    ("Sony", "ILCE-1", "A1"),
+    ("Sony", "ILCE-6000", "A6000"),
+    ("Sony", "ILCE-6100", "A6100"),
+    ("Sony", "ILCE-6300", "A6300"),
+    ("Sony", "ILCE-6400", "A6400"),
+    ("Sony", "ILCE-6500", "A6500"),
+    ("Sony", "ILCE-6600", "A6600"),
+    ("Sony", "ILCE-6700", "A6700"),
+    ("Sony", "ILCE-7", "A7"),
+    ("Sony", "ILCE-7C", "A7C"),
+    ("Sony", "ILCE-7CM2", "A7C II"),
+    ("Sony", "ILCE-7M2", "A7 II"),
+    ("Sony", "ILCE-7M3", "A7 III"),
+    ("Sony", "ILCE-7M4", "A7 IV"),
+    ("Sony", "ILCE-7R", "A7R"),
+    ("Sony", "ILCE-7RM2", "A7R II"),
+    ("Sony", "ILCE-7RM3", "A7R III"),
+    ("Sony", "ILCE-7RM4", "A7R IV"),
+    ("Sony", "ILCE-7RM5", "A7R V"),
+    ("Sony", "ILCE-7SM2", "A7S II"),
+    ("Sony", "ILCE-7SM3", "A7S III"),
+    ("Sony", "ILCE-9", "A9"),
+    ("Sony", "ILCE-9M2", "A9 II"),
+    ("Sony", "ILCE-9M3", "A9 III"),
+    ("Sony", "DSC-RX100M3", "RX100 III"),
+    ("Sony", "DSC-RX100M5A", "RX100 VA"),
+    ("Sony", "DSC-RX100M6", "RX100 VI"),
+    ("Sony", "DSC-RX100M7", "RX100 VII"),
+];
+
+/// Trims and collapses runs of whitespace, which vendors pad fields with.
+fn clean(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn brand_of_word(word: &str) -> Option<&'static str> {
+    let word = word.to_lowercase();
+    BRANDS.iter().find(|(key, _)| *key == word).map(|(_, brand)| *brand)
+}
+
+/// Removes a leading `prefix` and the space after it, ignoring case.
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+    let rest = text[prefix.len()..].strip_prefix(' ')?;
+    head.eq_ignore_ascii_case(prefix).then_some(rest)
+}
+
+/// Canonical name of a camera from the built-in tables: the brand followed by
+/// the model without a repeated brand, e.g. "Nikon Z 6II". The brand named
+/// at the start of `model` takes precedence over `make`, so a Pentax body
+/// made by Ricoh stays a Pentax.
+pub fn builtin_camera_name(make: Option<&str>, model: &str) -> String {
+    let make = make.map(clean).filter(|make| !make.is_empty());
+    let model = clean(model);
+    let first_word = |text: &str| text.split(' ').next().unwrap_or_default().to_string();
+
+    let model_word = first_word(&model);
+    let (brand, mut name) = match brand_of_word(&model_word) {
+        Some(brand) => {
+            let rest = strip_prefix_ignore_case(&model, brand)
+                .or_else(|| strip_prefix_ignore_case(&model, &model_word))
+                // A model named after the brand alone, e.g. "GoPro".
+                .or_else(|| (model == model_word).then_some(""))
+                .unwrap_or(&model);
+            (Some(brand.to_string()), rest.to_string())
+        }
+        None => {
+            let brand = match &make {
+                Some(make) => Some(brand_of_word(&first_word(make)).map_or_else(|| make.clone(), str::to_string)),
+                None => MODEL_PREFIXES
+                    .iter()
+                    .find(|(prefix, _)| model.starts_with(prefix))
+                    .map(|(_, brand)| brand.to_string()),
+            };
+            let rest = make
+                .as_deref()
+                .and_then(|make| strip_prefix_ignore_case(&model, make))
+                .unwrap_or(&model);
+            (brand, rest.to_string())
+        }
+    };
+
+    if let Some((_, _, marketing)) = MODELS
+        .iter()
+        .find(|(known_brand, code, _)| brand.as_deref() == Some(*known_brand) && code.eq_ignore_ascii_case(&name))
+    {
+        name = marketing.to_string();
+    }
+    match brand {
+        Some(brand) if !name.is_empty() => format!("{} {}", brand, name),
+        Some(brand) => brand,
+        None => name,
+    }
+}
+
+/// Built-in naming plus the user's `camera_aliases`.
+#[derive(Debug, Clone, Default)]
+pub struct CameraNames {
+    aliases: BTreeMap<String, String>,
+}
+
+impl CameraNames {
+    /// `aliases` map a raw `Model` value or a built-in canonical name to the
+    /// name to report instead.
+    pub fn new(aliases: &BTreeMap<String, String>) -> Self {
+        let aliases = aliases.iter().map(|(from, to)| (clean(from), clean(to))).collect();
+        Self { aliases }
+    }
+
+    pub fn canonical(&self, make: Option<&str>, model: &str) -> String {
+        if let Some(alias) = self.aliases.get(&clean(model)) {
+            return alias.clone();
+        }
+        let builtin = builtin_camera_name(make, model);
+        self.aliases.get(&builtin).cloned().unwrap_or(builtin)
+    }
+}
+
+/// Rebuilds `camera_names` for every make and model in the catalog and
+/// returns the number of distinct canonical names.
+pub fn normalize_cameras(conn: &Connection, names: &CameraNames) -> Result<usize> {
+    let mut stmt = conn.prepare("SELECT DISTINCT COALESCE(Make, ''), Model FROM metadata WHERE Model IS NOT NULL")?;
+    let combinations = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let mut cameras = BTreeSet::new();
+    let transaction = conn.unchecked_transaction()?;
+    transaction.execute("DELETE FROM camera_names", [])?;
+    for (make, model) in &combinations {
+        let camera = names.canonical(Some(make.as_str()), model);
+        transaction.execute(
+            "INSERT INTO camera_names (make, model, camera) VALUES (?, ?, ?)",
+            rusqlite::params![make, model, camera],
+        )?;
+        cameras.insert(camera);
+    }
+    transaction.commit()?;
+    Ok(cameras.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::insert_metadata;
+    use crate::migrations::run_migrations;
+    use serde_json::json;
+
+    #[test]
+    fn test_builtin_camera_names() {
+        assert_eq!(builtin_camera_name(Some("NIKON CORPORATION"), "NIKON Z 6_2"), "Nikon Z 6II");
+        assert_eq!(builtin_camera_name(Some("Canon"), "Canon EOS R5"), "Canon EOS R5");
+        assert_eq!(builtin_camera_name(Some("Canon "), "EOS R5  "), "Canon EOS R5");
+        assert_eq!(builtin_camera_name(None, "EOS R5"), "Canon EOS R5");
+        assert_eq!(builtin_camera_name(Some("SONY"), "ILCE-7M3"), "Sony A7 III");
+        assert_eq!(builtin_camera_name(Some("RICOH IMAGING COMPANY, LTD."), "PENTAX K-1"), "Pentax K-1");
+        assert_eq!(builtin_camera_name(Some("Apple"), "iPhone 13 Pro"), "Apple iPhone 13 Pro");
+        assert_eq!(builtin_camera_name(Some("Acme Optical"), "Acme Optical Model 1"), "Acme Optical Model 1");
+        assert_eq!(builtin_camera_name(None, "Mystery Cam"), "Mystery Cam");
+    }
+
+    #[test]
+    fn test_aliases_override_builtin_names() {
+        let aliases = BTreeMap::from([
+            ("X100V".to_string(), "Fujifilm X100V (silver)".to_string()),
+            ("Nikon Z 6II".to_string(), "Z6ii".to_string()),
+        ]);
+        let names = CameraNames::new(&aliases);
+        assert_eq!(names.canonical(Some("FUJIFILM"), "X100V "), "Fujifilm X100V (silver)");
+        assert_eq!(names.canonical(Some("NIKON CORPORATION"), "NIKON Z 6_2"), "Z6ii");
+        assert_eq!(names.canonical(Some("NIKON CORPORATION"), "NIKON Z 8"), "Nikon Z 8");
+    }
+
+    #[test]
+    fn test_normalize_cameras() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let files = [
+            ("a.nef", json!({ "Make": "NIKON CORPORATION", "Model": "NIKON Z 6_2" })),
+            ("b.nef", json!({ "Make": "Nikon", "Model": "Z 6_2" })),
+            ("c.cr3", json!({ "Make": "Canon", "Model": "Canon EOS R5" })),
+            ("d.jpg", json!({ "Model": "EOS R5" })),
+            ("e.jpg", json!({})),
+        ];
+        for (file, metadata) in &files {
+            insert_metadata(&conn, file, 1234567890.0, 0, metadata).unwrap();
+        }
+
+        assert_eq!(normalize_cameras(&conn, &CameraNames::default()).unwrap(), 2);
+        let camera = |file: &str| -> Option<String> {
+            conn.query_row(
+                &format!("SELECT {} FROM metadata WHERE source_file = ?", CAMERA_SQL),
+                [file],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(camera("a.nef").as_deref(), Some("Nikon Z 6II"));
+        assert_eq!(camera("b.nef").as_deref(), Some("Nikon Z 6II"));
+        assert_eq!(camera("d.jpg").as_deref(), Some("Canon EOS R5"));
+        assert_eq!(camera("e.jpg"), None);
+
+        // The raw values are kept.
+        let raw: String = conn
+            .query_row("SELECT Make || ' ' || Model FROM metadata WHERE source_file = 'a.nef'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(raw, "NIKON CORPORATION NIKON Z 6_2");
+    }
+}
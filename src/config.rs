@@ -1,16 +1,273 @@
-use clap::Parser;
-use std::path::PathBuf;
+use crate::bodies::BodyConfig;
+use crate::buckets::BucketConfig;
+use crate::distribution::OutputConfig;
+use crate::errors::{AppError, Result};
+use crate::extractor::ExtractorBackend;
+use crate::filter::StatisticsFilter;
+use crate::export::{ExportFormat, TrackGrouping};
+use crate::geocode::GeocodingConfig;
+use crate::insights::InsightsConfig;
+use crate::sessions::SessionConfig;
+use crate::statistics::{Dimension, PivotPreset};
+use crate::summary::SummaryGroup;
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Directory to scan for photos
     #[arg(default_value = ".")]
     pub directory: String,
 
     /// Path to the SQLite database
-    #[arg(short, long, default_value = "photo_stats_cache.db")]
+    #[arg(short, long, default_value = "photo_stats_cache.db", global = true)]
     pub database: PathBuf,
+
+    /// Path to a JSON configuration file
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Metadata extraction backend [default: the configuration file's, or exiftool]
+    #[arg(short, long, value_enum)]
+    pub backend: Option<ExtractorBackend>,
+
+    /// Re-extract every file, even those unchanged since the last scan
+    #[arg(short, long)]
+    pub force: bool,
+
+    /// Remove catalog entries for files under the directory that no longer exist
+    #[arg(long)]
+    pub prune: bool,
+
+    #[command(flatten)]
+    pub filter: StatisticsFilter,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Remove catalog entries for files that no longer exist on disk
+    Prune {
+        /// Root directory whose cataloged files are checked
+        #[arg(default_value = ".")]
+        directory: String,
+
+        /// List the entries that would be removed without removing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Rebuild the metadata columns from the stored raw ExifTool output
+    Reindex,
+    /// Print statistics for the cataloged photos without scanning
+    Stats {
+        #[command(flatten)]
+        filter: StatisticsFilter,
+
+        /// Year shown in the per-day calendar (default: latest year with photos)
+        #[arg(long, value_name = "YEAR")]
+        calendar_year: Option<i32>,
+    },
+    /// Cross-tabulate photos by two dimensions (all presets when none is given)
+    Pivot {
+        /// Predefined cross-tabulation
+        #[arg(long, value_enum, conflicts_with_all = ["rows", "columns"])]
+        preset: Option<PivotPreset>,
+
+        /// Dimension of the rows
+        #[arg(long, value_enum, requires = "columns")]
+        rows: Option<Dimension>,
+
+        /// Dimension of the columns
+        #[arg(long, value_enum, requires = "rows")]
+        columns: Option<Dimension>,
+
+        #[command(flatten)]
+        filter: StatisticsFilter,
+    },
+    /// Print min, max, mean, median, percentiles and mode of the numeric fields
+    Summary {
+        /// Summarize each camera or lens separately
+        #[arg(long, value_enum)]
+        group_by: Option<SummaryGroup>,
+
+        #[command(flatten)]
+        filter: StatisticsFilter,
+    },
+    /// Report where each zoom is used, suggest primes and flag idle lenses
+    Insights {
+        /// Share of a zoom's shots the suggested primes should cover
+        #[arg(long, value_name = "PERCENT")]
+        coverage: Option<f64>,
+
+        /// Flag lenses without photos in this many months
+        #[arg(long, value_name = "MONTHS")]
+        idle_months: Option<u32>,
+
+        #[command(flatten)]
+        filter: StatisticsFilter,
+    },
+    /// List the lenses and the photos whose lens could not be identified
+    Lenses {
+        /// Only list the unidentified lenses
+        #[arg(long)]
+        unidentified: bool,
+
+        #[command(flatten)]
+        filter: StatisticsFilter,
+    },
+    /// Report first and last use, photos and shutter wear of each camera body
+    Bodies {
+        /// Actuations the shutter is rated for
+        #[arg(long, value_name = "SHOTS")]
+        rated_shutter_life: Option<u32>,
+
+        #[command(flatten)]
+        filter: StatisticsFilter,
+    },
+    /// Write the geotagged photos to a GeoJSON or KML file
+    Export {
+        /// File to write
+        output: PathBuf,
+
+        /// Output format (default: from the file extension)
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+
+        /// Also trace the route of each day or shooting session
+        #[arg(long, value_enum)]
+        tracks: Option<TrackGrouping>,
+
+        #[command(flatten)]
+        filter: StatisticsFilter,
+    },
+    /// Detect and inspect shooting sessions
+    Sessions {
+        #[command(subcommand)]
+        command: SessionsCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SessionsCommand {
+    /// Group the cataloged photos into sessions, replacing the stored ones
+    Detect {
+        /// Minutes without photos after which a new session starts
+        #[arg(long, value_name = "MINUTES")]
+        gap_minutes: Option<u32>,
+
+        /// Also split when consecutive geotagged photos are farther apart (km)
+        #[arg(long, value_name = "KM")]
+        split_distance_km: Option<f64>,
+    },
+    /// List the stored sessions
+    List,
+    /// Print statistics for one session
+    Show {
+        /// Session ID, as printed by `sessions list`
+        id: i64,
+    },
+    /// Print the number of sessions and average photos per session per month
+    Trend,
+}
+
+/// Settings read from the JSON file passed with `--config`, e.g.
+/// `{ "extra_tags": ["ExposureCompensation", "MeteringMode"] }`.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    /// Metadata extraction backend, unless one is given on the command line
+    pub backend: Option<ExtractorBackend>,
+    /// ExifTool tags stored and reported in addition to the built-in ones
+    pub extra_tags: Vec<String>,
+    /// Stop and range settings for the bucketed distributions
+    pub buckets: BucketConfig,
+    /// Sort order and top-N truncation of the distributions
+    pub output: OutputConfig,
+    /// Time gap and distance that start a new shooting session
+    pub sessions: SessionConfig,
+    /// Crop factor per canonical camera name or raw model, e.g.
+    /// `{ "Fujifilm X-T5": 1.5 }`, overriding the factors inferred from the catalog
+    pub crop_factors: BTreeMap<String, f64>,
+    /// Camera name per raw model or built-in canonical name, e.g.
+    /// `{ "NIKON Z 6_2": "Nikon Z6 II" }`
+    pub camera_aliases: BTreeMap<String, String>,
+    /// Lens name per variant name of the same lens, e.g.
+    /// `{ "AF-S NIKKOR 50mm F1.8 G": "AF-S Nikkor 50mm f/1.8G" }`
+    pub lens_aliases: BTreeMap<String, String>,
+    /// Prime coverage and idle period of the gear insights
+    pub insights: InsightsConfig,
+    /// Gazetteer used to resolve GPS positions to places
+    pub geocoding: GeocodingConfig,
+    /// Rated shutter life of the camera bodies
+    pub bodies: BodyConfig,
+}
+
+fn is_valid_tag_name(tag: &str) -> bool {
+    !tag.is_empty() && tag.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+}
+
+impl FileConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| AppError::Config(format!("Cannot read {}: {}", path.display(), err)))?;
+        let mut config: Self = serde_json::from_str(&contents)
+            .map_err(|err| AppError::Config(format!("Invalid {}: {}", path.display(), err)))?;
+
+        // ExifTool keys its JSON output by tag name alone, so a value requested
+        // as "EXIF:ExposureCompensation" would never be found
+        if let Some(tag) = config.extra_tags.iter().find(|tag| tag.contains(':')) {
+            return Err(AppError::Config(format!(
+                "Group prefixes are not supported in extra_tags: {:?}, use the tag name alone",
+                tag
+            )));
+        }
+        if let Some(tag) = config.extra_tags.iter().find(|tag| !is_valid_tag_name(tag)) {
+            return Err(AppError::Config(format!("Invalid tag name in extra_tags: {:?}", tag)));
+        }
+        config
+            .buckets
+            .validate()
+            .map_err(|err| AppError::Config(format!("Invalid buckets in {}: {}", path.display(), err)))?;
+        config
+            .sessions
+            .validate()
+            .map_err(|err| AppError::Config(format!("Invalid sessions in {}: {}", path.display(), err)))?;
+        config
+            .insights
+            .validate()
+            .map_err(|err| AppError::Config(format!("Invalid insights in {}: {}", path.display(), err)))?;
+        config
+            .geocoding
+            .validate()
+            .map_err(|err| AppError::Config(format!("Invalid geocoding in {}: {}", path.display(), err)))?;
+        config
+            .bodies
+            .validate()
+            .map_err(|err| AppError::Config(format!("Invalid bodies in {}: {}", path.display(), err)))?;
+        if let Some((model, _)) = config.crop_factors.iter().find(|(_, factor)| !factor.is_finite() || **factor <= 0.0) {
+            return Err(AppError::Config(format!("Invalid crop factor for {:?}: must be a positive number", model)));
+        }
+        if let Some((camera, _)) = config.camera_aliases.iter().find(|(_, name)| name.trim().is_empty()) {
+            return Err(AppError::Config(format!("Invalid camera alias for {:?}: must not be empty", camera)));
+        }
+        if let Some((lens, _)) = config.lens_aliases.iter().find(|(_, name)| name.trim().is_empty()) {
+            return Err(AppError::Config(format!("Invalid lens alias for {:?}: must not be empty", lens)));
+        }
+        let mut seen = std::collections::HashSet::new();
+        config.extra_tags.retain(|tag| seen.insert(tag.clone()));
+
+        Ok(config)
+    }
+
+    /// Loads `path` if one was given, otherwise returns the defaults.
+    pub fn load_optional(path: Option<&Path>) -> Result<Self> {
+        path.map_or_else(|| Ok(Self::default()), Self::load)
+    }
 }
 
 /// Configuration for the photo-statistics application
@@ -18,17 +275,60 @@ pub struct Cli {
 pub struct Config {
     pub database_path: PathBuf,
     pub directory: String,
+    pub backend: ExtractorBackend,
+    pub force: bool,
+    pub prune: bool,
+    pub extra_tags: Vec<String>,
+    /// Restricts the statistics printed after the scan
+    pub filter: StatisticsFilter,
+    pub buckets: BucketConfig,
+    pub output: OutputConfig,
+    pub crop_factors: BTreeMap<String, f64>,
+    pub camera_aliases: BTreeMap<String, String>,
+    pub lens_aliases: BTreeMap<String, String>,
+    pub geocoding: GeocodingConfig,
 }
 
-impl From<Cli> for Config {
-    fn from(args: Cli) -> Self {
+impl Config {
+    pub fn new(args: Cli, file: FileConfig) -> Self {
         Self {
             database_path: args.database,
             directory: args.directory,
+            backend: args.backend.or(file.backend).unwrap_or_default(),
+            force: args.force,
+            prune: args.prune,
+            filter: args.filter,
+            ..Self::from_file(file)
+        }
+    }
+
+    /// Settings of `file` for an incremental scan of the current directory
+    /// into the default database.
+    pub fn from_file(file: FileConfig) -> Self {
+        Self {
+            database_path: PathBuf::from("photo_stats_cache.db"),
+            directory: ".".to_string(),
+            backend: file.backend.unwrap_or_default(),
+            force: false,
+            prune: false,
+            extra_tags: file.extra_tags,
+            filter: StatisticsFilter::default(),
+            buckets: file.buckets,
+            output: file.output,
+            crop_factors: file.crop_factors,
+            camera_aliases: file.camera_aliases,
+            lens_aliases: file.lens_aliases,
+            geocoding: file.geocoding,
         }
     }
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Self::from_file(FileConfig::default())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -37,11 +337,157 @@ mod tests {
     #[test]
     fn test_config_mapping() {
         let args = Cli {
+            command: None,
             directory: "photos".to_string(),
             database: PathBuf::from("test.db"),
+            config: None,
+            backend: None,
+            force: true,
+            prune: true,
+            filter: StatisticsFilter::default(),
+        };
+        let file = FileConfig {
+            backend: Some(ExtractorBackend::Native),
+            extra_tags: vec!["MeteringMode".to_string()],
+            ..FileConfig::default()
         };
-        let config: Config = args.into();
+        let config = Config::new(args, file.clone());
         assert_eq!(config.database_path, Path::new("test.db"));
         assert_eq!(config.directory, "photos");
+        assert_eq!(config.backend, ExtractorBackend::Native);
+        assert!(config.force);
+        assert!(config.prune);
+        assert_eq!(config.extra_tags, vec!["MeteringMode".to_string()]);
+
+        // The command line takes precedence over the configuration file
+        let args = Cli::parse_from(["photo-statistics", "--backend", "exiftool", "photos"]);
+        assert_eq!(Config::new(args, file).backend, ExtractorBackend::Exiftool);
+        let args = Cli::parse_from(["photo-statistics", "photos"]);
+        assert_eq!(Config::new(args, FileConfig::default()).backend, ExtractorBackend::Exiftool);
+    }
+
+    #[test]
+    fn test_load_file_config() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.json");
+        std::fs::write(&path, r#"{ "extra_tags": ["ExposureCompensation", "MeteringMode", "MeteringMode"] }"#).unwrap();
+
+        let config = FileConfig::load(&path).unwrap();
+        assert_eq!(config.extra_tags, vec!["ExposureCompensation".to_string(), "MeteringMode".to_string()]);
+        assert_eq!(FileConfig::load_optional(None).unwrap(), FileConfig::default());
+
+        std::fs::write(&path, r#"{ "extra_tags": ["Metering Mode"] }"#).unwrap();
+        assert!(FileConfig::load(&path).is_err());
+        std::fs::write(&path, r#"{ "extra_tags": ["EXIF:ExposureCompensation"] }"#).unwrap();
+        let error = FileConfig::load(&path).unwrap_err().to_string();
+        assert!(error.contains("Group prefixes"), "{}", error);
+        std::fs::write(&path, r#"{ "backend": "native" }"#).unwrap();
+        assert_eq!(FileConfig::load(&path).unwrap().backend, Some(ExtractorBackend::Native));
+        std::fs::write(&path, r#"{ "extra_tag": ["MeteringMode"] }"#).unwrap();
+        assert!(FileConfig::load(&path).is_err());
+        std::fs::write(&path, r#"{ "buckets": { "focal_ranges": [70, 35] } }"#).unwrap();
+        assert!(FileConfig::load(&path).is_err());
+
+        std::fs::write(&path, r#"{ "buckets": { "focal_ranges": [24, 70], "aperture_stops": "third" } }"#).unwrap();
+        let config = FileConfig::load(&path).unwrap();
+        assert_eq!(config.buckets.focal_ranges, vec![24.0, 70.0]);
+        assert_eq!(config.buckets.aperture_stops, crate::buckets::ApertureStops::Third);
+
+        std::fs::write(&path, r#"{ "output": { "sort": { "iso": "count_desc" }, "top": 5 } }"#).unwrap();
+        let config = FileConfig::load(&path).unwrap();
+        assert_eq!(config.output.top, Some(5));
+        assert_eq!(
+            config.output.sort_for("iso", crate::distribution::SortOrder::Natural),
+            crate::distribution::SortOrder::CountDesc
+        );
+
+        std::fs::write(&path, r#"{ "sessions": { "gap_minutes": 45, "split_distance_km": 5 } }"#).unwrap();
+        let config = FileConfig::load(&path).unwrap();
+        assert_eq!(config.sessions.gap_minutes, 45);
+        assert_eq!(config.sessions.split_distance_km, Some(5.0));
+        std::fs::write(&path, r#"{ "sessions": { "gap_minutes": 0 } }"#).unwrap();
+        assert!(FileConfig::load(&path).is_err());
+
+        std::fs::write(&path, r#"{ "crop_factors": { "X-T5": 1.5, "NIKON Z 6_2": 1 } }"#).unwrap();
+        let config = FileConfig::load(&path).unwrap();
+        assert_eq!(config.crop_factors.get("X-T5"), Some(&1.5));
+        std::fs::write(&path, r#"{ "crop_factors": { "X-T5": 0 } }"#).unwrap();
+        assert!(FileConfig::load(&path).is_err());
+
+        std::fs::write(&path, r#"{ "camera_aliases": { "NIKON Z 6_2": "Nikon Z6 II" } }"#).unwrap();
+        let config = FileConfig::load(&path).unwrap();
+        assert_eq!(config.camera_aliases.get("NIKON Z 6_2").map(String::as_str), Some("Nikon Z6 II"));
+        std::fs::write(&path, r#"{ "camera_aliases": { "X-T5": " " } }"#).unwrap();
+        assert!(FileConfig::load(&path).is_err());
+        std::fs::write(&path, r#"{ "lens_aliases": { "XF56mmF1.2 R": "" } }"#).unwrap();
+        assert!(FileConfig::load(&path).is_err());
+
+        std::fs::write(&path, r#"{ "insights": { "coverage_percent": 90, "idle_months": 6 } }"#).unwrap();
+        let config = FileConfig::load(&path).unwrap();
+        assert_eq!(config.insights.coverage_percent, 90.0);
+        assert_eq!(config.insights.idle_months, 6);
+        std::fs::write(&path, r#"{ "insights": { "coverage_percent": 120 } }"#).unwrap();
+        assert!(FileConfig::load(&path).is_err());
+
+        std::fs::write(&path, r#"{ "bodies": { "rated_shutter_life": 150000, "rated_shutter_life_by_camera": { "Canon EOS R5": 500000 } } }"#).unwrap();
+        let config = FileConfig::load(&path).unwrap();
+        assert_eq!(config.bodies.rated_shutter_life, 150_000);
+        std::fs::write(&path, r#"{ "bodies": { "rated_shutter_life": 0 } }"#).unwrap();
+        assert!(FileConfig::load(&path).is_err());
+
+        std::fs::write(&path, r#"{ "geocoding": { "cities": "cities500.txt", "max_distance_km": 20 } }"#).unwrap();
+        let config = FileConfig::load(&path).unwrap();
+        assert_eq!(config.geocoding.cities.as_deref(), Some(Path::new("cities500.txt")));
+        assert_eq!(config.geocoding.max_distance_km, 20.0);
+        std::fs::write(&path, r#"{ "geocoding": { "admin1_codes": "admin1CodesASCII.txt" } }"#).unwrap();
+        assert!(FileConfig::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_parse_prune_subcommand() {
+        let args = Cli::parse_from(["photo-statistics", "prune", "/mnt/nas", "--dry-run", "-d", "cache.db"]);
+        assert_eq!(args.database, Path::new("cache.db"));
+        match args.command {
+            Some(Command::Prune { directory, dry_run }) => {
+                assert_eq!(directory, "/mnt/nas");
+                assert!(dry_run);
+            }
+            other => panic!("Unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_stats_filter() {
+        let args = Cli::parse_from([
+            "photo-statistics", "stats", "--camera", "X-T5", "--camera", "X100V", "--from", "2023-01-01",
+            "--path", "/trips/iceland", "--iso-max", "1600",
+        ]);
+        match args.command {
+            Some(Command::Stats { filter, .. }) => {
+                assert_eq!(filter.cameras, vec!["X-T5".to_string(), "X100V".to_string()]);
+                assert_eq!(filter.date_from, chrono::NaiveDate::from_ymd_opt(2023, 1, 1));
+                assert_eq!(filter.path_prefix.as_deref(), Some("/trips/iceland"));
+                assert_eq!(filter.iso_max, Some(1600));
+            }
+            other => panic!("Unexpected command: {:?}", other),
+        }
+
+        assert!(Cli::try_parse_from(["photo-statistics", "stats", "--from", "2023-13-01"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_pivot_subcommand() {
+        let args = Cli::parse_from(["photo-statistics", "pivot", "--rows", "camera", "--columns", "iso-stop"]);
+        match args.command {
+            Some(Command::Pivot { preset, rows, columns, .. }) => {
+                assert_eq!(preset, None);
+                assert_eq!(rows, Some(Dimension::Camera));
+                assert_eq!(columns, Some(Dimension::IsoStop));
+            }
+            other => panic!("Unexpected command: {:?}", other),
+        }
+
+        assert!(Cli::try_parse_from(["photo-statistics", "pivot", "--rows", "camera"]).is_err());
+        assert!(Cli::try_parse_from(["photo-statistics", "pivot", "--preset", "lens-focal-range", "--rows", "lens", "--columns", "iso"]).is_err());
     }
 }
\ No newline at end of file
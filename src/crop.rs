@@ -0,0 +1,175 @@
+//! Crop factors and 35mm-equivalent focal lengths.
+//!
+//! Many RAW files carry `FocalLength` but not `FocalLengthIn35mmFormat`. The
+//! equivalent focal length of such a photo is derived from its camera's crop
+//! factor: the one configured for the camera in the `crop_factors` section of
+//! the configuration file, or else the median ratio between the two values
+//! over the camera's photos that carry both. Cameras are identified by their
+//! canonical name, so the variants of a model's `Model` value share a factor.
+
+use crate::cameras::CAMERA_SQL;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CropFactorSource {
+    /// From the configuration file
+    Configured,
+    /// From the camera's photos carrying both focal lengths
+    Inferred,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CropFactor {
+    /// Canonical camera name
+    pub model: String,
+    /// 1.0 for full frame, about 1.5 for APS-C
+    pub factor: f64,
+    pub source: CropFactorSource,
+    /// Photos taken with the camera
+    pub photos: i32,
+}
+
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) { (values[middle - 1] + values[middle]) / 2.0 } else { values[middle] })
+}
+
+/// Crop factor of every camera that has one, configured factors taking
+/// precedence over inferred ones. Inferred factors are rounded to two
+/// decimals. `configured` is keyed by canonical camera name; a raw `Model`
+/// value is accepted as well and applies to the camera it names.
+pub fn crop_factors(connection: &Connection, configured: &BTreeMap<String, f64>) -> Result<Vec<CropFactor>, anyhow::Error> {
+    let mut stmt = connection.prepare(&format!(
+        "SELECT Model, {}, FocalLength, FocalLengthIn35mmFormat FROM metadata
+         WHERE Model IS NOT NULL",
+        CAMERA_SQL
+    ))?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, Option<f64>>(2)?,
+            row.get::<_, Option<f64>>(3)?,
+        ))
+    })?;
+
+    let mut cameras: HashMap<String, String> = HashMap::new();
+    let mut photos: HashMap<String, i32> = HashMap::new();
+    let mut ratios: HashMap<String, Vec<f64>> = HashMap::new();
+    for row in rows {
+        let (raw_model, model, focal, equivalent) = row?;
+        cameras.insert(raw_model, model.clone());
+        *photos.entry(model.clone()).or_default() += 1;
+        if let (Some(focal), Some(equivalent)) = (focal, equivalent) {
+            if focal > 0.0 && equivalent > 0.0 {
+                ratios.entry(model).or_default().push(equivalent / focal);
+            }
+        }
+    }
+
+    let mut factors: BTreeMap<String, CropFactor> = BTreeMap::new();
+    for (model, mut values) in ratios {
+        if let Some(ratio) = median(&mut values) {
+            let photos = photos.get(&model).copied().unwrap_or(0);
+            let factor = (ratio * 100.0).round() / 100.0;
+            factors.insert(model.clone(), CropFactor { model, factor, source: CropFactorSource::Inferred, photos });
+        }
+    }
+    // Canonical names go last, so they override a raw model of the same camera
+    let (canonical, raw): (Vec<_>, Vec<_>) = configured.iter().partition(|(name, _)| photos.contains_key(*name));
+    for (name, factor) in raw.into_iter().chain(canonical) {
+        let model = cameras.get(name).unwrap_or(name);
+        let photos = photos.get(model).copied().unwrap_or(0);
+        let entry = CropFactor { model: model.clone(), factor: *factor, source: CropFactorSource::Configured, photos };
+        factors.insert(model.clone(), entry);
+    }
+    Ok(factors.into_values().collect())
+}
+
+/// SQL expression for the equivalent focal length of a `metadata` row: the
+/// stored value, or `FocalLength` times its camera's crop factor. NULL when
+/// neither is known.
+pub fn equivalent_focal_length_sql(factors: &[CropFactor]) -> String {
+    if factors.is_empty() {
+        return "NULLIF(FocalLengthIn35mmFormat, 0)".to_string();
+    }
+    let cases: String = factors
+        .iter()
+        .map(|crop| format!(" WHEN '{}' THEN {:?}", crop.model.replace('\'', "''"), crop.factor))
+        .collect();
+    format!("COALESCE(NULLIF(FocalLengthIn35mmFormat, 0), FocalLength * CASE {}{} END)", CAMERA_SQL, cases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cameras::{normalize_cameras, CameraNames};
+    use crate::database::insert_metadata;
+    use crate::migrations::run_migrations;
+    use serde_json::json;
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let photos = [
+            ("a.raf", json!({ "Model": "X-T5", "FocalLength": "23 mm", "FocalLengthIn35mmFormat": "35 mm" })),
+            ("b.raf", json!({ "Model": "X-T5", "FocalLength": "56 mm", "FocalLengthIn35mmFormat": "84 mm" })),
+            ("c.raf", json!({ "Model": "X-T5", "FocalLength": "16 mm" })),
+            ("d.nef", json!({ "Make": "NIKON CORPORATION", "Model": "NIKON Z 6", "FocalLength": "50 mm" })),
+            ("f.nef", json!({ "Make": "Nikon", "Model": "Z 6", "FocalLength": "35 mm" })),
+            ("e.cr3", json!({ "Model": "Photographer's EOS", "FocalLength": "24 mm" })),
+        ];
+        for (file, metadata) in photos {
+            insert_metadata(&conn, file, 0.0, 0, &metadata).unwrap();
+        }
+        normalize_cameras(&conn, &CameraNames::new(&BTreeMap::new())).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_infer_and_configure_crop_factors() {
+        let conn = setup();
+        let factors = crop_factors(&conn, &BTreeMap::from([("Nikon Z 6".to_string(), 1.0)])).unwrap();
+
+        assert_eq!(factors.len(), 2);
+        assert_eq!(factors[0], CropFactor { model: "Fujifilm X-T5".to_string(), factor: 1.51, source: CropFactorSource::Inferred, photos: 3 });
+        assert_eq!(factors[1], CropFactor { model: "Nikon Z 6".to_string(), factor: 1.0, source: CropFactorSource::Configured, photos: 2 });
+
+        // A raw model names its camera, but a canonical name takes precedence
+        let configured = BTreeMap::from([("X-T5".to_string(), 1.5), ("NIKON Z 6".to_string(), 1.1), ("Nikon Z 6".to_string(), 1.0)]);
+        let factors = crop_factors(&conn, &configured).unwrap();
+        assert_eq!(factors.len(), 2);
+        assert_eq!(factors[0], CropFactor { model: "Fujifilm X-T5".to_string(), factor: 1.5, source: CropFactorSource::Configured, photos: 3 });
+        assert_eq!(factors[1].factor, 1.0);
+    }
+
+    #[test]
+    fn test_equivalent_focal_length_expression() {
+        let conn = setup();
+        let configured = BTreeMap::from([("Nikon Z 6".to_string(), 1.0), ("Photographer's EOS".to_string(), 1.6)]);
+        let expression = equivalent_focal_length_sql(&crop_factors(&conn, &configured).unwrap());
+        let equivalent = |file: &str| -> Option<f64> {
+            conn.query_row(&format!("SELECT {} FROM metadata WHERE source_file = ?", expression), [file], |row| row.get(0))
+                .unwrap()
+        };
+
+        assert_eq!(equivalent("a.raf"), Some(35.0));
+        assert!((equivalent("c.raf").unwrap() - 24.16).abs() < 1e-9);
+        assert_eq!(equivalent("d.nef"), Some(50.0));
+        assert_eq!(equivalent("f.nef"), Some(35.0));
+        assert!((equivalent("e.cr3").unwrap() - 38.4).abs() < 1e-9);
+
+        let stored_only = equivalent_focal_length_sql(&[]);
+        let value: Option<f64> = conn
+            .query_row(&format!("SELECT {} FROM metadata WHERE source_file = 'c.raf'", stored_only), [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(value, None);
+    }
+}
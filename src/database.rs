@@ -1,31 +1,187 @@
-use crate::utils::{normalize_focal_length, normalize_white_balance};
-use crate::errors::Result;
-use rusqlite::Connection;
+use crate::utils::{format_exposure_time, json_altitude, json_coordinate, json_number, normalize_white_balance, parse_exposure_time};
+use crate::errors::{AppError, Result};
+use crate::lenses::{identify_lens, LensSource};
+use rusqlite::{Connection, OptionalExtension};
 use serde_json::Value;
-use chrono::NaiveDateTime;
+use chrono::{Duration, Local, NaiveDateTime};
+use crate::migrations::run_migrations;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
 
-pub fn create_tables_if_needed(conn: &Connection) -> Result<()> {
-    println!("🔍 Creating tables...");
+/// Opens the cache database and applies any pending schema migrations.
+pub fn open_database(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    run_migrations(&conn)?;
+    Ok(conn)
+}
+
+/// Typed values of the normalized `metadata` columns. Missing or unparsable
+/// values are `None` and stored as NULL.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MetadataRecord {
+    /// Local time at the camera, "2023-06-12 15:30:00"
+    pub date_time_original: Option<String>,
+    /// Camera's offset from UTC in minutes, when recorded
+    pub utc_offset_minutes: Option<i64>,
+    /// Fraction of the second of `date_time_original`, e.g. "42"
+    pub sub_sec_time: Option<String>,
+    /// Manufacturer as written by the camera, e.g. "NIKON CORPORATION"
+    pub make: Option<String>,
+    pub model: Option<String>,
+    /// `SerialNumber`, or else `InternalSerialNumber`
+    pub serial_number: Option<String>,
+    /// Maker-note `ShutterCount`, or else `ImageCount`
+    pub shutter_count: Option<i64>,
+    pub lens_model: Option<String>,
+    /// Lens from the first usable lens tag; lenses without one are described
+    /// per camera by [`crate::lenses::normalize_lenses`]
+    pub lens_name: Option<String>,
+    pub lens_source: Option<LensSource>,
+    /// Widest aperture of the lens at the focal length used, as an f-number
+    pub max_aperture: Option<f64>,
+    pub iso: Option<i64>,
+    /// Exposure time in seconds
+    pub exposure_time: Option<f64>,
+    /// Exposure time as printed by ExifTool, e.g. "1/200"
+    pub exposure_time_display: Option<String>,
+    pub f_number: Option<f64>,
+    pub focal_length: Option<f64>,
+    pub flash: Option<String>,
+    pub white_balance: Option<String>,
+    pub image_width: Option<i64>,
+    pub image_height: Option<i64>,
+    pub focal_length_35mm: Option<f64>,
+    /// Decimal degrees, negative south of the equator
+    pub gps_latitude: Option<f64>,
+    /// Decimal degrees, negative west of Greenwich
+    pub gps_longitude: Option<f64>,
+    /// Meters above sea level, negative below
+    pub gps_altitude: Option<f64>,
+}
+
+fn json_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.trim().to_string()).filter(|t| !t.is_empty()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+impl MetadataRecord {
+    /// Builds a record from one ExifTool JSON object. Values may be JSON
+    /// numbers or strings.
+    pub fn from_json(metadata: &Value) -> Self {
+        let exposure = &metadata["ExposureTime"];
+        let exposure_time = match exposure {
+            Value::String(text) => parse_exposure_time(text),
+            other => json_number(other).filter(|seconds| *seconds > 0.0),
+        };
+        let exposure_time_display = match exposure {
+            Value::String(text) => Some(text.trim().to_string()).filter(|t| !t.is_empty()),
+            _ => exposure_time.map(format_exposure_time),
+        };
+
+        let camera_offset = metadata["OffsetTimeOriginal"].as_str().and_then(parse_utc_offset);
+        let (date_time_original, utc_offset_minutes) =
+            match metadata["DateTimeOriginal"].as_str().and_then(parse_date_time) {
+                Some((time, suffix_offset)) => {
+                    // A date stamped with another offset (often UTC) is moved to the camera's local time.
+                    let local = match (suffix_offset, camera_offset) {
+                        (Some(stamped), Some(camera)) => time - Duration::minutes(stamped) + Duration::minutes(camera),
+                        _ => time,
+                    };
+                    (Some(local.format("%Y-%m-%d %H:%M:%S").to_string()), camera_offset.or(suffix_offset))
+                }
+                None => (None, camera_offset),
+            };
+
+        let focal_length = json_number(&metadata["FocalLength"]);
+        let (lens_name, lens_source) = identify_lens(metadata).unzip();
+
+        Self {
+            date_time_original,
+            utc_offset_minutes,
+            sub_sec_time: json_text(&metadata["SubSecTimeOriginal"]),
+            make: json_text(&metadata["Make"]),
+            model: json_text(&metadata["Model"]),
+            serial_number: json_text(&metadata["SerialNumber"]).or_else(|| json_text(&metadata["InternalSerialNumber"])),
+            shutter_count: ["ShutterCount", "ImageCount"]
+                .iter()
+                .find_map(|tag| json_number(&metadata[*tag]).filter(|count| *count > 0.0))
+                .map(|count| count.round() as i64),
+            lens_model: json_text(&metadata["LensModel"]),
+            lens_name,
+            lens_source,
+            max_aperture: json_number(&metadata["MaxApertureValue"]).filter(|aperture| *aperture > 0.0),
+            iso: json_number(&metadata["ISO"]).map(|iso| iso.round() as i64),
+            exposure_time,
+            exposure_time_display,
+            f_number: json_number(&metadata["FNumber"]),
+            focal_length,
+            flash: json_text(&metadata["Flash"]),
+            white_balance: metadata["WhiteBalance"]
+                .as_str()
+                .map(|wb| normalize_white_balance(Some(wb))),
+            image_width: json_number(&metadata["ImageWidth"]).map(|w| w as i64),
+            image_height: json_number(&metadata["ImageHeight"]).map(|h| h as i64),
+            focal_length_35mm: json_number(&metadata["FocalLengthIn35mmFormat"]),
+            gps_latitude: json_coordinate(&metadata["GPSLatitude"], metadata.get("GPSLatitudeRef"))
+                .filter(|latitude| latitude.abs() <= 90.0),
+            gps_longitude: json_coordinate(&metadata["GPSLongitude"], metadata.get("GPSLongitudeRef")),
+            gps_altitude: json_altitude(&metadata["GPSAltitude"], metadata.get("GPSAltitudeRef")),
+        }
+    }
+}
+
+pub(crate) fn write_record(
+    conn: &Connection,
+    file_path: &str,
+    mod_time: f64,
+    file_size: Option<i64>,
+    record: &MetadataRecord,
+) -> Result<()> {
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS metadata (
-            source_file TEXT PRIMARY KEY,
-            mod_time REAL,
-            DateTimeOriginal TEXT,
-            Model TEXT,
-            LensModel TEXT,
-            ISO TEXT,
-            ExposureTime TEXT,
-            FNumber TEXT,
-            FocalLength TEXT,
-            Flash TEXT,
-            WhiteBalance TEXT,
-            ImageWidth TEXT,
-            ImageHeight TEXT,
-            FocalLengthIn35mmFormat TEXT
-        )",
-        [],
+        "INSERT OR REPLACE INTO metadata (
+            source_file, mod_time, DateTimeOriginal, Model, LensModel, ISO, ExposureTime,
+            ExposureTimeDisplay, FNumber, FocalLength, Flash, WhiteBalance, ImageWidth,
+            ImageHeight, FocalLengthIn35mmFormat, file_size, UtcOffsetMinutes, SubSecTimeOriginal,
+            GPSLatitude, GPSLongitude, GPSAltitude, Make, LensName, LensSource, MaxApertureValue, SerialNumber,
+            ShutterCount
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        rusqlite::params![
+            file_path,
+            mod_time,
+            record.date_time_original,
+            record.model,
+            record.lens_model,
+            record.iso,
+            record.exposure_time,
+            record.exposure_time_display,
+            record.f_number,
+            record.focal_length,
+            record.flash,
+            record.white_balance,
+            record.image_width,
+            record.image_height,
+            record.focal_length_35mm,
+            file_size,
+            record.utc_offset_minutes,
+            record.sub_sec_time,
+            record.gps_latitude,
+            record.gps_longitude,
+            record.gps_altitude,
+            record.make,
+            record.lens_name,
+            record.lens_source.map(LensSource::tag),
+            record.max_aperture,
+            record.serial_number,
+            record.shutter_count,
+        ],
     )?;
-    println!("✅ Table created!");
     Ok(())
 }
 
@@ -33,39 +189,165 @@ pub fn insert_metadata(
     conn: &Connection,
     file_path: &str,
     mod_time: f64,
+    file_size: i64,
     metadata: &Value,
 ) -> Result<()> {
+    let record = MetadataRecord::from_json(metadata);
+    write_record(conn, file_path, mod_time, Some(file_size), &record)?;
+    insert_raw_metadata(conn, file_path, metadata)
+}
+
+fn compress_json(metadata: &Value) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    serde_json::to_writer(&mut encoder, metadata)?;
+    Ok(encoder.finish()?)
+}
+
+fn decompress_json(data: &[u8]) -> Result<Value> {
+    let mut json = Vec::new();
+    ZlibDecoder::new(data).read_to_end(&mut json)?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+fn tag_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(_) | Value::Number(_) => json_text(value),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Replaces the stored values of the configured extra `tags` for a file.
+/// Tags the file does not have are not stored.
+pub fn insert_metadata_tags(conn: &Connection, file_path: &str, metadata: &Value, tags: &[String]) -> Result<()> {
+    conn.execute("DELETE FROM metadata_tags WHERE source_file = ?", [file_path])?;
+    for tag in tags {
+        if let Some(value) = tag_value(&metadata[tag.as_str()]) {
+            conn.execute(
+                "INSERT INTO metadata_tags (source_file, tag, value) VALUES (?, ?, ?)",
+                rusqlite::params![file_path, tag, value],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Stores the complete extractor output for a file, compressed.
+pub fn insert_raw_metadata(conn: &Connection, file_path: &str, metadata: &Value) -> Result<()> {
     conn.execute(
-        "INSERT OR REPLACE INTO metadata VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        "INSERT OR REPLACE INTO raw_metadata (source_file, json) VALUES (?, ?)",
+        rusqlite::params![file_path, compress_json(metadata)?],
+    )?;
+    Ok(())
+}
+
+/// Loads the stored extractor output for a file, if it was scanned since raw
+/// metadata has been kept.
+pub fn load_raw_metadata(conn: &Connection, file_path: &str) -> Result<Option<Value>> {
+    let data: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT json FROM raw_metadata WHERE source_file = ?",
+            [file_path],
+            |row| row.get(0),
+        )
+        .optional()?;
+    data.map(|data| decompress_json(&data)).transpose()
+}
+
+/// Loads the GPS position of every geotagged file, as `(latitude, longitude)`
+/// in decimal degrees.
+pub fn load_positions(conn: &Connection) -> Result<HashMap<String, (f64, f64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT source_file, GPSLatitude, GPSLongitude FROM metadata
+         WHERE GPSLatitude IS NOT NULL AND GPSLongitude IS NOT NULL",
+    )?;
+    let positions = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, (row.get(1)?, row.get(2)?))))?
+        .collect::<std::result::Result<_, _>>()?;
+    Ok(positions)
+}
+
+/// Loads the modification time and size recorded for every cataloged file.
+/// The size is `None` for rows written before sizes were tracked.
+pub fn load_file_stamps(conn: &Connection) -> Result<HashMap<String, (f64, Option<i64>)>> {
+    let mut stmt = conn.prepare("SELECT source_file, mod_time, file_size FROM metadata")?;
+    let stamps = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, (row.get::<_, Option<f64>>(1)?.unwrap_or(0.0), row.get(2)?)))
+        })?
+        .collect::<std::result::Result<_, _>>()?;
+    Ok(stamps)
+}
+
+pub fn update_file_size(conn: &Connection, file_path: &str, file_size: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE metadata SET file_size = ? WHERE source_file = ?",
+        rusqlite::params![file_size, file_path],
+    )?;
+    Ok(())
+}
+
+/// Records why a file failed, replacing any error left by an earlier scan.
+pub fn insert_scan_error(conn: &Connection, file_path: &str, error: &AppError) -> Result<()> {
+    clear_scan_errors(conn, file_path)?;
+    conn.execute(
+        "INSERT INTO scan_errors (source_file, error_kind, message, occurred_at) VALUES (?, ?, ?, ?)",
         rusqlite::params![
             file_path,
-            mod_time,
-            metadata["DateTimeOriginal"]
-                .as_str()
-                .map_or("N/A".to_string(), format_date),
-            metadata["Model"].as_str().unwrap_or("N/A"),
-            metadata["LensModel"].as_str().unwrap_or("N/A"),
-            metadata["ISO"].as_str().unwrap_or("N/A"),
-            metadata["ExposureTime"].as_str().unwrap_or("N/A"),
-            metadata["FNumber"].as_str().unwrap_or("N/A"),
-            normalize_focal_length(metadata["FocalLength"].as_str()),
-            metadata["Flash"].as_str().unwrap_or("N/A"),
-            normalize_white_balance(metadata["WhiteBalance"].as_str()),
-            metadata["ImageWidth"].as_str().unwrap_or("N/A"),
-            metadata["ImageHeight"].as_str().unwrap_or("N/A"),
-            metadata["FocalLengthIn35mmFormat"]
-                .as_str()
-                .unwrap_or("N/A"),
+            error.kind(),
+            error.to_string(),
+            Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
         ],
     )?;
     Ok(())
 }
 
-pub fn format_date(date: &str) -> String {
-    if let Ok(parsed) = NaiveDateTime::parse_from_str(date, "%Y:%m:%d %H:%M:%S") {
-        return parsed.format("%Y-%m-%d %H:%M:%S").to_string();
+/// Removes the errors recorded for a file, once it has been processed.
+pub fn clear_scan_errors(conn: &Connection, file_path: &str) -> Result<()> {
+    conn.execute("DELETE FROM scan_errors WHERE source_file = ?", [file_path])?;
+    Ok(())
+}
+
+/// Parses a UTC offset such as "+02:00", "-0530" or "Z" into minutes.
+pub fn parse_utc_offset(offset: &str) -> Option<i64> {
+    let offset = offset.trim();
+    if offset == "Z" {
+        return Some(0);
     }
-    "N/A".to_string()
+    let sign = match offset.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits: String = offset[1..].chars().filter(|c| *c != ':').collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) || !matches!(digits.len(), 2 | 4) {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits.get(2..).filter(|m| !m.is_empty()).map_or(Ok(0), str::parse).ok()?;
+    (hours <= 14 && minutes < 60).then_some(sign * (hours * 60 + minutes))
+}
+
+/// Splits an EXIF date into its wall-clock time and the UTC offset stamped
+/// after it, if any: "2023:06:12 15:30:00", "2023:06:12 15:30:00.25+02:00".
+pub fn parse_date_time(date: &str) -> Option<(NaiveDateTime, Option<i64>)> {
+    let date = date.trim();
+    let (time, suffix) = date.split_at(date.char_indices().nth(19).map_or(date.len(), |(index, _)| index));
+    let time = ["%Y:%m:%d %H:%M:%S", "%Y-%m-%d %H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(time, format).ok())?;
+
+    let suffix = suffix.strip_prefix('.').map_or(suffix, |fraction| fraction.trim_start_matches(|c: char| c.is_ascii_digit()));
+    if suffix.is_empty() {
+        return Some((time, None));
+    }
+    parse_utc_offset(suffix).map(|offset| (time, Some(offset)))
+}
+
+/// Converts an EXIF date ("2023:06:12 15:30:00") to the stored
+/// "2023-06-12 15:30:00" form. Already converted dates are accepted as well.
+pub fn format_date(date: &str) -> Option<String> {
+    parse_date_time(date).map(|(parsed, _)| parsed.format("%Y-%m-%d %H:%M:%S").to_string())
 }
 
 #[cfg(test)]
@@ -76,7 +358,7 @@ mod tests {
 
     fn setup_test_db() -> Connection {
         let conn = Connection::open_in_memory().unwrap();
-        create_tables_if_needed(&conn).unwrap();
+        run_migrations(&conn).unwrap();
         conn
     }
 
@@ -111,7 +393,7 @@ mod tests {
             "FocalLengthIn35mmFormat": "50"
         });
 
-        insert_metadata(&conn, "test.jpg", 1234567890.0, &metadata).unwrap();
+        insert_metadata(&conn, "test.jpg", 1234567890.0, 0, &metadata).unwrap();
 
         let count: i64 = conn
             .query_row(
@@ -128,16 +410,170 @@ mod tests {
         let conn = setup_test_db();
         let metadata = json!({});
 
-        insert_metadata(&conn, "test.jpg", 1234567890.0, &metadata).unwrap();
+        insert_metadata(&conn, "test.jpg", 1234567890.0, 0, &metadata).unwrap();
 
-        let result: String = conn
+        let result: Option<f64> = conn
             .query_row(
                 "SELECT FocalLength FROM metadata WHERE source_file = 'test.jpg'",
                 [],
                 |row| row.get(0),
             )
             .unwrap();
-        assert_eq!(result, "N/A");
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn test_metadata_record_from_exiftool_json() {
+        let record = MetadataRecord::from_json(&json!({
+            "DateTimeOriginal": "2023:06:12 15:30:00",
+            "Make": "NIKON CORPORATION ",
+            "Model": "NIKON Z 6_2",
+            "SerialNumber": 6012345,
+            "ShutterCount": "48211",
+            "ISO": 1000,
+            "ExposureTime": "1/200",
+            "FNumber": 2.8,
+            "FocalLength": "57.0 mm",
+            "ImageWidth": 6048,
+            "FocalLengthIn35mmFormat": "57 mm"
+        }));
+
+        assert_eq!(record.date_time_original.as_deref(), Some("2023-06-12 15:30:00"));
+        assert_eq!(record.make.as_deref(), Some("NIKON CORPORATION"));
+        assert_eq!(record.model.as_deref(), Some("NIKON Z 6_2"));
+        assert_eq!(record.serial_number.as_deref(), Some("6012345"));
+        assert_eq!(record.shutter_count, Some(48211));
+        assert_eq!(record.iso, Some(1000));
+        assert_eq!(record.exposure_time, Some(0.005));
+        assert_eq!(record.exposure_time_display.as_deref(), Some("1/200"));
+        assert_eq!(record.f_number, Some(2.8));
+        assert_eq!(record.focal_length, Some(57.0));
+        assert_eq!(record.image_width, Some(6048));
+        assert_eq!(record.image_height, None);
+        assert_eq!(record.focal_length_35mm, Some(57.0));
+        assert_eq!(record.white_balance, None);
+    }
+
+
+    #[test]
+    fn test_load_file_stamps() {
+        let conn = setup_test_db();
+        insert_metadata(&conn, "a.jpg", 1234567890.0, 2048, &json!({})).unwrap();
+        update_file_size(&conn, "a.jpg", 4096).unwrap();
+
+        let stamps = load_file_stamps(&conn).unwrap();
+        assert_eq!(stamps.get("a.jpg"), Some(&(1234567890.0, Some(4096))));
+    }
+
+    #[test]
+    fn test_migrations_add_file_size_to_legacy_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE metadata (source_file TEXT PRIMARY KEY, mod_time REAL)", [])
+            .unwrap();
+        conn.execute("INSERT INTO metadata VALUES ('old.jpg', 1.0)", []).unwrap();
+
+        run_migrations(&conn).unwrap();
+
+        let stamps = load_file_stamps(&conn).unwrap();
+        assert_eq!(stamps.get("old.jpg"), Some(&(1.0, None)));
+    }
+
+    #[test]
+    fn test_raw_metadata_round_trip() {
+        let conn = setup_test_db();
+        let metadata = json!({
+            "SourceFile": "raw.nef",
+            "Model": "NIKON Z 6_2",
+            "ISO": 400,
+            "ShutterCount": 12345
+        });
+
+        insert_metadata(&conn, "raw.nef", 1234567890.0, 0, &metadata).unwrap();
+
+        assert_eq!(load_raw_metadata(&conn, "raw.nef").unwrap(), Some(metadata));
+        assert_eq!(load_raw_metadata(&conn, "other.nef").unwrap(), None);
+    }
+
+    #[test]
+    fn test_insert_metadata_tags() {
+        let conn = setup_test_db();
+        let tags = vec!["ExposureCompensation".to_string(), "MeteringMode".to_string(), "ExposureProgram".to_string()];
+        insert_metadata_tags(&conn, "a.jpg", &json!({ "ExposureProgram": "Manual" }), &tags).unwrap();
+        insert_metadata_tags(
+            &conn,
+            "a.jpg",
+            &json!({ "ExposureCompensation": -0.7, "MeteringMode": "Multi-segment" }),
+            &tags,
+        )
+        .unwrap();
+
+        let stored: Vec<(String, String)> = conn
+            .prepare("SELECT tag, value FROM metadata_tags WHERE source_file = 'a.jpg' ORDER BY tag")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .filter_map(std::result::Result::ok)
+            .collect();
+        assert_eq!(
+            stored,
+            vec![
+                ("ExposureCompensation".to_string(), "-0.7".to_string()),
+                ("MeteringMode".to_string(), "Multi-segment".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_dates_with_offsets() {
+        assert_eq!(parse_utc_offset("+02:00"), Some(120));
+        assert_eq!(parse_utc_offset("-0530"), Some(-330));
+        assert_eq!(parse_utc_offset("Z"), Some(0));
+        assert_eq!(parse_utc_offset("02:00"), None);
+
+        assert_eq!(format_date("2023:06:12 15:30:00").as_deref(), Some("2023-06-12 15:30:00"));
+        assert_eq!(format_date("2023-06-12 15:30:00").as_deref(), Some("2023-06-12 15:30:00"));
+        let (_, offset) = parse_date_time("2023:06:12 15:30:00.25+02:00").unwrap();
+        assert_eq!(offset, Some(120));
+        assert_eq!(format_date("0000:00:00 00:00:00"), None);
+    }
+
+    #[test]
+    fn test_metadata_record_uses_camera_offset() {
+        let local = MetadataRecord::from_json(&json!({
+            "DateTimeOriginal": "2023:06:12 23:30:00",
+            "OffsetTimeOriginal": "-05:00"
+        }));
+        assert_eq!(local.date_time_original.as_deref(), Some("2023-06-12 23:30:00"));
+        assert_eq!(local.utc_offset_minutes, Some(-300));
+
+        // Stamped in UTC, shot at UTC-5: the local date is the day before.
+        let utc = MetadataRecord::from_json(&json!({
+            "DateTimeOriginal": "2023:06:13 04:30:00Z",
+            "OffsetTimeOriginal": "-05:00"
+        }));
+        assert_eq!(utc.date_time_original.as_deref(), Some("2023-06-12 23:30:00"));
+
+        let stamped = MetadataRecord::from_json(&json!({ "DateTimeOriginal": "2023:06:12 15:30:00+02:00" }));
+        assert_eq!(stamped.date_time_original.as_deref(), Some("2023-06-12 15:30:00"));
+        assert_eq!(stamped.utc_offset_minutes, Some(120));
+    }
+
+    #[test]
+    fn test_insert_scan_error() {
+        let conn = setup_test_db();
+        let error = AppError::ExifTool("File format error".to_string());
+
+        insert_scan_error(&conn, "broken.cr3", &error).unwrap();
+
+        let (kind, message): (String, String) = conn
+            .query_row(
+                "SELECT error_kind, message FROM scan_errors WHERE source_file = 'broken.cr3'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(kind, "ExifTool");
+        assert_eq!(message, "ExifTool error: File format error");
     }
 
     #[test]
@@ -145,7 +581,7 @@ mod tests {
         let conn = Connection::open_in_memory().unwrap();
         let broken_metadata = json!({ "InvalidColumn": "Test" });
 
-        let result = insert_metadata(&conn, "broken.jpg", 1234567890.0, &broken_metadata);
+        let result = insert_metadata(&conn, "broken.jpg", 1234567890.0, 0, &broken_metadata);
 
         assert!(result.is_err(), "Expected error due to invalid column.");
     }
@@ -0,0 +1,192 @@
+//! Ordered value distributions as returned to the CLI and the frontend.
+//!
+//! A [`Distribution`] keeps its entries in display order, with the share of
+//! each entry precomputed, so consumers can render it as is. Photos without a
+//! value ("N/A") are always listed last.
+
+use crate::buckets::{Bucket, NOT_AVAILABLE};
+use crate::utils::{parse_exposure_time, parse_number};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Label of the entry collecting the values beyond the top N.
+pub const OTHER: &str = "Other";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// Most frequent first
+    CountDesc,
+    /// By numeric value, e.g. ISO 100 before ISO 1000 and 1/250 before 1/30
+    Natural,
+    /// By label, oldest first for dates such as "2023" or "2023-05"
+    Chronological,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DistributionEntry {
+    pub label: String,
+    pub count: i32,
+    /// Share of the distribution's total, from 0 to 100
+    pub percent: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Distribution {
+    pub total: i32,
+    pub entries: Vec<DistributionEntry>,
+}
+
+/// Sorting and truncation of the distributions, read from the `output`
+/// section of the configuration file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Sort order per distribution, keyed by `Statistics` field or extra tag
+    /// name. Unlisted distributions use their default order.
+    pub sort: HashMap<String, SortOrder>,
+    /// Keep only the N most frequent values and collapse the rest into "Other"
+    pub top: Option<usize>,
+    /// Year of the per-day calendar; defaults to the latest year with photos
+    pub calendar_year: Option<i32>,
+}
+
+impl OutputConfig {
+    pub fn sort_for(&self, dimension: &str, default: SortOrder) -> SortOrder {
+        self.sort.get(dimension).copied().unwrap_or(default)
+    }
+}
+
+/// Numeric value of labels such as "1000", "1/250", "f/2.8" or "50 mm".
+fn natural_key(label: &str) -> Option<f64> {
+    if label.starts_with("f/") {
+        parse_number(label)
+    } else {
+        parse_exposure_time(label)
+    }
+}
+
+fn compare(a: &(String, i32), b: &(String, i32), order: SortOrder) -> Ordering {
+    match order {
+        SortOrder::CountDesc => b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)),
+        SortOrder::Chronological => a.0.cmp(&b.0),
+        SortOrder::Natural => match (natural_key(&a.0), natural_key(&b.0)) {
+            (Some(x), Some(y)) => x.total_cmp(&y).then_with(|| a.0.cmp(&b.0)),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => a.0.cmp(&b.0),
+        },
+    }
+}
+
+impl Distribution {
+    /// Builds a distribution from unordered counts. With `top`, only the
+    /// `top` most frequent values are kept and the others are summed into an
+    /// "Other" entry, listed before "N/A".
+    pub fn from_counts(counts: HashMap<String, i32>, order: SortOrder, top: Option<usize>) -> Self {
+        let mut missing = 0;
+        let mut values: Vec<(String, i32)> = Vec::new();
+        for (label, count) in counts {
+            if label == NOT_AVAILABLE {
+                missing += count;
+            } else {
+                values.push((label, count));
+            }
+        }
+
+        let mut other = 0;
+        if let Some(top) = top.filter(|top| values.len() > *top) {
+            values.sort_by(|a, b| compare(a, b, SortOrder::CountDesc));
+            other = values.drain(top..).map(|(_, count)| count).sum();
+        }
+        values.sort_by(|a, b| compare(a, b, order));
+
+        if other > 0 {
+            values.push((OTHER.to_string(), other));
+        }
+        if missing > 0 {
+            values.push((NOT_AVAILABLE.to_string(), missing));
+        }
+        Self::from_ordered(values)
+    }
+
+    /// Builds a distribution keeping the given order.
+    pub fn from_ordered(counts: Vec<(String, i32)>) -> Self {
+        let total: i32 = counts.iter().map(|(_, count)| count).sum();
+        let entries = counts
+            .into_iter()
+            .map(|(label, count)| DistributionEntry {
+                percent: if total > 0 { 100.0 * f64::from(count) / f64::from(total) } else { 0.0 },
+                label,
+                count,
+            })
+            .collect();
+        Self { total, entries }
+    }
+
+    /// Count of the entry with `label`.
+    pub fn get(&self, label: &str) -> Option<i32> {
+        self.entries.iter().find(|entry| entry.label == label).map(|entry| entry.count)
+    }
+
+    /// Entry labels in display order.
+    pub fn labels(&self) -> Vec<&str> {
+        self.entries.iter().map(|entry| entry.label.as_str()).collect()
+    }
+}
+
+impl From<Vec<Bucket>> for Distribution {
+    fn from(buckets: Vec<Bucket>) -> Self {
+        Self::from_ordered(buckets.into_iter().map(|bucket| (bucket.label, bucket.count)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(values: &[(&str, i32)]) -> HashMap<String, i32> {
+        values.iter().map(|(label, count)| (label.to_string(), *count)).collect()
+    }
+
+    #[test]
+    fn test_natural_order() {
+        let iso = counts(&[("1000", 1), ("N/A", 2), ("100", 5), ("200", 1)]);
+        let distribution = Distribution::from_counts(iso, SortOrder::Natural, None);
+        assert_eq!(distribution.labels(), vec!["100", "200", "1000", "N/A"]);
+        assert_eq!(distribution.total, 9);
+
+        let shutter = counts(&[("1/30", 1), ("1/250", 1), ("2", 1), ("0.5", 1)]);
+        let distribution = Distribution::from_counts(shutter, SortOrder::Natural, None);
+        assert_eq!(distribution.labels(), vec!["1/250", "1/30", "0.5", "2"]);
+
+        let aperture = counts(&[("f/11", 1), ("f/2.8", 1)]);
+        assert_eq!(Distribution::from_counts(aperture, SortOrder::Natural, None).labels(), vec!["f/2.8", "f/11"]);
+    }
+
+    #[test]
+    fn test_count_and_chronological_order() {
+        let years = counts(&[("2023", 4), ("2021", 9), ("2022", 1)]);
+        let by_count = Distribution::from_counts(years.clone(), SortOrder::CountDesc, None);
+        assert_eq!(by_count.labels(), vec!["2021", "2023", "2022"]);
+        let by_date = Distribution::from_counts(years, SortOrder::Chronological, None);
+        assert_eq!(by_date.labels(), vec!["2021", "2022", "2023"]);
+    }
+
+    #[test]
+    fn test_top_n_collapses_tail_into_other() {
+        let lenses = counts(&[("A", 10), ("B", 6), ("C", 2), ("D", 1), ("N/A", 1)]);
+        let distribution = Distribution::from_counts(lenses, SortOrder::Chronological, Some(2));
+        assert_eq!(distribution.labels(), vec!["A", "B", OTHER, NOT_AVAILABLE]);
+        assert_eq!(distribution.get(OTHER), Some(3));
+        assert_eq!(distribution.total, 20);
+        assert_eq!(distribution.entries[0].percent, 50.0);
+    }
+
+    #[test]
+    fn test_empty_distribution() {
+        let distribution = Distribution::from_counts(HashMap::new(), SortOrder::CountDesc, Some(3));
+        assert_eq!(distribution, Distribution::default());
+    }
+}
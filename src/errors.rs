@@ -19,9 +19,28 @@ pub enum AppError {
 
     #[error("Processing error: {0}")]
     Processing(String),
+
+    #[error("Database schema version {found} is newer than the supported version {supported}; please upgrade photo-statistics")]
+    UnsupportedSchema { found: i64, supported: i64 },
     
     #[error("UTF-8 conversion error: {0}")]
     Utf8(#[from] std::string::FromUtf8Error),
 }
 
+impl AppError {
+    /// Stable name of the error variant, as stored in the `scan_errors` table.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::Database(_) => "Database",
+            AppError::Io(_) => "Io",
+            AppError::Serialization(_) => "Serialization",
+            AppError::ExifTool(_) => "ExifTool",
+            AppError::Config(_) => "Config",
+            AppError::Processing(_) => "Processing",
+            AppError::UnsupportedSchema { .. } => "UnsupportedSchema",
+            AppError::Utf8(_) => "Utf8",
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, AppError>;
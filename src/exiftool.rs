@@ -2,57 +2,258 @@
 //!
 //! This module provides a wrapper around the external `exiftool` binary
 //! to extract metadata from image files in JSON format.
+//!
+//! Starting a Perl interpreter for every chunk dominates the scan time on large
+//! libraries, so requests are served by a pool of long-lived
+//! `exiftool -stay_open True -@ -` processes, one per rayon thread. Arguments
+//! are written to the process' stdin and each response is terminated by the
+//! `{ready}` sentinel.
 
 use crate::errors::{AppError, Result};
+use crate::extractor::MetadataExtractor;
 use serde_json::Value;
-use std::process::{Command, Output};
-
-fn execute_exiftool(file_paths: &[String]) -> std::io::Result<Output> {
-    Command::new("exiftool")
-        .args([
-            "-json",
-            "-DateTimeOriginal",
-            "-Model",
-            "-LensModel",
-            "-ISO",
-            "-ExposureTime",
-            "-FNumber",
-            "-FocalLength",
-            "-Flash",
-            "-WhiteBalance",
-            "-ImageWidth",
-            "-ImageHeight",
-            "-FocalLengthIn35mmFormat",
-        ])
-        .args(file_paths)
-        .output()
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+
+/// Every tag is requested and the complete object is kept in `raw_metadata`,
+/// so `reindex` can re-derive the columns, or fill in a newly configured extra
+/// tag, without reading the files again. GPS coordinates are printed as signed
+/// decimal degrees (`+38.716700`) instead of degrees, minutes and seconds.
+const EXIFTOOL_ARGS: [&str; 3] = ["-json", "-c", "%+.6f"];
+
+const READY_SENTINEL: &str = "{ready}";
+
+/// A single `exiftool` process running in `-stay_open` mode.
+struct ExifToolProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    stderr: BufReader<ChildStderr>,
+}
+
+impl ExifToolProcess {
+    fn spawn() -> std::io::Result<Self> {
+        let mut child = Command::new("exiftool")
+            .args(["-stay_open", "True", "-@", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(std::io::Error::other("Failed to capture ExifTool pipes"));
+        };
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+            stderr: BufReader::new(stderr),
+        })
+    }
+
+    fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Sends one request and returns the (stdout, stderr) of that request.
+    fn execute(&mut self, file_paths: &[String], extra_tags: &[String]) -> std::io::Result<(String, String)> {
+        self.stdin.write_all(build_request(file_paths, extra_tags).as_bytes())?;
+        self.stdin.flush()?;
+
+        let stdout = read_until_ready(&mut self.stdout)?;
+        let stderr = read_until_ready(&mut self.stderr)?;
+        Ok((stdout, stderr))
+    }
+}
+
+impl Drop for ExifToolProcess {
+    fn drop(&mut self) {
+        let graceful = self
+            .stdin
+            .write_all(b"-stay_open\nFalse\n")
+            .and_then(|_| self.stdin.flush());
+        if graceful.is_err() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// Builds the argument file contents for a single `-execute` request. Naming a
+/// tag restricts the output to the named tags, so the configured `extra_tags`
+/// (which may be unknown or duplicate tags ExifTool leaves out by default) are
+/// requested next to `-all`.
+///
+/// `-echo4` prints the sentinel on stderr once processing is complete, so both
+/// streams can be read up to a known boundary.
+fn build_request(file_paths: &[String], extra_tags: &[String]) -> String {
+    let mut request = String::new();
+    for arg in EXIFTOOL_ARGS.iter().copied() {
+        request.push_str(arg);
+        request.push('\n');
+    }
+    if !extra_tags.is_empty() {
+        request.push_str("-all\n");
+    }
+    for tag in extra_tags {
+        request.push('-');
+        request.push_str(tag);
+        request.push('\n');
+    }
+    for file_path in file_paths {
+        request.push_str(file_path);
+        request.push('\n');
+    }
+    request.push_str("-echo4\n");
+    request.push_str(READY_SENTINEL);
+    request.push_str("\n-execute\n");
+    request
+}
+
+fn read_until_ready<R: BufRead>(reader: &mut R) -> std::io::Result<String> {
+    let mut output = String::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "ExifTool exited before completing the request",
+            ));
+        }
+        if line.trim_end() == READY_SENTINEL {
+            return Ok(output);
+        }
+        output.push_str(&line);
+    }
+}
+
+/// Pool of persistent ExifTool processes, one slot per rayon thread plus one
+/// shared slot for callers outside the rayon pool.
+struct ExifToolPool {
+    slots: Vec<Mutex<Option<ExifToolProcess>>>,
+}
+
+impl ExifToolPool {
+    fn new(size: usize) -> Self {
+        Self {
+            slots: (0..size.max(1)).map(|_| Mutex::new(None)).collect(),
+        }
+    }
+
+    fn slot(&self) -> &Mutex<Option<ExifToolProcess>> {
+        let shared = self.slots.len() - 1;
+        let index = rayon::current_thread_index().map_or(shared, |i| i % self.slots.len());
+        &self.slots[index]
+    }
+
+    fn execute(&self, file_paths: &[String], extra_tags: &[String]) -> Result<(String, String)> {
+        let mut slot = self
+            .slot()
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if slot.as_mut().is_some_and(|process| !process.is_alive()) {
+            *slot = None;
+        }
+
+        // A request that fails mid-flight leaves the process in an unknown state,
+        // so it is replaced and the request retried once on a fresh process.
+        let mut last_error = None;
+        for _ in 0..2 {
+            let process = match slot.as_mut() {
+                Some(process) => process,
+                None => slot.insert(ExifToolProcess::spawn()?),
+            };
+            match process.execute(file_paths, extra_tags) {
+                Ok(output) => return Ok(output),
+                Err(err) => {
+                    *slot = None;
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(AppError::ExifTool(format!(
+            "ExifTool process failed: {}",
+            last_error.map_or_else(String::new, |e| e.to_string())
+        )))
+    }
+
+    fn shutdown(&self) {
+        for slot in &self.slots {
+            let mut slot = slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            *slot = None;
+        }
+    }
+}
+
+static POOL: OnceLock<ExifToolPool> = OnceLock::new();
+
+fn pool() -> &'static ExifToolPool {
+    POOL.get_or_init(|| ExifToolPool::new(rayon::current_num_threads() + 1))
+}
+
+/// Stops every pooled ExifTool process. New processes are started on demand.
+pub fn shutdown_pool() {
+    if let Some(pool) = POOL.get() {
+        pool.shutdown();
+    }
 }
 
 pub fn run_exiftool(file_paths: &[String]) -> Result<Vec<Value>> {
+    run_exiftool_with_tags(file_paths, &[])
+}
+
+fn run_exiftool_with_tags(file_paths: &[String], extra_tags: &[String]) -> Result<Vec<Value>> {
     if file_paths.is_empty() {
         return Err(AppError::ExifTool("No files provided to ExifTool".to_string()));
     }
 
-    let output = execute_exiftool(file_paths).map_err(AppError::Io)?; // Explicit mapping not strictly needed due to From impl, but good for clarity if needed. Actually From impl handles it.
-    
-    if !output.status.success() {
-         let stderr = String::from_utf8_lossy(&output.stderr);
-         return Err(AppError::ExifTool(format!("ExifTool failed: {}", stderr)));
-    }
+    let (stdout, stderr) = pool().execute(file_paths, extra_tags)?;
 
-    let json_str = String::from_utf8(output.stdout)?;
-
-    if json_str.trim().is_empty() {
+    if stdout.trim().is_empty() {
+        if !stderr.trim().is_empty() {
+            return Err(AppError::ExifTool(format!("ExifTool failed: {}", stderr.trim())));
+        }
         return Err(AppError::ExifTool("ExifTool returned empty output".to_string()));
     }
-    let metadata: Vec<Value> = serde_json::from_str(&json_str)?;
+    let metadata: Vec<Value> = serde_json::from_str(&stdout)?;
 
     Ok(metadata)
 }
 
+/// [`MetadataExtractor`] backed by the pooled `exiftool` processes.
+#[derive(Debug, Default, Clone)]
+pub struct ExifToolExtractor {
+    /// Tags requested explicitly, for those ExifTool leaves out by default
+    extra_tags: Vec<String>,
+}
+
+impl ExifToolExtractor {
+    pub fn new(extra_tags: &[String]) -> Self {
+        Self {
+            extra_tags: extra_tags.to_vec(),
+        }
+    }
+}
+
+impl MetadataExtractor for ExifToolExtractor {
+    fn extract(&self, file_paths: &[String]) -> Result<Vec<Value>> {
+        run_exiftool_with_tags(file_paths, &self.extra_tags)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Cursor;
 
     #[test]
     fn test_run_exiftool_success() {
@@ -88,4 +289,40 @@ mod tests {
         let result = String::from_utf8(invalid_utf8);
         assert!(result.is_err(), "Expected failure due to invalid UTF-8.");
     }
+
+    #[test]
+    fn test_build_request() {
+        let request = build_request(&["a.jpg".to_string(), "dir/b.nef".to_string()], &[]);
+        let lines: Vec<&str> = request.lines().collect();
+        assert_eq!(lines[..3], ["-json", "-c", "%+.6f"]);
+        assert!(!lines.contains(&"-all"));
+        assert_eq!(lines[3..5], ["a.jpg", "dir/b.nef"]);
+        assert!(lines.contains(&"dir/b.nef"));
+        assert_eq!(&lines[lines.len() - 3..], ["-echo4", "{ready}", "-execute"]);
+    }
+
+    #[test]
+    fn test_build_request_asks_for_extra_tags() {
+        let extra_tags = ["MeteringMode".to_string(), "ExposureProgram".to_string()];
+        let request = build_request(&["a.jpg".to_string()], &extra_tags);
+        let lines: Vec<&str> = request.lines().collect();
+        let metering = lines.iter().position(|line| *line == "-MeteringMode").unwrap();
+        assert!(lines.iter().position(|line| *line == "-all").unwrap() < metering);
+        assert!(lines.contains(&"-ExposureProgram"));
+        assert!(metering < lines.iter().position(|line| *line == "a.jpg").unwrap());
+    }
+
+    #[test]
+    fn test_read_until_ready() {
+        let mut reader = Cursor::new("[{\"ISO\": 100}]\n{ready}\nnext response\n");
+        let output = read_until_ready(&mut reader).unwrap();
+        assert_eq!(output, "[{\"ISO\": 100}]\n");
+    }
+
+    #[test]
+    fn test_read_until_ready_unexpected_eof() {
+        let mut reader = Cursor::new("partial output\n");
+        let result = read_until_ready(&mut reader);
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+    }
 }
@@ -0,0 +1,376 @@
+//! Export of geotagged photos as GeoJSON or KML, for QGIS and Google Earth.
+//!
+//! Every geotagged photo becomes a point carrying its camera, lens, date and
+//! settings. Optionally, the photos of each day or stored shooting session are
+//! joined, in capture order, into a line tracing the route.
+
+use crate::cameras::CAMERA_SQL;
+use crate::lenses::LENS_SQL;
+use clap::ValueEnum;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// GeoJSON FeatureCollection
+    Geojson,
+    /// KML document
+    Kml,
+}
+
+impl ExportFormat {
+    /// Format matching the extension of `path`, if any.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "geojson" | "json" => Some(ExportFormat::Geojson),
+            "kml" => Some(ExportFormat::Kml),
+            _ => None,
+        }
+    }
+}
+
+/// How photos are joined into tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackGrouping {
+    /// One track per calendar day
+    Day,
+    /// One track per stored shooting session (see `sessions detect`)
+    Session,
+}
+
+/// A geotagged photo.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhotoPoint {
+    pub source_file: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Meters above sea level
+    pub altitude: Option<f64>,
+    /// Local time at the camera, "2023-06-12 15:30:00"
+    pub date_time: Option<String>,
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+    pub iso: Option<i64>,
+    /// As printed by ExifTool, e.g. "1/200"
+    pub shutter_speed: Option<String>,
+    pub aperture: Option<f64>,
+    /// In mm
+    pub focal_length: Option<f64>,
+    pub city: Option<String>,
+    pub country: Option<String>,
+    pub session: Option<i64>,
+}
+
+/// Dated photos of one day or session, in capture order.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Track {
+    /// "2023-06-12" or "Session 3"
+    pub name: String,
+    pub start: String,
+    pub end: String,
+    /// `(latitude, longitude, altitude)` of each photo
+    pub points: Vec<(f64, f64, Option<f64>)>,
+}
+
+/// Photos and tracks to export.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PhotoMap {
+    pub photos: Vec<PhotoPoint>,
+    pub tracks: Vec<Track>,
+}
+
+/// Loads the geotagged photos matching `condition`, ordered by capture time,
+/// and joins them into tracks when `tracks` is set. Tracks of a single photo
+/// are left out.
+pub fn generate_photo_map(
+    connection: &Connection,
+    condition: &str,
+    params: &[SqlValue],
+    tracks: Option<TrackGrouping>,
+) -> Result<PhotoMap, anyhow::Error> {
+    let mut stmt = connection.prepare(&format!(
+        "SELECT source_file, GPSLatitude, GPSLongitude, GPSAltitude, DateTimeOriginal, {}, {},
+                ISO, ExposureTimeDisplay, FNumber, FocalLength,
+                photo_places.city, photo_places.country,
+                (SELECT session_id FROM session_photos WHERE session_photos.source_file = metadata.source_file)
+         FROM metadata LEFT JOIN photo_places USING (source_file)
+         WHERE {} AND GPSLatitude IS NOT NULL AND GPSLongitude IS NOT NULL
+         ORDER BY DateTimeOriginal, source_file",
+        CAMERA_SQL, LENS_SQL, condition
+    ))?;
+    let photos = stmt
+        .query_map(params_from_iter(params), |row| {
+            Ok(PhotoPoint {
+                source_file: row.get(0)?,
+                latitude: row.get(1)?,
+                longitude: row.get(2)?,
+                altitude: row.get(3)?,
+                date_time: row.get(4)?,
+                camera: row.get(5)?,
+                lens: row.get(6)?,
+                iso: row.get(7)?,
+                shutter_speed: row.get(8)?,
+                aperture: row.get(9)?,
+                focal_length: row.get(10)?,
+                city: row.get(11)?,
+                country: row.get(12)?,
+                session: row.get(13)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let tracks = tracks.map_or_else(Vec::new, |grouping| build_tracks(&photos, grouping));
+    Ok(PhotoMap { photos, tracks })
+}
+
+fn build_tracks(photos: &[PhotoPoint], grouping: TrackGrouping) -> Vec<Track> {
+    // Keys sort chronologically: dates, or session numbers.
+    let mut groups: BTreeMap<(i64, String), Vec<&PhotoPoint>> = BTreeMap::new();
+    for photo in photos {
+        let Some(date_time) = &photo.date_time else {
+            continue;
+        };
+        let key = match grouping {
+            TrackGrouping::Day => (0, date_time.chars().take(10).collect()),
+            TrackGrouping::Session => match photo.session {
+                Some(session) => (session, format!("Session {}", session)),
+                None => continue,
+            },
+        };
+        groups.entry(key).or_default().push(photo);
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|((_, name), members)| Track {
+            name,
+            start: members[0].date_time.clone().unwrap_or_default(),
+            end: members[members.len() - 1].date_time.clone().unwrap_or_default(),
+            points: members.iter().map(|photo| (photo.latitude, photo.longitude, photo.altitude)).collect(),
+        })
+        .collect()
+}
+
+fn coordinates(latitude: f64, longitude: f64, altitude: Option<f64>) -> Vec<f64> {
+    let mut position = vec![longitude, latitude];
+    position.extend(altitude);
+    position
+}
+
+fn file_name(source_file: &str) -> String {
+    Path::new(source_file)
+        .file_name()
+        .map_or_else(|| source_file.to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+/// "Z 6 · 24-70mm · ISO 400 · 1/200 · f/4.0 · 50 mm", leaving out unknown values.
+fn settings_summary(photo: &PhotoPoint) -> String {
+    let parts = [
+        photo.camera.clone(),
+        photo.lens.clone(),
+        photo.iso.map(|iso| format!("ISO {}", iso)),
+        photo.shutter_speed.clone(),
+        photo.aperture.map(|aperture| format!("f/{:.1}", aperture)),
+        photo.focal_length.map(|focal| format!("{} mm", focal)),
+    ];
+    parts.into_iter().flatten().collect::<Vec<_>>().join(" · ")
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn kml_time(date_time: &str) -> String {
+    date_time.replacen(' ', "T", 1)
+}
+
+fn kml_coordinates(latitude: f64, longitude: f64, altitude: Option<f64>) -> String {
+    match altitude {
+        Some(altitude) => format!("{},{},{}", longitude, latitude, altitude),
+        None => format!("{},{}", longitude, latitude),
+    }
+}
+
+impl PhotoMap {
+    /// GeoJSON FeatureCollection with a Point per photo and a LineString per
+    /// track. Coordinates are longitude, latitude and, when known, altitude.
+    pub fn to_geojson(&self) -> Value {
+        let photos = self.photos.iter().map(|photo| {
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": coordinates(photo.latitude, photo.longitude, photo.altitude) },
+                "properties": {
+                    "file": photo.source_file,
+                    "date": photo.date_time,
+                    "camera": photo.camera,
+                    "lens": photo.lens,
+                    "iso": photo.iso,
+                    "shutter_speed": photo.shutter_speed,
+                    "aperture": photo.aperture,
+                    "focal_length": photo.focal_length,
+                    "city": photo.city,
+                    "country": photo.country,
+                    "session": photo.session,
+                },
+            })
+        });
+        let tracks = self.tracks.iter().map(|track| {
+            let line: Vec<Vec<f64>> = track
+                .points
+                .iter()
+                .map(|(latitude, longitude, altitude)| coordinates(*latitude, *longitude, *altitude))
+                .collect();
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "LineString", "coordinates": line },
+                "properties": { "name": track.name, "start": track.start, "end": track.end, "photos": track.points.len() },
+            })
+        });
+        json!({ "type": "FeatureCollection", "features": photos.chain(tracks).collect::<Vec<_>>() })
+    }
+
+    /// KML document with a Photos folder of placemarks and, when there are
+    /// tracks, a Tracks folder of lines.
+    pub fn to_kml(&self) -> String {
+        let mut kml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n<name>Photos</name>\n<Folder>\n<name>Photos</name>\n",
+        );
+        for photo in &self.photos {
+            kml.push_str("<Placemark>\n");
+            kml.push_str(&format!("<name>{}</name>\n", escape_xml(&file_name(&photo.source_file))));
+            kml.push_str(&format!("<description>{}</description>\n", escape_xml(&settings_summary(photo))));
+            if let Some(date_time) = &photo.date_time {
+                kml.push_str(&format!("<TimeStamp><when>{}</when></TimeStamp>\n", kml_time(date_time)));
+            }
+            kml.push_str("<ExtendedData>\n");
+            let data = [
+                ("file", Some(photo.source_file.clone())),
+                ("camera", photo.camera.clone()),
+                ("lens", photo.lens.clone()),
+                ("iso", photo.iso.map(|iso| iso.to_string())),
+                ("shutter_speed", photo.shutter_speed.clone()),
+                ("aperture", photo.aperture.map(|aperture| aperture.to_string())),
+                ("focal_length", photo.focal_length.map(|focal| focal.to_string())),
+                ("city", photo.city.clone()),
+                ("country", photo.country.clone()),
+            ];
+            for (name, value) in data {
+                if let Some(value) = value {
+                    kml.push_str(&format!("<Data name=\"{}\"><value>{}</value></Data>\n", name, escape_xml(&value)));
+                }
+            }
+            kml.push_str("</ExtendedData>\n");
+            kml.push_str(&format!(
+                "<Point><coordinates>{}</coordinates></Point>\n</Placemark>\n",
+                kml_coordinates(photo.latitude, photo.longitude, photo.altitude)
+            ));
+        }
+        kml.push_str("</Folder>\n");
+
+        if !self.tracks.is_empty() {
+            kml.push_str("<Folder>\n<name>Tracks</name>\n");
+            for track in &self.tracks {
+                let line: Vec<String> = track
+                    .points
+                    .iter()
+                    .map(|(latitude, longitude, altitude)| kml_coordinates(*latitude, *longitude, *altitude))
+                    .collect();
+                kml.push_str(&format!(
+                    "<Placemark>\n<name>{}</name>\n<TimeSpan><begin>{}</begin><end>{}</end></TimeSpan>\n\
+                     <LineString><tessellate>1</tessellate><coordinates>{}</coordinates></LineString>\n</Placemark>\n",
+                    escape_xml(&track.name),
+                    kml_time(&track.start),
+                    kml_time(&track.end),
+                    line.join(" ")
+                ));
+            }
+            kml.push_str("</Folder>\n");
+        }
+        kml.push_str("</Document>\n</kml>\n");
+        kml
+    }
+
+    /// The map in `format`, as file contents.
+    pub fn render(&self, format: ExportFormat) -> Result<String, anyhow::Error> {
+        Ok(match format {
+            ExportFormat::Geojson => serde_json::to_string_pretty(&self.to_geojson())?,
+            ExportFormat::Kml => self.to_kml(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::insert_metadata;
+    use crate::migrations::run_migrations;
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let photos = [
+            ("/trip/a.jpg", json!({ "DateTimeOriginal": "2023:05:01 09:00:00", "Model": "Z 6", "ISO": 100, "GPSLatitude": 38.71, "GPSLongitude": -9.14, "GPSAltitude": "50 m" })),
+            ("/trip/b.jpg", json!({ "DateTimeOriginal": "2023:05:01 11:00:00", "Model": "Z 6", "LensModel": "24-70 <S>", "GPSLatitude": 38.8, "GPSLongitude": -9.38 })),
+            ("/trip/c.jpg", json!({ "DateTimeOriginal": "2023:05:02 10:00:00", "Model": "X100V", "GPSLatitude": 41.15, "GPSLongitude": -8.61 })),
+            ("/trip/d.jpg", json!({ "DateTimeOriginal": "2023:05:02 12:00:00", "Model": "X100V" })),
+        ];
+        for (file, metadata) in photos {
+            insert_metadata(&conn, file, 0.0, 0, &metadata).unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn test_geojson_points_and_day_tracks() {
+        let conn = setup();
+        let map = generate_photo_map(&conn, "1", &[], Some(TrackGrouping::Day)).unwrap();
+        assert_eq!(map.photos.len(), 3);
+        assert_eq!(map.tracks.len(), 1);
+        assert_eq!(map.tracks[0].name, "2023-05-01");
+
+        let geojson = map.to_geojson();
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 4);
+        assert_eq!(features[0]["geometry"]["coordinates"], json!([-9.14, 38.71, 50.0]));
+        assert_eq!(features[0]["properties"]["camera"], "Z 6");
+        assert_eq!(features[0]["properties"]["date"], "2023-05-01 09:00:00");
+        assert_eq!(features[3]["geometry"]["type"], "LineString");
+        assert_eq!(features[3]["geometry"]["coordinates"], json!([[-9.14, 38.71, 50.0], [-9.38, 38.8]]));
+
+        let filtered = generate_photo_map(&conn, "Model = ?", &[SqlValue::Text("X100V".to_string())], None).unwrap();
+        assert_eq!(filtered.photos.len(), 1);
+        assert!(filtered.tracks.is_empty());
+    }
+
+    #[test]
+    fn test_kml_document() {
+        let conn = setup();
+        let kml = generate_photo_map(&conn, "1", &[], Some(TrackGrouping::Day)).unwrap().to_kml();
+        assert!(kml.starts_with("<?xml"));
+        assert_eq!(kml.matches("<Point>").count(), 3);
+        assert!(kml.contains("<name>b.jpg</name>"));
+        assert!(kml.contains("24-70 &lt;S&gt;"));
+        assert!(kml.contains("<when>2023-05-01T09:00:00</when>"));
+        assert!(kml.contains("<coordinates>-9.14,38.71,50 -9.38,38.8</coordinates>"));
+        assert!(kml.trim_end().ends_with("</kml>"));
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(ExportFormat::from_path(Path::new("map.geojson")), Some(ExportFormat::Geojson));
+        assert_eq!(ExportFormat::from_path(Path::new("map.KML")), Some(ExportFormat::Kml));
+        assert_eq!(ExportFormat::from_path(Path::new("map.txt")), None);
+    }
+}
@@ -0,0 +1,55 @@
+//! Metadata extraction backends.
+//!
+//! Every backend produces one JSON object per file, in the shape consumed by
+//! [`crate::database::insert_metadata`].
+
+use crate::errors::Result;
+use crate::exiftool::ExifToolExtractor;
+use crate::native::NativeExtractor;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+pub trait MetadataExtractor: Send + Sync {
+    fn extract(&self, file_paths: &[String]) -> Result<Vec<Value>>;
+}
+
+impl<T: MetadataExtractor + ?Sized> MetadataExtractor for Arc<T> {
+    fn extract(&self, file_paths: &[String]) -> Result<Vec<Value>> {
+        (**self).extract(file_paths)
+    }
+}
+
+/// Selects which [`MetadataExtractor`] is used for a scan.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractorBackend {
+    /// External `exiftool` binary
+    #[default]
+    Exiftool,
+    /// Built-in EXIF parser, falling back to ExifTool for unsupported formats
+    Native,
+}
+
+impl ExtractorBackend {
+    /// Backend that actually serves a scan storing `extra_tags`. The native
+    /// parser only reads the built-in tags, so any extra tag sends every file
+    /// to ExifTool.
+    pub fn effective(self, extra_tags: &[String]) -> Self {
+        match self {
+            Self::Native if !extra_tags.is_empty() => Self::Exiftool,
+            backend => backend,
+        }
+    }
+}
+
+/// Creates the extractor for `backend`. ExifTool is asked for `extra_tags`
+/// as well, including when it is the native backend's fallback.
+pub fn create_extractor(backend: ExtractorBackend, extra_tags: &[String]) -> Box<dyn MetadataExtractor> {
+    let exiftool = ExifToolExtractor::new(extra_tags);
+    match backend.effective(extra_tags) {
+        ExtractorBackend::Exiftool => Box::new(exiftool),
+        ExtractorBackend::Native => Box::new(NativeExtractor::with_fallback(Box::new(exiftool))),
+    }
+}
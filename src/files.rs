@@ -1,14 +1,31 @@
 use walkdir::WalkDir;
 use std::collections::HashSet;
+use std::path::PathBuf;
+
+pub const RAW_EXTENSIONS: [&str; 7] = ["cr2", "cr3", "nef", "arw", "raf", "dng", "rw2"];
+pub const JPEG_EXTENSIONS: [&str; 2] = ["jpg", "jpeg"];
+
+/// Resolves `path` to an absolute path without `.` or `..` components or
+/// symlinks, so that a file is cataloged under the same path whatever the
+/// working directory of the scan.
+pub fn canonical_path(path: &str) -> std::io::Result<PathBuf> {
+    let path = std::fs::canonicalize(path)?;
+    // Windows returns verbatim `\\?\C:\...` paths, which ExifTool does not accept.
+    match path.to_str().and_then(|p| p.strip_prefix(r"\\?\")) {
+        Some(stripped) if !stripped.starts_with(r"UNC\") => Ok(PathBuf::from(stripped)),
+        _ => Ok(path),
+    }
+}
 
-const RAW_EXTENSIONS: [&str; 7] = ["cr2", "cr3", "nef", "arw", "raf", "dng", "rw2"];
-const JPEG_EXTENSIONS: [&str; 2] = ["jpg", "jpeg"];
-
+/// Lists the photos under `directory` by their canonical absolute path.
 pub fn scan_directory(directory: &str) -> Vec<String> {
     let allowed_extensions: HashSet<&str> = RAW_EXTENSIONS.iter().chain(JPEG_EXTENSIONS.iter()).cloned().collect();
     let mut files = Vec::new();
+    let Ok(root) = canonical_path(directory) else {
+        return files;
+    };
 
-    for entry in WalkDir::new(directory).into_iter().filter_map(Result::ok) {
+    for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
         if entry.file_type().is_file() {
             if let Some(ext) = entry.path().extension().and_then(|s| s.to_str()) {
                 let ext_lower = ext.to_lowercase();
@@ -105,6 +122,18 @@ mod tests {
         assert!(results.iter().any(|p| p.ends_with("sub.jpg")));
     }
 
+    #[test]
+    fn test_scan_directory_returns_canonical_paths() {
+        let temp_dir = tempdir().unwrap();
+        let sub_dir = temp_dir.path().join("subdir");
+        fs::create_dir(&sub_dir).unwrap();
+        fs::write(temp_dir.path().join("image.jpg"), "test").unwrap();
+
+        let results = scan_directory(sub_dir.join("..").to_str().unwrap());
+        let expected = canonical_path(temp_dir.path().to_str().unwrap()).unwrap().join("image.jpg");
+        assert_eq!(results, vec![expected.display().to_string()]);
+    }
+
     #[test]
     fn test_nonexistent_directory() {
         let results = scan_directory("nonexistent_directory");
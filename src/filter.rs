@@ -0,0 +1,241 @@
+//! Restricting statistics to a subset of the catalog.
+//!
+//! A [`StatisticsFilter`] is turned into a SQL condition on the `metadata`
+//! table. Every criterion is optional; an empty filter matches every photo.
+
+use crate::cameras::CAMERA_SQL;
+use crate::lenses::LENS_SQL;
+use chrono::NaiveDate;
+use clap::Args;
+use rusqlite::types::Value as SqlValue;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, PartialEq, Args, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatisticsFilter {
+    /// Only photos taken on or after this date (YYYY-MM-DD)
+    #[arg(long = "from", value_name = "DATE")]
+    pub date_from: Option<NaiveDate>,
+
+    /// Only photos taken on or before this date (YYYY-MM-DD)
+    #[arg(long = "to", value_name = "DATE")]
+    pub date_to: Option<NaiveDate>,
+
+    /// Only photos taken with this camera, by canonical name or raw model (repeatable)
+    #[arg(long = "camera", value_name = "MODEL")]
+    pub cameras: Vec<String>,
+
+    /// Only photos taken with this lens, by name, alias or raw LensModel (repeatable)
+    #[arg(long = "lens", value_name = "LENS")]
+    pub lenses: Vec<String>,
+
+    /// Only photos under this directory
+    #[arg(long = "path", value_name = "DIR")]
+    pub path_prefix: Option<String>,
+
+    /// Minimum ISO
+    #[arg(long)]
+    pub iso_min: Option<i64>,
+
+    /// Maximum ISO
+    #[arg(long)]
+    pub iso_max: Option<i64>,
+
+    /// Minimum f-number
+    #[arg(long)]
+    pub aperture_min: Option<f64>,
+
+    /// Maximum f-number
+    #[arg(long)]
+    pub aperture_max: Option<f64>,
+
+    /// Minimum focal length in mm
+    #[arg(long)]
+    pub focal_min: Option<f64>,
+
+    /// Maximum focal length in mm
+    #[arg(long)]
+    pub focal_max: Option<f64>,
+
+    /// Only photos of this shooting session (see `sessions list`)
+    #[arg(long, value_name = "ID")]
+    pub session: Option<i64>,
+
+    /// Count the RAW and JPEG files of a RAW+JPEG shot separately
+    #[arg(long)]
+    pub count_files: bool,
+}
+
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+impl StatisticsFilter {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Builds the condition for a `WHERE` clause on `metadata` and its
+    /// parameters. Unless `count_files` is set, the JPEG half of every
+    /// RAW+JPEG pair is left out, so each shot is counted once.
+    pub fn to_sql(&self) -> (String, Vec<SqlValue>) {
+        let mut conditions: Vec<String> = Vec::new();
+        let mut params: Vec<SqlValue> = Vec::new();
+
+        if let Some(from) = self.date_from {
+            conditions.push("DateTimeOriginal >= ?".to_string());
+            params.push(SqlValue::Text(from.format("%Y-%m-%d 00:00:00").to_string()));
+        }
+        if let Some(to) = self.date_to {
+            conditions.push("DateTimeOriginal <= ?".to_string());
+            params.push(SqlValue::Text(to.format("%Y-%m-%d 23:59:59").to_string()));
+        }
+        if !self.cameras.is_empty() {
+            // Canonical names and raw models both match.
+            conditions.push(format!(
+                "({} IN ({1}) OR Model IN ({1}))",
+                CAMERA_SQL,
+                placeholders(self.cameras.len())
+            ));
+            params.extend(self.cameras.iter().chain(&self.cameras).cloned().map(SqlValue::Text));
+        }
+        if !self.lenses.is_empty() {
+            conditions.push(format!(
+                "({} IN ({1}) OR LensName IN ({1}) OR LensModel IN ({1}))",
+                LENS_SQL,
+                placeholders(self.lenses.len())
+            ));
+            params.extend((0..3).flat_map(|_| self.lenses.iter().cloned()).map(SqlValue::Text));
+        }
+        if let Some(prefix) = &self.path_prefix {
+            // Compared per path component, so "/trips/ice" does not match "/trips/iceland".
+            let directory = prefix.trim_end_matches('/');
+            conditions.push("(source_file = ? OR substr(source_file, 1, ?) = ?)".to_string());
+            params.push(SqlValue::Text(directory.to_string()));
+            params.push(SqlValue::Integer(directory.chars().count() as i64 + 1));
+            params.push(SqlValue::Text(format!("{}/", directory)));
+        }
+
+        if let Some(session) = self.session {
+            conditions.push("source_file IN (SELECT source_file FROM session_photos WHERE session_id = ?)".to_string());
+            params.push(SqlValue::Integer(session));
+        }
+
+        let ranges = [
+            ("ISO", self.iso_min.map(|v| v as f64), self.iso_max.map(|v| v as f64)),
+            ("FNumber", self.aperture_min, self.aperture_max),
+            ("FocalLength", self.focal_min, self.focal_max),
+        ];
+        for (column, min, max) in ranges {
+            if let Some(min) = min {
+                conditions.push(format!("{} >= ?", column));
+                params.push(SqlValue::Real(min));
+            }
+            if let Some(max) = max {
+                conditions.push(format!("{} <= ?", column));
+                params.push(SqlValue::Real(max));
+            }
+        }
+
+        if !self.count_files {
+            conditions.push("source_file NOT IN (SELECT jpeg_file FROM raw_jpeg_pairs)".to_string());
+        }
+
+        if conditions.is_empty() {
+            return ("1".to_string(), params);
+        }
+        (conditions.join(" AND "), params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::insert_metadata;
+    use crate::migrations::run_migrations;
+    use rusqlite::Connection;
+    use serde_json::json;
+
+    fn matching_files(conn: &Connection, filter: &StatisticsFilter) -> Vec<String> {
+        let (condition, params) = filter.to_sql();
+        let mut stmt = conn
+            .prepare(&format!("SELECT source_file FROM metadata WHERE {} ORDER BY source_file", condition))
+            .unwrap();
+        stmt.query_map(rusqlite::params_from_iter(params), |row| row.get(0))
+            .unwrap()
+            .filter_map(Result::ok)
+            .collect()
+    }
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let photos = [
+            ("/trips/iceland/a.raf", json!({ "DateTimeOriginal": "2023:03:01 10:00:00", "Model": "X-T5", "LensModel": "XF16-55mm", "ISO": 200, "FNumber": 8, "FocalLength": "16 mm" })),
+            ("/trips/iceland/b.raf", json!({ "DateTimeOriginal": "2023:12:31 23:30:00", "Model": "X-T5", "LensModel": "XF56mm", "ISO": 3200, "FNumber": 1.2, "FocalLength": "56 mm" })),
+            ("/trips/icelandic/c.nef", json!({ "DateTimeOriginal": "2024:01:01 09:00:00", "Model": "NIKON Z 6_2", "ISO": 100 })),
+            ("/home/d.jpg", json!({})),
+        ];
+        for (file, metadata) in photos {
+            insert_metadata(&conn, file, 0.0, 0, &metadata).unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn test_empty_filter_matches_everything() {
+        let conn = setup();
+        let filter = StatisticsFilter::default();
+        assert!(filter.is_empty());
+        assert_eq!(matching_files(&conn, &filter).len(), 4);
+    }
+
+    #[test]
+    fn test_filter_by_camera_and_year() {
+        let conn = setup();
+        let filter = StatisticsFilter {
+            date_from: NaiveDate::from_ymd_opt(2023, 1, 1),
+            date_to: NaiveDate::from_ymd_opt(2023, 12, 31),
+            cameras: vec!["X-T5".to_string()],
+            ..StatisticsFilter::default()
+        };
+        assert_eq!(matching_files(&conn, &filter), vec!["/trips/iceland/a.raf", "/trips/iceland/b.raf"]);
+    }
+
+    #[test]
+    fn test_filter_by_path_prefix_respects_components() {
+        let conn = setup();
+        let filter = StatisticsFilter {
+            path_prefix: Some("/trips/iceland/".to_string()),
+            ..StatisticsFilter::default()
+        };
+        assert_eq!(matching_files(&conn, &filter), vec!["/trips/iceland/a.raf", "/trips/iceland/b.raf"]);
+    }
+
+    #[test]
+    fn test_raw_jpeg_pairs_count_once() {
+        let conn = setup();
+        let shot = json!({ "DateTimeOriginal": "2023:03:01 10:00:00", "Model": "X-T5" });
+        insert_metadata(&conn, "/trips/iceland/a.jpg", 0.0, 0, &shot).unwrap();
+        insert_metadata(&conn, "/trips/iceland/a.raf", 0.0, 0, &shot).unwrap();
+        crate::pairs::detect_pairs(&conn).unwrap();
+
+        let filter = StatisticsFilter { path_prefix: Some("/trips/iceland".to_string()), ..StatisticsFilter::default() };
+        assert_eq!(matching_files(&conn, &filter), vec!["/trips/iceland/a.raf", "/trips/iceland/b.raf"]);
+        let files = StatisticsFilter { count_files: true, ..filter };
+        assert_eq!(matching_files(&conn, &files).len(), 3);
+    }
+
+    #[test]
+    fn test_filter_by_ranges() {
+        let conn = setup();
+        let filter = StatisticsFilter {
+            iso_max: Some(1600),
+            aperture_min: Some(4.0),
+            focal_max: Some(24.0),
+            lenses: vec!["XF16-55mm".to_string()],
+            ..StatisticsFilter::default()
+        };
+        assert_eq!(matching_files(&conn, &filter), vec!["/trips/iceland/a.raf"]);
+    }
+}
@@ -0,0 +1,390 @@
+//! Offline reverse geocoding of photo positions to country, region and city.
+//!
+//! Every geotagged photo is matched to the nearest place of a gazetteer within
+//! the configured distance; nothing is looked up over the network. The bundled
+//! gazetteer is a hand-compiled list of capitals and large cities, which
+//! `scripts/update_gazetteer.py` can replace with a GeoNames extract. For
+//! town-level results, point `geocoding.cities` at a GeoNames dump such as
+//! `cities500.txt`, and `geocoding.admin1_codes` at `admin1CodesASCII.txt` for
+//! the region names.
+//! Matches are stored in the `photo_places` table. After a scan, only photos
+//! that are new or whose position changed are looked up, unless the gazetteer
+//! or its settings changed, which resolves the whole catalog again.
+
+use crate::errors::{AppError, Result};
+use crate::sessions::distance_km;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const BUNDLED_CITIES: &str = include_str!("../assets/gazetteer/cities.tsv");
+const COUNTRY_NAMES: &str = include_str!("../assets/gazetteer/countries.tsv");
+
+/// Geocoding settings, read from the `geocoding` section of the configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeocodingConfig {
+    /// GeoNames cities file (`cities500.txt`, `cities1000.txt`, ...) used
+    /// instead of the bundled gazetteer
+    pub cities: Option<PathBuf>,
+    /// GeoNames `admin1CodesASCII.txt`, naming the regions of `cities`
+    pub admin1_codes: Option<PathBuf>,
+    /// Photos farther than this from every place stay unresolved
+    pub max_distance_km: f64,
+}
+
+impl Default for GeocodingConfig {
+    fn default() -> Self {
+        Self { cities: None, admin1_codes: None, max_distance_km: 100.0 }
+    }
+}
+
+impl GeocodingConfig {
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if !(self.max_distance_km.is_finite() && self.max_distance_km > 0.0) {
+            return Err("max_distance_km must be a positive number".to_string());
+        }
+        if self.admin1_codes.is_some() && self.cities.is_none() {
+            return Err("admin1_codes requires cities".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Named place a photo position resolves to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Place {
+    pub city: String,
+    /// First-level administrative division (state, province, ...), when known
+    pub region: Option<String>,
+    /// ISO 3166-1 alpha-2 code
+    pub country_code: String,
+    pub country: String,
+}
+
+/// Places indexed by 1° latitude/longitude cells for nearest-place lookups.
+#[derive(Debug)]
+pub struct Gazetteer {
+    places: Vec<(f64, f64, Place)>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    max_distance_km: f64,
+}
+
+fn cell(latitude: f64, longitude: f64) -> (i32, i32) {
+    (latitude.floor() as i32, (longitude.floor() as i32 + 180).rem_euclid(360))
+}
+
+fn country_names() -> HashMap<&'static str, &'static str> {
+    COUNTRY_NAMES
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once('\t'))
+        .collect()
+}
+
+fn parse_coordinates(latitude: &str, longitude: &str) -> Option<(f64, f64)> {
+    let latitude: f64 = latitude.trim().parse().ok()?;
+    let longitude: f64 = longitude.trim().parse().ok()?;
+    (latitude.abs() <= 90.0 && longitude.abs() <= 180.0).then_some((latitude, longitude))
+}
+
+fn invalid_line(source: &str, number: usize) -> AppError {
+    AppError::Config(format!("Invalid gazetteer line {} in {}", number + 1, source))
+}
+
+/// Parses the bundled format: name, region, country code, latitude and
+/// longitude, tab-separated.
+fn parse_bundled(text: &str, source: &str) -> Result<Vec<(f64, f64, Place)>> {
+    let countries = country_names();
+    let mut places = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [city, region, code, latitude, longitude] = fields[..] else {
+            return Err(invalid_line(source, number));
+        };
+        let (latitude, longitude) = parse_coordinates(latitude, longitude).ok_or_else(|| invalid_line(source, number))?;
+        let place = Place {
+            city: city.to_string(),
+            region: Some(region.to_string()).filter(|region| !region.is_empty()),
+            country_code: code.to_string(),
+            country: countries.get(code).map_or_else(|| code.to_string(), |name| name.to_string()),
+        };
+        places.push((latitude, longitude, place));
+    }
+    Ok(places)
+}
+
+/// Parses a GeoNames cities dump. `admin1` maps "CC.code" to region names.
+fn parse_geonames(text: &str, source: &str, admin1: &HashMap<String, String>) -> Result<Vec<(f64, f64, Place)>> {
+    let countries = country_names();
+    let mut places = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 11 {
+            return Err(invalid_line(source, number));
+        }
+        let (latitude, longitude) = parse_coordinates(fields[4], fields[5]).ok_or_else(|| invalid_line(source, number))?;
+        let code = fields[8];
+        let place = Place {
+            city: fields[1].to_string(),
+            region: admin1.get(&format!("{}.{}", code, fields[10])).cloned(),
+            country_code: code.to_string(),
+            country: countries.get(code).map_or_else(|| code.to_string(), |name| name.to_string()),
+        };
+        places.push((latitude, longitude, place));
+    }
+    Ok(places)
+}
+
+/// Parses GeoNames `admin1CodesASCII.txt`: code, name, ASCII name, geonameid.
+fn parse_admin1_codes(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            Some((fields.next()?.to_string(), fields.next()?.to_string()))
+        })
+        .collect()
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|err| AppError::Config(format!("Cannot read gazetteer {}: {}", path.display(), err)))
+}
+
+impl Gazetteer {
+    /// Indexes `places`, given as `(latitude, longitude, place)`.
+    pub fn new(places: Vec<(f64, f64, Place)>, max_distance_km: f64) -> Self {
+        let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for (index, (latitude, longitude, _)) in places.iter().enumerate() {
+            cells.entry(cell(*latitude, *longitude)).or_default().push(index);
+        }
+        Self { places, cells, max_distance_km }
+    }
+
+    /// Loads the configured GeoNames files, or the bundled gazetteer.
+    pub fn load(config: &GeocodingConfig) -> Result<Self> {
+        let places = match &config.cities {
+            Some(path) => {
+                let admin1 = match &config.admin1_codes {
+                    Some(codes) => parse_admin1_codes(&read_file(codes)?),
+                    None => HashMap::new(),
+                };
+                parse_geonames(&read_file(path)?, &path.display().to_string(), &admin1)?
+            }
+            None => parse_bundled(BUNDLED_CITIES, "the bundled gazetteer")?,
+        };
+        Ok(Self::new(places, config.max_distance_km))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.places.is_empty()
+    }
+
+    /// Nearest place within the maximum distance.
+    pub fn lookup(&self, latitude: f64, longitude: f64) -> Option<&Place> {
+        let (row, column) = cell(latitude, longitude);
+        let rows = (self.max_distance_km / 111.0).ceil() as i32;
+        // Cells narrow towards the poles, so more of them span the distance.
+        let narrowest = (latitude.abs() + f64::from(rows)).min(89.0).to_radians().cos();
+        let columns = ((self.max_distance_km / (111.0 * narrowest)).ceil() as i32).min(180);
+
+        let mut nearest: Option<(f64, &Place)> = None;
+        for row in row - rows..=row + rows {
+            for column in column - columns..=column + columns {
+                let Some(indices) = self.cells.get(&(row, column.rem_euclid(360))) else {
+                    continue;
+                };
+                for &index in indices {
+                    let (place_latitude, place_longitude, place) = &self.places[index];
+                    let distance = distance_km((latitude, longitude), (*place_latitude, *place_longitude));
+                    if distance <= self.max_distance_km && nearest.is_none_or(|(best, _)| distance < best) {
+                        nearest = Some((distance, place));
+                    }
+                }
+            }
+        }
+        nearest.map(|(_, place)| place)
+    }
+}
+
+/// FNV-1a hash, stable across builds unlike the standard library's hasher.
+fn fnv1a(text: &str) -> u64 {
+    text.bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Identifies the gazetteer `config` loads, by the size and modification time
+/// of its files or the contents of the bundled one, and its maximum distance.
+fn gazetteer_fingerprint(config: &GeocodingConfig) -> Result<String> {
+    let file = |path: &Path| -> Result<String> {
+        let metadata = std::fs::metadata(path)
+            .map_err(|err| AppError::Config(format!("Cannot read gazetteer {}: {}", path.display(), err)))?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_secs());
+        Ok(format!("{}:{}:{}", path.display(), metadata.len(), modified))
+    };
+    let source = match &config.cities {
+        Some(cities) => {
+            let admin1 = config.admin1_codes.as_deref().map(file).transpose()?;
+            format!("{};{}", file(cities)?, admin1.unwrap_or_default())
+        }
+        None => format!("bundled:{:016x}:{:016x}", fnv1a(BUNDLED_CITIES), fnv1a(COUNTRY_NAMES)),
+    };
+    Ok(format!("{};{}", source, config.max_distance_km))
+}
+
+/// Resolves the positions of the geotagged photos not yet geocoded at their
+/// current position, or of every photo when the gazetteer changed, and updates
+/// the `photo_places` table. The gazetteer is only loaded when there is a
+/// position to resolve. Returns the number of photos placed.
+pub fn geocode_catalog(conn: &Connection, config: &GeocodingConfig) -> Result<usize> {
+    let fingerprint = gazetteer_fingerprint(config)?;
+    let transaction = conn.unchecked_transaction()?;
+
+    let previous: Option<String> = transaction
+        .query_row("SELECT gazetteer FROM geocoding_state", [], |row| row.get(0))
+        .optional()?;
+    if previous.as_deref() != Some(fingerprint.as_str()) {
+        transaction.execute("DELETE FROM photo_places", [])?;
+        transaction.execute("DELETE FROM geocoded_positions", [])?;
+        transaction.execute("DELETE FROM geocoding_state", [])?;
+        transaction.execute("INSERT INTO geocoding_state (gazetteer) VALUES (?)", [&fingerprint])?;
+    }
+
+    // Photos whose position was removed keep no place.
+    for table in ["photo_places", "geocoded_positions"] {
+        transaction.execute(
+            &format!(
+                "DELETE FROM {} WHERE source_file IN (
+                    SELECT source_file FROM metadata WHERE GPSLatitude IS NULL OR GPSLongitude IS NULL
+                )",
+                table
+            ),
+            [],
+        )?;
+    }
+
+    let positions = {
+        let mut stmt = transaction.prepare(
+            "SELECT metadata.source_file, GPSLatitude, GPSLongitude
+             FROM metadata LEFT JOIN geocoded_positions USING (source_file)
+             WHERE GPSLatitude IS NOT NULL AND GPSLongitude IS NOT NULL
+               AND (geocoded_positions.source_file IS NULL
+                    OR geocoded_positions.latitude <> GPSLatitude
+                    OR geocoded_positions.longitude <> GPSLongitude)",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?, row.get::<_, f64>(2)?)))?;
+        rows.collect::<std::result::Result<Vec<_>, _>>()?
+    };
+    if positions.is_empty() {
+        transaction.commit()?;
+        return Ok(0);
+    }
+
+    let gazetteer = Gazetteer::load(config)?;
+    let mut placed = 0;
+    for (source_file, latitude, longitude) in &positions {
+        transaction.execute("DELETE FROM photo_places WHERE source_file = ?", [source_file])?;
+        if let Some(place) = gazetteer.lookup(*latitude, *longitude) {
+            transaction.execute(
+                "INSERT INTO photo_places (source_file, city, region, country_code, country) VALUES (?, ?, ?, ?, ?)",
+                rusqlite::params![source_file, place.city, place.region, place.country_code, place.country],
+            )?;
+            placed += 1;
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO geocoded_positions (source_file, latitude, longitude) VALUES (?, ?, ?)",
+            rusqlite::params![source_file, latitude, longitude],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(placed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::insert_metadata;
+    use crate::migrations::run_migrations;
+    use serde_json::json;
+
+    #[test]
+    fn test_bundled_gazetteer_lookup() {
+        let gazetteer = Gazetteer::load(&GeocodingConfig::default()).unwrap();
+        assert!(!gazetteer.is_empty());
+
+        let lisbon = gazetteer.lookup(38.71, -9.13).unwrap();
+        assert_eq!((lisbon.city.as_str(), lisbon.country.as_str()), ("Lisbon", "Portugal"));
+        let reykjavik = gazetteer.lookup(64.14, -21.9).unwrap();
+        assert_eq!(reykjavik.region.as_deref(), Some("Capital Region"));
+        // The middle of the Atlantic.
+        assert_eq!(gazetteer.lookup(30.0, -40.0), None);
+
+        let taveuni = Place { city: "Waiyevo".to_string(), region: None, country_code: "FJ".to_string(), country: "Fiji".to_string() };
+        let gazetteer = Gazetteer::new(vec![(-16.79, 179.98, taveuni.clone())], 50.0);
+        assert_eq!(gazetteer.lookup(-16.8, -179.95), Some(&taveuni));
+    }
+
+    #[test]
+    fn test_geonames_format() {
+        let cities = "2267057\tLisbon\tLisbon\tLisboa\t38.71667\t-9.13333\tP\tPPLC\tPT\t\t14\t\t\t\t517802\t\t45\tEurope/Lisbon\t2023-01-01\n\
+                      2735943\tPorto\tPorto\t\t41.14961\t-8.61099\tP\tPPLA\tPT\t\t17\t\t\t\t249633\t\t95\tEurope/Lisbon\t2023-01-01\n";
+        let admin1 = parse_admin1_codes("PT.14\tLisbon\tLisbon\t2267056\n");
+        let gazetteer = Gazetteer::new(parse_geonames(cities, "cities500.txt", &admin1).unwrap(), 50.0);
+
+        let porto = gazetteer.lookup(41.1, -8.6).unwrap();
+        assert_eq!(porto, &Place { city: "Porto".to_string(), region: None, country_code: "PT".to_string(), country: "Portugal".to_string() });
+        assert_eq!(gazetteer.lookup(38.7, -9.1).unwrap().region.as_deref(), Some("Lisbon"));
+
+        let error = parse_geonames("2267057\tLisbon\n", "cities500.txt", &admin1).unwrap_err();
+        assert!(error.to_string().contains("line 1 in cities500.txt"));
+    }
+
+    #[test]
+    fn test_geocode_catalog() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let photos = [
+            ("a.jpg", json!({ "GPSLatitude": 38.7, "GPSLatitudeRef": "N", "GPSLongitude": 9.1333, "GPSLongitudeRef": "W" })),
+            ("b.jpg", json!({ "GPSLatitude": 0.0, "GPSLongitude": -30.0 })),
+            ("c.jpg", json!({})),
+        ];
+        for (file, metadata) in photos {
+            insert_metadata(&conn, file, 0.0, 0, &metadata).unwrap();
+        }
+
+        let config = GeocodingConfig::default();
+        assert_eq!(geocode_catalog(&conn, &config).unwrap(), 1);
+        let city = |file: &str| -> Option<String> {
+            conn.query_row("SELECT city FROM photo_places WHERE source_file = ?", [file], |row| row.get(0))
+                .optional()
+                .unwrap()
+        };
+        assert_eq!(city("a.jpg").as_deref(), Some("Lisbon"));
+
+        // Only new and moved photos are looked up again.
+        assert_eq!(geocode_catalog(&conn, &config).unwrap(), 0);
+        insert_metadata(&conn, "a.jpg", 0.0, 0, &json!({ "GPSLatitude": 41.15, "GPSLongitude": -8.61 })).unwrap();
+        insert_metadata(&conn, "c.jpg", 0.0, 0, &json!({ "GPSLatitude": 38.7, "GPSLongitude": -9.14 })).unwrap();
+        assert_eq!(geocode_catalog(&conn, &config).unwrap(), 2);
+        assert_eq!(city("a.jpg").as_deref(), Some("Porto"));
+        assert_eq!(city("c.jpg").as_deref(), Some("Lisbon"));
+
+        // Another gazetteer setting resolves every photo again.
+        let near = GeocodingConfig { max_distance_km: 1.0, ..GeocodingConfig::default() };
+        assert_eq!(geocode_catalog(&conn, &near).unwrap(), 1);
+        assert_eq!(city("a.jpg").as_deref(), Some("Porto"));
+        assert_eq!(city("c.jpg"), None);
+    }
+}
@@ -0,0 +1,316 @@
+//! Gear usage insights for lens purchase decisions.
+//!
+//! For every lens, the focal lengths it was used at are grouped into clusters
+//! of nearby values. A zoom used mostly at a couple of clusters could be
+//! replaced by primes at those focal lengths; the report lists them until the
+//! configured share of the lens' shots is covered. Lenses not used for a
+//! while are flagged as well.
+
+use crate::lenses::{IDENTIFIED_LENS_SQL, LENS_SQL};
+use chrono::{Months, NaiveDate};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Consecutive focal lengths belong to one cluster when the longer one is at
+/// most this much longer than the shorter one.
+const CLUSTER_GAP_RATIO: f64 = 1.15;
+
+/// Clusters holding less than this share of a lens' shots do not count as
+/// used range.
+const MIN_CLUSTER_PERCENT: f64 = 5.0;
+
+/// Insight settings, read from the `insights` section of the configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InsightsConfig {
+    /// Share of a lens' shots the suggested primes should cover
+    pub coverage_percent: f64,
+    /// Lenses without photos in this many months are reported as idle
+    pub idle_months: u32,
+}
+
+impl Default for InsightsConfig {
+    fn default() -> Self {
+        Self { coverage_percent: 80.0, idle_months: 12 }
+    }
+}
+
+impl InsightsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.coverage_percent > 0.0 && self.coverage_percent <= 100.0) {
+            return Err("coverage_percent must be between 0 and 100".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Focal lengths a lens was used at, with no gap wider than
+/// [`CLUSTER_GAP_RATIO`] between them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FocalCluster {
+    /// Shortest focal length in the cluster, in mm
+    pub from: f64,
+    /// Longest focal length in the cluster, in mm
+    pub to: f64,
+    /// Most used focal length in the cluster
+    pub peak: f64,
+    pub photos: i32,
+    /// Share of the lens' photos with a focal length
+    pub percent: f64,
+}
+
+/// A prime that would replace one cluster of a zoom.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PrimeSuggestion {
+    pub focal_length: f64,
+    pub photos: i32,
+    /// Share of the lens' shots covered by this and the previous suggestions
+    pub cumulative_percent: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LensUsage {
+    pub lens: String,
+    pub photos: i32,
+    /// Focal range from the lens name ("24-70mm"), or else the range it was
+    /// used at
+    pub range: (f64, f64),
+    pub zoom: bool,
+    pub clusters: Vec<FocalCluster>,
+    /// Share of the zoom range outside the clusters holding at least 5% of
+    /// the shots
+    pub unused_range_percent: f64,
+    /// Primes, most used first, covering the configured share of the shots
+    pub primes: Vec<PrimeSuggestion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IdleLens {
+    pub lens: String,
+    /// Date of the latest photo, "2023-06-12"
+    pub last_used: String,
+    pub photos: i32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GearInsights {
+    /// Lenses by number of photos, most used first
+    pub lenses: Vec<LensUsage>,
+    /// Lenses not used in the configured number of months, least recently
+    /// used first
+    pub idle_lenses: Vec<IdleLens>,
+}
+
+/// Focal range written in a lens name, e.g. (16, 55) for "XF16-55mmF2.8 R LM
+/// WR" or (56, 56) for "XF56mmF1.2 R".
+pub fn nominal_range(lens: &str) -> Option<(f64, f64)> {
+    let lower = lens.to_lowercase();
+    let end = lower.find("mm")?;
+    let before = lower[..end].trim_end();
+    let start = before
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_ascii_digit() || *c == '.' || *c == '-')
+        .last()
+        .map(|(index, _)| index)?;
+    let mut bounds = before[start..].trim_start_matches('-').split('-').map(str::parse::<f64>);
+    let min = bounds.next()?.ok()?;
+    let max = match bounds.next() {
+        Some(max) => max.ok()?,
+        None => min,
+    };
+    (min > 0.0 && max >= min).then_some((min, max))
+}
+
+/// Groups `(focal length, photos)` pairs sorted by focal length into clusters.
+fn clusters(focal_lengths: &[(f64, i32)], total: i32) -> Vec<FocalCluster> {
+    // Each cluster with the number of photos at its peak.
+    let mut clusters: Vec<(FocalCluster, i32)> = Vec::new();
+    for &(focal, photos) in focal_lengths {
+        match clusters.last_mut() {
+            Some((cluster, peak_photos)) if focal <= cluster.to * CLUSTER_GAP_RATIO => {
+                cluster.to = focal;
+                cluster.photos += photos;
+                if photos > *peak_photos {
+                    cluster.peak = focal;
+                    *peak_photos = photos;
+                }
+            }
+            _ => clusters.push((FocalCluster { from: focal, to: focal, peak: focal, photos, percent: 0.0 }, photos)),
+        }
+    }
+    clusters
+        .into_iter()
+        .map(|(mut cluster, _)| {
+            cluster.percent = 100.0 * f64::from(cluster.photos) / f64::from(total);
+            cluster
+        })
+        .collect()
+}
+
+fn lens_usage(lens: String, photos: i32, focal_lengths: &[(f64, i32)], config: &InsightsConfig) -> LensUsage {
+    let total: i32 = focal_lengths.iter().map(|(_, count)| count).sum();
+    let clusters = if total > 0 { clusters(focal_lengths, total) } else { Vec::new() };
+    let used = (
+        focal_lengths.first().map_or(0.0, |(focal, _)| *focal),
+        focal_lengths.last().map_or(0.0, |(focal, _)| *focal),
+    );
+    let range = nominal_range(&lens).unwrap_or(used);
+    let zoom = range.1 > range.0;
+
+    let mut unused_range_percent = 0.0;
+    let mut primes = Vec::new();
+    if zoom {
+        let width = range.1 - range.0;
+        let covered: f64 = clusters
+            .iter()
+            .filter(|cluster| cluster.percent >= MIN_CLUSTER_PERCENT)
+            .map(|cluster| cluster.to.min(range.1) - cluster.from.max(range.0))
+            .filter(|covered| *covered > 0.0)
+            .sum();
+        unused_range_percent = 100.0 * (1.0 - covered / width).clamp(0.0, 1.0);
+
+        let mut by_photos: Vec<&FocalCluster> = clusters.iter().collect();
+        by_photos.sort_by(|a, b| b.photos.cmp(&a.photos).then(a.peak.total_cmp(&b.peak)));
+        let mut cumulative = 0;
+        for cluster in by_photos {
+            cumulative += cluster.photos;
+            let cumulative_percent = 100.0 * f64::from(cumulative) / f64::from(total);
+            primes.push(PrimeSuggestion { focal_length: cluster.peak, photos: cluster.photos, cumulative_percent });
+            if cumulative_percent >= config.coverage_percent {
+                break;
+            }
+        }
+    }
+
+    LensUsage { lens, photos, range, zoom, clusters, unused_range_percent, primes }
+}
+
+/// Analyzes the lens usage of the photos matching `condition`. Lenses whose
+/// latest photo is more than `config.idle_months` before `today` are
+/// reported as idle.
+pub fn generate_gear_insights(
+    connection: &Connection,
+    condition: &str,
+    params: &[SqlValue],
+    config: &InsightsConfig,
+    today: NaiveDate,
+) -> Result<GearInsights, anyhow::Error> {
+    let mut stmt = connection.prepare(&format!(
+        "SELECT {}, COUNT(*), MAX(DateTimeOriginal) FROM metadata
+         WHERE {} AND {}
+         GROUP BY 1",
+        LENS_SQL, condition, IDENTIFIED_LENS_SQL
+    ))?;
+    let lenses = stmt
+        .query_map(params_from_iter(params), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i32>(1)?, row.get::<_, Option<String>>(2)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut stmt = connection.prepare(&format!(
+        "SELECT {}, ROUND(FocalLength), COUNT(*) FROM metadata
+         WHERE {} AND {} AND FocalLength > 0
+         GROUP BY 1, 2 ORDER BY 1, 2",
+        LENS_SQL, condition, IDENTIFIED_LENS_SQL
+    ))?;
+    let mut focal_lengths: BTreeMap<String, Vec<(f64, i32)>> = BTreeMap::new();
+    for row in stmt.query_map(params_from_iter(params), |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?, row.get::<_, i32>(2)?))
+    })? {
+        let (lens, focal, count) = row?;
+        focal_lengths.entry(lens).or_default().push((focal, count));
+    }
+
+    let idle_since = today
+        .checked_sub_months(Months::new(config.idle_months))
+        .map(|date| date.format("%Y-%m-%d").to_string());
+    let mut insights = GearInsights::default();
+    for (lens, photos, last_used) in lenses {
+        if let (Some(last_used), Some(idle_since)) = (&last_used, &idle_since) {
+            if last_used[..10] < idle_since[..] {
+                insights.idle_lenses.push(IdleLens { lens: lens.clone(), last_used: last_used[..10].to_string(), photos });
+            }
+        }
+        let usage = lens_usage(lens.clone(), photos, focal_lengths.get(&lens).map_or(&[], Vec::as_slice), config);
+        insights.lenses.push(usage);
+    }
+    insights.lenses.sort_by(|a, b| b.photos.cmp(&a.photos).then_with(|| a.lens.cmp(&b.lens)));
+    insights.idle_lenses.sort_by(|a, b| a.last_used.cmp(&b.last_used).then_with(|| a.lens.cmp(&b.lens)));
+    Ok(insights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::insert_metadata;
+    use crate::lenses::{normalize_lenses, LensNames};
+    use crate::migrations::run_migrations;
+    use serde_json::json;
+
+    #[test]
+    fn test_nominal_range() {
+        assert_eq!(nominal_range("XF16-55mmF2.8 R LM WR"), Some((16.0, 55.0)));
+        assert_eq!(nominal_range("NIKKOR Z 24-70mm f/4 S"), Some((24.0, 70.0)));
+        assert_eq!(nominal_range("EF70-200mm f/2.8L IS II USM"), Some((70.0, 200.0)));
+        assert_eq!(nominal_range("XF56mmF1.2 R"), Some((56.0, 56.0)));
+        assert_eq!(nominal_range("E 18-135mm F3.5-5.6 OSS"), Some((18.0, 135.0)));
+        assert_eq!(nominal_range("Unknown lens"), None);
+    }
+
+    #[test]
+    fn test_zoom_clusters_and_primes() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let shots = [(24, 40), (26, 10), (35, 5), (50, 2), (70, 43)];
+        let mut index = 0;
+        for (focal, count) in shots {
+            for _ in 0..count {
+                index += 1;
+                let metadata = json!({
+                    "LensModel": "NIKKOR Z 24-70mm f/4 S",
+                    "FocalLength": format!("{} mm", focal),
+                    "DateTimeOriginal": "2024:03:01 10:00:00",
+                });
+                insert_metadata(&conn, &format!("{}.nef", index), 0.0, 0, &metadata).unwrap();
+            }
+        }
+        let old = json!({ "LensModel": "XF56mmF1.2 R", "FocalLength": "56 mm", "DateTimeOriginal": "2022:01:15 10:00:00" });
+        insert_metadata(&conn, "old.raf", 0.0, 0, &old).unwrap();
+        // Lenses only described, or not known at all, are not gear
+        let described = json!({ "LensInfo": "18-55mm f/2.8-4", "FocalLength": "18 mm", "DateTimeOriginal": "2022:01:15 10:00:00" });
+        insert_metadata(&conn, "described.raf", 0.0, 0, &described).unwrap();
+        insert_metadata(&conn, "unknown.raf", 0.0, 0, &json!({ "FocalLength": "35 mm" })).unwrap();
+        normalize_lenses(&conn, &LensNames::default()).unwrap();
+
+        let today = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let insights = generate_gear_insights(&conn, "1", &[], &InsightsConfig::default(), today).unwrap();
+
+        let zoom = &insights.lenses[0];
+        assert!(zoom.zoom);
+        assert_eq!(zoom.range, (24.0, 70.0));
+        assert_eq!(zoom.clusters.iter().map(|c| (c.from, c.to, c.photos)).collect::<Vec<_>>(), vec![
+            (24.0, 26.0, 50),
+            (35.0, 35.0, 5),
+            (50.0, 50.0, 2),
+            (70.0, 70.0, 43),
+        ]);
+        // Only 24-26 mm of the 46 mm range is used by clusters of at least 5%.
+        assert!((zoom.unused_range_percent - 100.0 * 44.0 / 46.0).abs() < 1e-9);
+        assert_eq!(zoom.primes.iter().map(|p| p.focal_length).collect::<Vec<_>>(), vec![24.0, 70.0]);
+        assert_eq!(zoom.primes[1].cumulative_percent, 93.0);
+
+        assert_eq!(insights.lenses.len(), 2);
+        let prime = &insights.lenses[1];
+        assert!(!prime.zoom);
+        assert!(prime.primes.is_empty());
+
+        assert_eq!(insights.idle_lenses, vec![IdleLens {
+            lens: "XF56mmF1.2 R".to_string(),
+            last_used: "2022-01-15".to_string(),
+            photos: 1,
+        }]);
+    }
+}
@@ -0,0 +1,351 @@
+//! Lens identification and lens names.
+//!
+//! `LensModel` is empty on many older bodies and on adapted lenses. The lens
+//! of a photo is named after the first usable tag among `LensModel`,
+//! `LensID`, `Lens`, `LensInfo` and `LensType`. The photos of a camera
+//! without any of them share one description of the range they cover: the
+//! shortest and longest focal length and the widest and narrowest maximum
+//! aperture among them, e.g. "Unknown 18-55mm f/3.5-5.6". Such descriptions,
+//! like `LensInfo`, do not identify a lens and are kept out of the gear
+//! insights. Variant names of the same lens are merged by the `lens_aliases`
+//! of the configuration file; descriptions and the mapping, stored in the
+//! `lens_names` table, are rebuilt after every scan.
+
+use crate::cameras::CAMERA_SQL;
+use crate::errors::Result;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Name of the lens of a `metadata` row, after aliases.
+pub const LENS_SQL: &str =
+    "COALESCE((SELECT name FROM lens_names WHERE lens_names.lens = metadata.LensName), LensName)";
+
+/// Condition on a `metadata` row whose lens is named, rather than unknown or
+/// only described by `LensInfo` or its camera's focal length range.
+pub const IDENTIFIED_LENS_SQL: &str =
+    "(LensName IS NOT NULL AND COALESCE(LensSource, '') NOT IN ('LensInfo', 'FocalLength'))";
+
+/// Tag the lens name of a photo was taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum LensSource {
+    LensModel,
+    #[serde(rename = "LensID")]
+    LensId,
+    Lens,
+    LensInfo,
+    LensType,
+    /// Described from the focal lengths and maximum apertures of the
+    /// camera's photos without a lens tag
+    FocalLength,
+}
+
+/// Tags naming the lens, most specific first, then the description.
+const SOURCES: [LensSource; 6] = [
+    LensSource::LensModel,
+    LensSource::LensId,
+    LensSource::Lens,
+    LensSource::LensInfo,
+    LensSource::LensType,
+    LensSource::FocalLength,
+];
+
+impl LensSource {
+    /// Tag name, as stored in the `LensSource` column.
+    pub fn tag(self) -> &'static str {
+        match self {
+            LensSource::LensModel => "LensModel",
+            LensSource::LensId => "LensID",
+            LensSource::Lens => "Lens",
+            LensSource::LensInfo => "LensInfo",
+            LensSource::LensType => "LensType",
+            LensSource::FocalLength => "FocalLength",
+        }
+    }
+
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        SOURCES.into_iter().find(|source| source.tag() == tag)
+    }
+}
+
+/// Trims and collapses runs of whitespace.
+fn clean(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Rejects the placeholders cameras and ExifTool write for a missing lens:
+/// "", "----", "None", "n/a", "Unknown (0)", "0mm f/0".
+fn usable_name(value: &Value) -> Option<String> {
+    let name = match value {
+        Value::String(text) => clean(text),
+        Value::Number(number) => number.to_string(),
+        _ => return None,
+    };
+    let lower = name.to_lowercase();
+    let placeholder = matches!(lower.as_str(), "none" | "n/a")
+        || lower.starts_with("unknown")
+        || !name.chars().any(|c| c.is_ascii_alphanumeric() && c != '0')
+        || lower.starts_with("0mm")
+        || lower.starts_with("0.0 mm");
+    (!placeholder).then_some(name)
+}
+
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:.1}", value)
+    }
+}
+
+/// Names the lens of one ExifTool JSON object. `None` when no lens tag holds
+/// a usable value; [`normalize_lenses`] then describes it.
+pub fn identify_lens(metadata: &Value) -> Option<(String, LensSource)> {
+    SOURCES
+        .iter()
+        .filter(|source| **source != LensSource::FocalLength)
+        .find_map(|&source| usable_name(&metadata[source.tag()]).map(|name| (name, source)))
+}
+
+/// Describes a lens by the focal lengths and maximum apertures it was used
+/// at: "Unknown 18-55mm f/3.5-5.6", "Unknown 50mm f/1.8" or "Unknown 35mm".
+fn describe_lens(focal_lengths: (f64, f64), apertures: Option<(f64, f64)>) -> String {
+    let range = |(min, max): (f64, f64)| {
+        if min == max {
+            format_number(min)
+        } else {
+            format!("{}-{}", format_number(min), format_number(max))
+        }
+    };
+    let focal = range((focal_lengths.0.round(), focal_lengths.1.round()));
+    match apertures {
+        Some(apertures) => format!("Unknown {}mm f/{}", focal, range(apertures)),
+        None => format!("Unknown {}mm", focal),
+    }
+}
+
+/// User aliases merging variant names of the same lens.
+#[derive(Debug, Clone, Default)]
+pub struct LensNames {
+    aliases: BTreeMap<String, String>,
+}
+
+impl LensNames {
+    pub fn new(aliases: &BTreeMap<String, String>) -> Self {
+        let aliases = aliases.iter().map(|(from, to)| (clean(from), clean(to))).collect();
+        Self { aliases }
+    }
+
+    pub fn canonical(&self, lens: &str) -> String {
+        let lens = clean(lens);
+        self.aliases.get(&lens).cloned().unwrap_or(lens)
+    }
+}
+
+/// Describes the lens of the photos without a lens tag, per camera, then
+/// rebuilds `lens_names` for every lens name in the catalog and returns the
+/// number of distinct lenses.
+pub fn normalize_lenses(conn: &Connection, names: &LensNames) -> Result<usize> {
+    let transaction = conn.unchecked_transaction()?;
+    transaction.execute("UPDATE metadata SET LensName = NULL, LensSource = NULL WHERE LensSource = 'FocalLength'", [])?;
+    let mut stmt = transaction.prepare(&format!(
+        "SELECT {}, MIN(FocalLength), MAX(FocalLength), MIN(MaxApertureValue), MAX(MaxApertureValue) FROM metadata
+         WHERE LensName IS NULL AND FocalLength > 0
+         GROUP BY 1",
+        CAMERA_SQL
+    ))?;
+    let ranges = stmt
+        .query_map([], |row| {
+            let apertures = row.get::<_, Option<f64>>(3)?.zip(row.get::<_, Option<f64>>(4)?);
+            Ok((row.get::<_, Option<String>>(0)?, (row.get::<_, f64>(1)?, row.get::<_, f64>(2)?), apertures))
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    drop(stmt);
+    for (camera, focal_lengths, apertures) in ranges {
+        transaction.execute(
+            &format!(
+                "UPDATE metadata SET LensName = ?, LensSource = ? WHERE LensName IS NULL AND {} IS ?",
+                CAMERA_SQL
+            ),
+            rusqlite::params![describe_lens(focal_lengths, apertures), LensSource::FocalLength.tag(), camera],
+        )?;
+    }
+
+    let mut stmt = transaction.prepare("SELECT DISTINCT LensName FROM metadata WHERE LensName IS NOT NULL")?;
+    let lenses = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    drop(stmt);
+
+    let mut canonical = BTreeSet::new();
+    transaction.execute("DELETE FROM lens_names", [])?;
+    for lens in &lenses {
+        let name = names.canonical(lens);
+        transaction.execute("INSERT INTO lens_names (lens, name) VALUES (?, ?)", rusqlite::params![lens, name])?;
+        canonical.insert(name);
+    }
+    transaction.commit()?;
+    Ok(canonical.len())
+}
+
+/// A lens and the names its photos carry.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LensEntry {
+    /// Name after aliases
+    pub lens: String,
+    pub photos: i32,
+    /// Names merged into `lens`, with the tag each was taken from
+    pub names: Vec<(String, Option<LensSource>)>,
+}
+
+/// Photos of one camera whose lens is only described, or not known at all.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UnidentifiedLens {
+    /// Description from `LensInfo` or the camera's focal length range; `None`
+    /// when the photos carry neither
+    pub lens: Option<String>,
+    pub source: Option<LensSource>,
+    pub camera: Option<String>,
+    pub photos: i32,
+    /// One of the photos, to look the lens up
+    pub example: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LensReport {
+    /// Lenses by photo count, most used first
+    pub lenses: Vec<LensEntry>,
+    /// Unidentified lenses by photo count, most used first
+    pub unidentified: Vec<UnidentifiedLens>,
+}
+
+/// Lists the lenses of the photos matching `condition`, and the photos whose
+/// lens could not be identified, per camera.
+pub fn generate_lens_report(
+    connection: &Connection,
+    condition: &str,
+    params: &[SqlValue],
+) -> Result<LensReport> {
+    let mut stmt = connection.prepare(&format!(
+        "SELECT {}, LensName, LensSource, COUNT(*) FROM metadata
+         WHERE {} AND LensName IS NOT NULL
+         GROUP BY 1, 2, 3",
+        LENS_SQL, condition
+    ))?;
+    let mut lenses: BTreeMap<String, LensEntry> = BTreeMap::new();
+    for row in stmt.query_map(params_from_iter(params), |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<String>>(2)?, row.get::<_, i32>(3)?))
+    })? {
+        let (lens, name, source, photos) = row?;
+        let entry = lenses
+            .entry(lens.clone())
+            .or_insert_with(|| LensEntry { lens, photos: 0, names: Vec::new() });
+        entry.photos += photos;
+        entry.names.push((name, source.as_deref().and_then(LensSource::from_tag)));
+    }
+
+    let mut stmt = connection.prepare(&format!(
+        "SELECT LensName, LensSource, {}, COUNT(*), MIN(source_file) FROM metadata
+         WHERE {} AND NOT {}
+         GROUP BY 1, 2, 3",
+        CAMERA_SQL, condition, IDENTIFIED_LENS_SQL
+    ))?;
+    let mut unidentified = stmt
+        .query_map(params_from_iter(params), |row| {
+            Ok(UnidentifiedLens {
+                lens: row.get(0)?,
+                source: row.get::<_, Option<String>>(1)?.as_deref().and_then(LensSource::from_tag),
+                camera: row.get(2)?,
+                photos: row.get(3)?,
+                example: row.get(4)?,
+            })
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let mut lenses: Vec<LensEntry> = lenses.into_values().collect();
+    for entry in &mut lenses {
+        entry.names.sort();
+    }
+    lenses.sort_by(|a, b| b.photos.cmp(&a.photos).then_with(|| a.lens.cmp(&b.lens)));
+    unidentified.sort_by(|a, b| b.photos.cmp(&a.photos).then_with(|| a.example.cmp(&b.example)));
+    Ok(LensReport { lenses, unidentified })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::insert_metadata;
+    use crate::migrations::run_migrations;
+    use serde_json::json;
+
+    #[test]
+    fn test_identify_lens_fallbacks() {
+        let identify = |metadata: Value| identify_lens(&metadata);
+        assert_eq!(
+            identify(json!({ "LensModel": "XF56mmF1.2 R ", "LensID": "Other" })),
+            Some(("XF56mmF1.2 R".to_string(), LensSource::LensModel))
+        );
+        assert_eq!(
+            identify(json!({ "LensModel": "----", "LensID": "AF-S Nikkor 50mm f/1.8G" })),
+            Some(("AF-S Nikkor 50mm f/1.8G".to_string(), LensSource::LensId))
+        );
+        assert_eq!(
+            identify(json!({ "LensID": "Unknown (00 40 2D 88)", "LensInfo": "18-55mm f/3.5-5.6" })),
+            Some(("18-55mm f/3.5-5.6".to_string(), LensSource::LensInfo))
+        );
+        assert_eq!(
+            identify(json!({ "LensInfo": "0mm f/0", "LensType": "Olympus M.Zuiko Digital 25mm F1.8" })),
+            Some(("Olympus M.Zuiko Digital 25mm F1.8".to_string(), LensSource::LensType))
+        );
+        // Without a lens tag, the lens is described per camera by normalize_lenses
+        assert_eq!(identify(json!({ "LensType": "None", "FocalLength": "50.0 mm", "MaxApertureValue": 1.4 })), None);
+
+        assert_eq!(describe_lens((16.0, 55.0), Some((2.8, 4.0))), "Unknown 16-55mm f/2.8-4");
+        assert_eq!(describe_lens((49.6, 50.2), Some((1.4, 1.4))), "Unknown 50mm f/1.4");
+        assert_eq!(describe_lens((35.0, 35.0), None), "Unknown 35mm");
+    }
+
+    #[test]
+    fn test_lens_report_with_aliases() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let files = [
+            ("a.nef", json!({ "Model": "NIKON D700", "LensModel": "AF-S Nikkor 50mm f/1.8G" })),
+            ("b.nef", json!({ "Model": "NIKON D700", "LensID": "AF-S Nikkor 50mm f/1.8G" })),
+            ("c.nef", json!({ "Model": "NIKON D700", "LensID": "AF-S NIKKOR 50mm F1.8 G" })),
+            ("d.raf", json!({ "Model": "X-T5", "FocalLength": "16 mm", "MaxApertureValue": 2.8 })),
+            ("e.raf", json!({ "Model": "X-T5", "FocalLength": "55 mm", "MaxApertureValue": 4.0 })),
+            ("f.raf", json!({ "Model": "X-T5" })),
+            ("g.jpg", json!({})),
+        ];
+        for (file, metadata) in &files {
+            insert_metadata(&conn, file, 1234567890.0, 0, metadata).unwrap();
+        }
+        let aliases = BTreeMap::from([("AF-S NIKKOR 50mm F1.8 G".to_string(), "AF-S Nikkor 50mm f/1.8G".to_string())]);
+        assert_eq!(normalize_lenses(&conn, &LensNames::new(&aliases)).unwrap(), 2);
+        insert_metadata(&conn, "h.raf", 1234567890.0, 0, &json!({ "Model": "X-T5", "FocalLength": "35 mm" })).unwrap();
+        assert_eq!(normalize_lenses(&conn, &LensNames::new(&aliases)).unwrap(), 2);
+
+        let report = generate_lens_report(&conn, "1", &[]).unwrap();
+        assert_eq!(report.lenses[1].lens, "AF-S Nikkor 50mm f/1.8G");
+        assert_eq!(report.lenses[1].photos, 3);
+        assert_eq!(report.lenses[1].names, vec![
+            ("AF-S NIKKOR 50mm F1.8 G".to_string(), Some(LensSource::LensId)),
+            ("AF-S Nikkor 50mm f/1.8G".to_string(), Some(LensSource::LensModel)),
+            ("AF-S Nikkor 50mm f/1.8G".to_string(), Some(LensSource::LensId)),
+        ]);
+        assert_eq!(report.unidentified, vec![
+            // One description for the camera's range, not one per focal length
+            UnidentifiedLens {
+                lens: Some("Unknown 16-55mm f/2.8-4".to_string()),
+                source: Some(LensSource::FocalLength),
+                camera: Some("X-T5".to_string()),
+                photos: 4,
+                example: "d.raf".to_string(),
+            },
+            UnidentifiedLens { lens: None, source: None, camera: None, photos: 1, example: "g.jpg".to_string() },
+        ]);
+    }
+}
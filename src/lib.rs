@@ -1,8 +1,27 @@
+pub mod bodies;
+pub mod buckets;
+pub mod cameras;
 pub mod config;
+pub mod crop;
 pub mod database;
+pub mod distribution;
 pub mod errors;
 pub mod exiftool;
+pub mod export;
+pub mod extractor;
 pub mod files;
+pub mod filter;
+pub mod geocode;
+pub mod insights;
+pub mod lenses;
+pub mod migrations;
+pub mod native;
+pub mod pairs;
+pub mod prune;
+pub mod reindex;
+pub mod sessions;
 pub mod statistics;
+pub mod summary;
+pub mod timeseries;
 pub mod utils;
 pub mod worker;
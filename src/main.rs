@@ -1,28 +1,591 @@
-use photo_statistics::config::{Cli, Config};
-use photo_statistics::database::create_tables_if_needed;
+use photo_statistics::bodies::{BodyConfig, BodyUsage};
+use photo_statistics::buckets::BucketConfig;
+use photo_statistics::config::{Cli, Command, Config, FileConfig, SessionsCommand};
+use photo_statistics::database::open_database;
+use photo_statistics::distribution::Distribution;
+use photo_statistics::exiftool::shutdown_pool;
+use photo_statistics::export::{ExportFormat, TrackGrouping};
 use photo_statistics::files::scan_directory;
-use photo_statistics::statistics::generate_statistics;
-use photo_statistics::worker::process_files_in_parallel;
+use photo_statistics::prune::{prune_missing_files, PruneReport};
+use photo_statistics::reindex::reindex_catalog;
+use photo_statistics::sessions::{detect_sessions, load_sessions, session_trend, SessionConfig};
+use photo_statistics::filter::StatisticsFilter;
+use photo_statistics::insights::{GearInsights, InsightsConfig};
+use photo_statistics::lenses::{LensReport, LensSource};
+use photo_statistics::timeseries::{Calendar, Heatmap};
+use photo_statistics::statistics::{
+    generate_filtered_body_statistics, generate_filtered_gear_insights, generate_filtered_lens_report, generate_filtered_photo_map, generate_filtered_statistics, generate_filtered_summary, generate_pivot, Dimension, PivotPreset, PivotTable,
+    Statistics,
+};
+use photo_statistics::summary::{NumericSummary, SummaryGroup, SummaryStatistics};
+use photo_statistics::utils::format_exposure_time;
+use photo_statistics::worker::{finish_scan, process_files_in_parallel, FinishReport};
 use anyhow::{Context, Result};
-use clap::Parser;
-use rusqlite::Connection;
+use chrono::{Datelike, NaiveDate};
+use clap::{Parser, ValueEnum};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::ExitCode;
 use std::time::Instant;
 
-fn main() -> Result<()> {
+/// Exit code used when the scan finished but some files could not be processed.
+const EXIT_PARTIAL_FAILURE: u8 = 2;
+
+fn main() -> Result<ExitCode> {
     env_logger::init();
 
+    let mut args = Cli::parse();
+    let mut file_config = FileConfig::load_optional(args.config.as_deref())
+        .context("Failed to load configuration")?;
+
+    match args.command.take() {
+        Some(Command::Prune { directory, dry_run }) => run_prune(&args.database, &directory, dry_run),
+        Some(Command::Reindex) => run_reindex(&args.database, &file_config),
+        Some(Command::Stats { filter, calendar_year }) => {
+            file_config.output.calendar_year = calendar_year.or(file_config.output.calendar_year);
+            run_stats(&args.database, &filter, &file_config)
+        }
+        Some(Command::Pivot { preset, rows, columns, filter }) => {
+            let tables = match (preset, rows.zip(columns)) {
+                (_, Some(dimensions)) => vec![dimensions],
+                (Some(preset), None) => vec![preset.dimensions()],
+                (None, None) => PivotPreset::value_variants().iter().map(|preset| preset.dimensions()).collect(),
+            };
+            run_pivot(&args.database, &tables, &filter, &file_config.buckets)
+        }
+        Some(Command::Summary { group_by, filter }) => {
+            run_summary(&args.database, group_by, &filter, &file_config.crop_factors)
+        }
+        Some(Command::Insights { coverage, idle_months, filter }) => {
+            let mut insights = file_config.insights;
+            insights.coverage_percent = coverage.unwrap_or(insights.coverage_percent);
+            insights.idle_months = idle_months.unwrap_or(insights.idle_months);
+            run_insights(&args.database, &filter, &insights)
+        }
+        Some(Command::Lenses { unidentified, filter }) => run_lenses(&args.database, unidentified, &filter),
+        Some(Command::Bodies { rated_shutter_life, filter }) => {
+            let mut bodies = file_config.bodies;
+            bodies.rated_shutter_life = rated_shutter_life.unwrap_or(bodies.rated_shutter_life);
+            run_bodies(&args.database, &filter, &bodies)
+        }
+        Some(Command::Export { output, format, tracks, filter }) => run_export(&args.database, &output, format, tracks, &filter),
+        Some(Command::Sessions { command }) => match command {
+            SessionsCommand::Detect { gap_minutes, split_distance_km } => {
+                let mut sessions = file_config.sessions;
+                sessions.gap_minutes = gap_minutes.unwrap_or(sessions.gap_minutes);
+                sessions.split_distance_km = split_distance_km.or(sessions.split_distance_km);
+                run_detect_sessions(&args.database, &sessions)
+            }
+            SessionsCommand::List => run_list_sessions(&args.database),
+            SessionsCommand::Show { id } => {
+                let filter = StatisticsFilter { session: Some(id), ..StatisticsFilter::default() };
+                run_stats(&args.database, &filter, &file_config)
+            }
+            SessionsCommand::Trend => run_session_trend(&args.database),
+        },
+        None => run_scan(Config::new(args, file_config)),
+    }
+}
+
+fn print_prune_report(report: &PruneReport, dry_run: bool) {
+    if dry_run {
+        println!("🧹 {} cataloged files no longer exist:", report.missing.len());
+        for file in &report.missing {
+            println!("  {}", file);
+        }
+    } else {
+        println!("🧹 Removed {} catalog entries for missing files.", report.removed);
+    }
+}
+
+fn run_prune(database_path: &Path, directory: &str, dry_run: bool) -> Result<ExitCode> {
+    println!("📂 Pruning directory: {}", directory);
+
+    let conn = open_database(database_path)
+        .context("Failed to open database")?;
+    let report = prune_missing_files(&conn, directory, dry_run)
+        .context("Failed to prune catalog")?;
+    print_prune_report(&report, dry_run);
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Reports the camera and lens names and the places the catalog was mapped to.
+fn print_catalog_names(finished: &FinishReport) {
+    if finished.cameras > 0 {
+        println!("📷 Mapped camera makes and models to {} camera names.", finished.cameras);
+    }
+    if finished.lenses > 0 {
+        println!("🔭 Mapped lens names to {} lenses.", finished.lenses);
+    }
+    if finished.placed > 0 {
+        println!("🌍 Resolved {} photo positions to places.", finished.placed);
+    }
+}
+
+fn run_reindex(database_path: &Path, file_config: &FileConfig) -> Result<ExitCode> {
     let start_time = Instant::now();
-    let args = Cli::parse();
-    
-    println!("📂 Processing directory: {}", args.directory);
 
-    let config = Config::from(args);
+    let conn = open_database(database_path)
+        .context("Failed to open database")?;
+    let report = reindex_catalog(&conn, &file_config.extra_tags)
+        .context("Failed to reindex catalog")?;
+
+    println!("🔁 Rebuilt metadata for {} files.", report.reindexed);
+    if !report.missing_raw.is_empty() {
+        println!(
+            "⚠️ {} files have no stored raw metadata. Rescan them with --force to include them.",
+            report.missing_raw.len()
+        );
+    }
+    let finished = finish_scan(&conn, &Config::from_file(file_config.clone()))
+        .context("Failed to update pairs, names and places")?;
+    print_catalog_names(&finished);
+    println!("✅ Completed in {:.2?}", start_time.elapsed());
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_statistics(stats: &Statistics) {
+    let print_distribution = |title: &str, distribution: &Distribution| {
+        println!("📊 {}:", title);
+        for entry in &distribution.entries {
+            println!("  {}: {} ({:.1}%)", entry.label, entry.count, entry.percent);
+        }
+        println!();
+    };
+
+    print_distribution("Photos Per Year", &stats.photos_per_year);
+    print_distribution("Camera Models", &stats.camera_models);
+    print_distribution("Lens Models", &stats.lens_models);
+    print_distribution("ISO", &stats.iso);
+    print_distribution("Shutter Speed", &stats.shutter_speed);
+    print_distribution("Aperture", &stats.aperture);
+    print_distribution("Focal Length", &stats.focal_length);
+    print_distribution("White Balance", &stats.white_balance);
+    print_distribution("Flash Usage", &stats.flash_usage);
+    for (tag, distribution) in &stats.extra {
+        print_distribution(tag, distribution);
+    }
+    print_distribution("ISO (full stops)", &stats.iso_stops);
+    print_distribution("Shutter Speed (full stops)", &stats.shutter_stops);
+    print_distribution("Aperture (stops)", &stats.aperture_stops);
+    print_distribution("Focal Length (ranges)", &stats.focal_ranges);
+    print_distribution("Focal Length (35mm equivalent)", &stats.equivalent_focal_length);
+    if !stats.crop_factors.is_empty() {
+        println!("📊 Crop Factors:");
+        for crop in &stats.crop_factors {
+            println!("  {}: {:.2} ({:?}, {} photos)", crop.model, crop.factor, crop.source, crop.photos);
+        }
+        println!();
+    }
+
+    print_distribution("Countries", &stats.countries);
+    print_distribution("Cities", &stats.cities);
+
+    print_distribution("Photos Per Month", &stats.time_series.photos_per_month);
+    print_distribution("Photos Per Week", &stats.time_series.photos_per_week);
+    print_heatmap(&stats.time_series.weekday_hour);
+    if let Some(calendar) = &stats.time_series.calendar {
+        print_calendar(calendar);
+    }
+    print_summary("Summary", &stats.summary);
+}
+
+fn print_summary_row(label: &str, summary: Option<&NumericSummary>, format: fn(f64) -> String) {
+    match summary {
+        Some(s) => println!(
+            "  {:<19} {:>6} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            label,
+            s.count,
+            format(s.min),
+            format(s.p10),
+            format(s.median),
+            format(s.mean),
+            format(s.p90),
+            format(s.max),
+            format(s.mode)
+        ),
+        None => println!("  {:<19} {:>6}", label, 0),
+    }
+}
+
+fn print_summary(title: &str, summary: &SummaryStatistics) {
+    println!("📊 {}:", title);
+    println!(
+        "  {:<19} {:>6} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+        "", "count", "min", "p10", "median", "mean", "p90", "max", "mode"
+    );
+    print_summary_row("ISO", summary.iso.as_ref(), |value| format!("{:.0}", value));
+    print_summary_row("Shutter Speed", summary.shutter_speed.as_ref(), format_exposure_time);
+    print_summary_row("Aperture", summary.aperture.as_ref(), |value| format!("f/{:.1}", value));
+    print_summary_row("Focal Length", summary.focal_length.as_ref(), |value| format!("{:.0} mm", value));
+    print_summary_row("Focal Length (35mm)", summary.focal_length_35mm.as_ref(), |value| format!("{:.0} mm", value));
+    println!();
+}
+
+fn print_heatmap(heatmap: &Heatmap) {
+    println!("📊 Day of Week by Hour:");
+    println!("      {}", heatmap.columns.iter().map(|hour| format!("{:>4}", hour)).collect::<String>());
+    for (day, counts) in heatmap.rows.iter().zip(&heatmap.counts) {
+        println!("  {} {}", day, counts.iter().map(|count| format!("{:>4}", count)).collect::<String>());
+    }
+    println!();
+}
+
+/// Prints one column per week and one row per weekday, shaded by photo count.
+fn print_calendar(calendar: &Calendar) {
+    const SHADES: [char; 5] = ['·', '░', '▒', '▓', '█'];
+
+    let max = calendar.days.iter().map(|day| day.count).max().unwrap_or(0);
+    let photos: i32 = calendar.days.iter().map(|day| day.count).sum();
+    let active = calendar.days.iter().filter(|day| day.count > 0).count();
+    println!("📅 {}: {} photos on {} days", calendar.year, photos, active);
+
+    // January 1st starts in the row of its weekday.
+    let first_weekday = NaiveDate::from_ymd_opt(calendar.year, 1, 1)
+        .map_or(0, |date| date.weekday().num_days_from_monday() as usize);
+    let mut rows = vec![String::new(); 7];
+    for row in rows.iter_mut().take(first_weekday) {
+        row.push(' ');
+    }
+    for (index, day) in calendar.days.iter().enumerate() {
+        let shade = match day.count {
+            0 => 0,
+            _ if max <= 1 => 4,
+            count => 1 + (3 * (count - 1) / (max - 1)) as usize,
+        };
+        rows[(first_weekday + index) % 7].push(SHADES[shade]);
+    }
+    for (label, row) in ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"].iter().zip(rows) {
+        println!("  {} {}", label, row);
+    }
+    println!();
+}
+
+fn run_stats(database_path: &Path, filter: &StatisticsFilter, file_config: &FileConfig) -> Result<ExitCode> {
+    let conn = open_database(database_path)
+        .context("Failed to open database")?;
+    let stats = generate_filtered_statistics(
+        &conn,
+        filter,
+        &file_config.buckets,
+        &file_config.output,
+        &file_config.crop_factors,
+    )
+        .context("Failed to generate statistics")?;
+    print_statistics(&stats);
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_summary(
+    database_path: &Path,
+    group_by: Option<SummaryGroup>,
+    filter: &StatisticsFilter,
+    crop_factors: &BTreeMap<String, f64>,
+) -> Result<ExitCode> {
+    let conn = open_database(database_path)
+        .context("Failed to open database")?;
+    let groups = generate_filtered_summary(&conn, filter, group_by, crop_factors)
+        .context("Failed to generate summary statistics")?;
+    for group in &groups {
+        print_summary(&format!("{} ({} photos)", group.group, group.photos), &group.summary);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_gear_insights(insights: &GearInsights, config: &InsightsConfig) {
+    for lens in &insights.lenses {
+        println!("📊 {} ({} photos):", lens.lens, lens.photos);
+        for cluster in &lens.clusters {
+            let span = if cluster.from == cluster.to {
+                format!("{} mm", cluster.from)
+            } else {
+                format!("{}-{} mm", cluster.from, cluster.to)
+            };
+            println!("  {}: {} ({:.1}%), mostly at {} mm", span, cluster.photos, cluster.percent, cluster.peak);
+        }
+        if lens.zoom {
+            println!("  Unused zoom range: {:.0}% of {}-{} mm", lens.unused_range_percent, lens.range.0, lens.range.1);
+            let primes: Vec<String> = lens.primes.iter().map(|prime| format!("{} mm", prime.focal_length)).collect();
+            if let Some(last) = lens.primes.last() {
+                println!(
+                    "  Primes covering {:.0}% of shots (target {:.0}%): {}",
+                    last.cumulative_percent,
+                    config.coverage_percent,
+                    primes.join(", ")
+                );
+            }
+        }
+        println!();
+    }
+
+    println!("📊 Lenses Not Used in {} Months:", config.idle_months);
+    if insights.idle_lenses.is_empty() {
+        println!("  None");
+    }
+    for lens in &insights.idle_lenses {
+        println!("  {}: last used {} ({} photos)", lens.lens, lens.last_used, lens.photos);
+    }
+    println!();
+}
+
+fn run_insights(database_path: &Path, filter: &StatisticsFilter, config: &InsightsConfig) -> Result<ExitCode> {
+    config.validate().map_err(anyhow::Error::msg)?;
+    let conn = open_database(database_path)
+        .context("Failed to open database")?;
+    let insights = generate_filtered_gear_insights(&conn, filter, config, chrono::Local::now().date_naive())
+        .context("Failed to analyze lens usage")?;
+    print_gear_insights(&insights, config);
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_lens_report(report: &LensReport, unidentified_only: bool) {
+    if !unidentified_only {
+        println!("📊 Lenses:");
+        if report.lenses.is_empty() {
+            println!("  None");
+        }
+        for lens in &report.lenses {
+            println!("  {}: {} photos", lens.lens, lens.photos);
+            for (name, source) in &lens.names {
+                let tag = source.map_or("?", LensSource::tag);
+                if name != &lens.lens || *source != Some(LensSource::LensModel) {
+                    println!("      {} (from {})", name, tag);
+                }
+            }
+        }
+        println!();
+    }
+
+    println!("📊 Unidentified Lenses:");
+    if report.unidentified.is_empty() {
+        println!("  None");
+    }
+    for lens in &report.unidentified {
+        println!(
+            "  {} on {}: {} photos, e.g. {}",
+            lens.lens.as_deref().unwrap_or("N/A"),
+            lens.camera.as_deref().unwrap_or("N/A"),
+            lens.photos,
+            lens.example
+        );
+    }
+    if !report.unidentified.is_empty() {
+        println!("Map a described lens to its name with `lens_aliases` in the configuration file, or write LensModel into the files and rescan them with --force.");
+    }
+    println!();
+}
+
+fn run_lenses(database_path: &Path, unidentified_only: bool, filter: &StatisticsFilter) -> Result<ExitCode> {
+    let conn = open_database(database_path)
+        .context("Failed to open database")?;
+    let report = generate_filtered_lens_report(&conn, filter)
+        .context("Failed to list lenses")?;
+    print_lens_report(&report, unidentified_only);
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_bodies(bodies: &[BodyUsage]) {
+    println!("📊 Camera Bodies:");
+    if bodies.is_empty() {
+        println!("  None");
+    }
+    for body in bodies {
+        println!(
+            "  {} ({}): {} photos, {} → {}",
+            body.camera,
+            body.serial_number.as_deref().map_or_else(|| "no serial number".to_string(), |serial| format!("#{}", serial)),
+            body.photos,
+            body.first_used.as_deref().unwrap_or("N/A"),
+            body.last_used.as_deref().unwrap_or("N/A")
+        );
+        let estimated = if body.shutter_count_estimated { " (estimated from the cataloged photos)" } else { "" };
+        println!(
+            "      Shutter count: {}{}, {:.1}% of {}",
+            body.shutter_count, estimated, body.rated_life_percent, body.rated_shutter_life
+        );
+        if let Some(rate) = body.shots_per_day {
+            let projection = match &body.projected_end_of_life {
+                Some(date) => format!(", rated life reached around {}", date),
+                None => String::new(),
+            };
+            println!("      About {:.1} shots per day{}", rate, projection);
+        }
+        for point in &body.history {
+            println!(
+                "      {}: {}{} ({} photos)",
+                point.month,
+                if point.estimated { "~" } else { "" },
+                point.shutter_count,
+                point.photos
+            );
+        }
+    }
+    println!();
+}
+
+fn run_bodies(database_path: &Path, filter: &StatisticsFilter, config: &BodyConfig) -> Result<ExitCode> {
+    config.validate().map_err(anyhow::Error::msg)?;
+    let conn = open_database(database_path)
+        .context("Failed to open database")?;
+    let bodies = generate_filtered_body_statistics(&conn, filter, config)
+        .context("Failed to generate body statistics")?;
+    print_bodies(&bodies);
 
-    let conn = Connection::open(&config.database_path)
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_export(
+    database_path: &Path,
+    output: &Path,
+    format: Option<ExportFormat>,
+    tracks: Option<TrackGrouping>,
+    filter: &StatisticsFilter,
+) -> Result<ExitCode> {
+    let format = format
+        .or_else(|| ExportFormat::from_path(output))
+        .context("Cannot tell the export format from the file name; use --format geojson or --format kml")?;
+    let conn = open_database(database_path)
         .context("Failed to open database")?;
+    let map = generate_filtered_photo_map(&conn, filter, tracks)
+        .context("Failed to load geotagged photos")?;
+    std::fs::write(output, map.render(format)?)
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+
+    println!("🗺️ Exported {} photos and {} tracks to {}", map.photos.len(), map.tracks.len(), output.display());
+    if tracks == Some(TrackGrouping::Session) && map.tracks.is_empty() {
+        println!("No session tracks. Run `sessions detect` first.");
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
 
-    create_tables_if_needed(&conn)
-        .context("Failed to create database tables")?;
+fn run_detect_sessions(database_path: &Path, config: &SessionConfig) -> Result<ExitCode> {
+    config.validate().map_err(anyhow::Error::msg)?;
+    let conn = open_database(database_path)
+        .context("Failed to open database")?;
+    let sessions = detect_sessions(&conn, config)
+        .context("Failed to detect sessions")?;
+    let photos: i32 = sessions.iter().map(|session| session.photo_count).sum();
+    println!("🗂️  Detected {} sessions covering {} dated photos.", sessions.len(), photos);
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_list_sessions(database_path: &Path) -> Result<ExitCode> {
+    let conn = open_database(database_path)
+        .context("Failed to open database")?;
+    let sessions = load_sessions(&conn)
+        .context("Failed to load sessions")?;
+    if sessions.is_empty() {
+        println!("No sessions stored. Run `sessions detect` first.");
+    }
+    for session in &sessions {
+        println!(
+            "  #{} {} → {}: {} photos in {}",
+            session.id,
+            session.start,
+            // Only the time when the session ends on the day it started.
+            session.end.strip_prefix(&session.start[..11]).unwrap_or(&session.end),
+            session.photo_count,
+            session.directory.as_deref().unwrap_or("N/A")
+        );
+        if !session.cameras.is_empty() || !session.lenses.is_empty() {
+            println!("      {}", session.cameras.iter().chain(&session.lenses).cloned().collect::<Vec<_>>().join(", "));
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_session_trend(database_path: &Path) -> Result<ExitCode> {
+    let conn = open_database(database_path)
+        .context("Failed to open database")?;
+    let sessions = load_sessions(&conn)
+        .context("Failed to load sessions")?;
+    println!("📊 Photos Per Session:");
+    for month in session_trend(&sessions) {
+        println!("  {}: {} sessions, {:.1} photos per session", month.period, month.sessions, month.average_photos);
+    }
+    println!();
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn dimension_name(dimension: Dimension) -> String {
+    dimension
+        .to_possible_value()
+        .map_or_else(|| format!("{:?}", dimension), |value| value.get_name().to_string())
+}
+
+fn print_pivot(pivot: &PivotTable) {
+    println!("📊 {} × {}:", dimension_name(pivot.rows), dimension_name(pivot.columns));
+
+    let mut grid = vec![vec![0; pivot.column_labels.len()]; pivot.row_labels.len()];
+    for cell in &pivot.cells {
+        grid[cell.row][cell.column] = cell.count;
+    }
+
+    let label_width = pivot.row_labels.iter().map(|label| label.chars().count()).max().unwrap_or(0).max(5);
+    let widths: Vec<usize> = pivot
+        .column_labels
+        .iter()
+        .zip(&pivot.column_totals)
+        .map(|(label, total)| label.chars().count().max(total.to_string().len()))
+        .collect();
+    let total_width = pivot.total.to_string().len().max(5);
+
+    let mut header = format!("  {:label_width$}", "");
+    for (label, width) in pivot.column_labels.iter().zip(&widths) {
+        header.push_str(&format!("  {:>width$}", label));
+    }
+    println!("{}  {:>total_width$}", header, "Total");
+
+    for ((label, counts), total) in pivot.row_labels.iter().zip(&grid).zip(&pivot.row_totals) {
+        let mut line = format!("  {:label_width$}", label);
+        for (count, width) in counts.iter().zip(&widths) {
+            line.push_str(&format!("  {:>width$}", count));
+        }
+        println!("{}  {:>total_width$}", line, total);
+    }
+
+    let mut footer = format!("  {:label_width$}", "Total");
+    for (total, width) in pivot.column_totals.iter().zip(&widths) {
+        footer.push_str(&format!("  {:>width$}", total));
+    }
+    println!("{}  {:>total_width$}", footer, pivot.total);
+    println!();
+}
+
+fn run_pivot(
+    database_path: &Path,
+    tables: &[(Dimension, Dimension)],
+    filter: &StatisticsFilter,
+    buckets: &BucketConfig,
+) -> Result<ExitCode> {
+    let conn = open_database(database_path)
+        .context("Failed to open database")?;
+    for (rows, columns) in tables {
+        let pivot = generate_pivot(&conn, *rows, *columns, filter, buckets)
+            .context("Failed to generate cross-tabulation")?;
+        print_pivot(&pivot);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_scan(config: Config) -> Result<ExitCode> {
+    let start_time = Instant::now();
+
+    println!("📂 Processing directory: {}", config.directory);
+
+    let conn = open_database(&config.database_path)
+        .context("Failed to open database")?;
 
     println!("🔍 Scanning directory...");
     let files = scan_directory(&config.directory);
@@ -31,37 +594,56 @@ fn main() -> Result<()> {
 
     if files.is_empty() {
         println!("🚫 No valid images found.");
-        return Ok(());
+        if config.prune {
+            let report = prune_missing_files(&conn, &config.directory, false)
+                .context("Failed to prune catalog")?;
+            print_prune_report(&report, false);
+        }
+        return Ok(ExitCode::SUCCESS);
     }
 
+    if config.backend.effective(&config.extra_tags) != config.backend {
+        println!("⚠️ The native backend does not read extra_tags; every file is read with ExifTool.");
+    }
     println!("🚀 Processing metadata...");
-    process_files_in_parallel(files, &config)
-        .context("Failed to process files")?;
+    let result = process_files_in_parallel(files, &config);
+    shutdown_pool();
+    let summary = result.context("Failed to process files")?;
 
-    println!("📊 Generating statistics...");
-    println!("📊 Generating statistics...");
-    let stats = generate_statistics(&conn)
-        .context("Failed to generate statistics")?;
+    println!(
+        "🗂️ {} new, {} changed, {} unchanged",
+        summary.new, summary.changed, summary.unchanged
+    );
+    println!(
+        "🧾 Scan summary: {} succeeded, {} failed",
+        summary.succeeded, summary.failed
+    );
+    if summary.failed > 0 {
+        println!("⚠️ Failed files are listed in the scan_errors table.");
+    }
 
-    let print_map = |title: &str, map: &std::collections::HashMap<String, i32>| {
-        println!("📊 {}:", title);
-        for (key, count) in map {
-            println!("  {}: {}", key, count);
-        }
-        println!();
-    };
+    if config.prune {
+        let report = prune_missing_files(&conn, &config.directory, false)
+            .context("Failed to prune catalog")?;
+        print_prune_report(&report, false);
+    }
+
+    let finished = finish_scan(&conn, &config)
+        .context("Failed to update pairs, names and places")?;
+    if finished.pairs > 0 {
+        println!("🔗 {} RAW+JPEG pairs are counted as one shot each (use --count-files to count files).", finished.pairs);
+    }
+    print_catalog_names(&finished);
 
-    print_map("Photos Per Year", &stats.photos_per_year);
-    print_map("Camera Models", &stats.camera_models);
-    print_map("Lens Models", &stats.lens_models);
-    print_map("ISO", &stats.iso);
-    print_map("Shutter Speed", &stats.shutter_speed);
-    print_map("Aperture", &stats.aperture);
-    print_map("Focal Length", &stats.focal_length);
-    print_map("White Balance", &stats.white_balance);
-    print_map("Flash Usage", &stats.flash_usage);
+    println!("📊 Generating statistics...");
+    let stats = generate_filtered_statistics(&conn, &config.filter, &config.buckets, &config.output, &config.crop_factors)
+        .context("Failed to generate statistics")?;
+    print_statistics(&stats);
 
     println!("✅ Completed in {:.2?}", start_time.elapsed());
-    
-    Ok(())
+
+    if summary.failed > 0 {
+        return Ok(ExitCode::from(EXIT_PARTIAL_FAILURE));
+    }
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,550 @@
+//! Versioned schema migrations for the cache database.
+//!
+//! The schema version is tracked with SQLite's `PRAGMA user_version`. Pending
+//! migrations are applied in order, inside a single transaction, every time a
+//! connection is opened. Each migration is frozen once released: later schema
+//! changes are new entries at the end of [`MIGRATIONS`].
+
+use crate::errors::{AppError, Result};
+use chrono::NaiveDateTime;
+use rusqlite::Connection;
+
+struct Migration {
+    version: i64,
+    description: &'static str,
+    apply: fn(&Connection) -> Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create metadata and scan_errors tables",
+        apply: create_initial_schema,
+    },
+    Migration {
+        version: 2,
+        description: "Store numeric metadata in typed columns",
+        apply: migrate_metadata_to_typed_columns,
+    },
+    Migration {
+        version: 3,
+        description: "Keep the raw extractor JSON of every file",
+        apply: create_raw_metadata_table,
+    },
+    Migration {
+        version: 4,
+        description: "Store user-configured extra tags",
+        apply: create_metadata_tags_table,
+    },
+    Migration {
+        version: 5,
+        description: "Record the camera's UTC offset",
+        apply: add_utc_offset_column,
+    },
+    Migration {
+        version: 6,
+        description: "Store detected shooting sessions",
+        apply: create_sessions_tables,
+    },
+    Migration {
+        version: 7,
+        description: "Record RAW+JPEG pairs",
+        apply: create_raw_jpeg_pairs_table,
+    },
+    Migration {
+        version: 8,
+        description: "Store GPS positions and the places they resolve to",
+        apply: create_photo_places_table,
+    },
+    Migration {
+        version: 9,
+        description: "Record the camera make and canonical camera names",
+        apply: create_camera_names_table,
+    },
+    Migration {
+        version: 10,
+        description: "Identify lenses without LensModel and store lens aliases",
+        apply: create_lens_names_table,
+    },
+    Migration {
+        version: 11,
+        description: "Record body serial numbers and shutter counts",
+        apply: add_body_columns,
+    },
+    Migration {
+        version: 12,
+        description: "Track geocoded positions to resolve only new and moved photos",
+        apply: create_geocoded_positions_table,
+    },
+];
+
+/// Schema version written by this build.
+pub const SCHEMA_VERSION: i64 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+pub fn schema_version(conn: &Connection) -> Result<i64> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+/// Brings the database up to [`SCHEMA_VERSION`].
+///
+/// Fails with [`AppError::UnsupportedSchema`] when the database was written
+/// by a newer version of the application.
+pub fn run_migrations(conn: &Connection) -> Result<()> {
+    let current = schema_version(conn)?;
+    if current > SCHEMA_VERSION {
+        return Err(AppError::UnsupportedSchema {
+            found: current,
+            supported: SCHEMA_VERSION,
+        });
+    }
+
+    let pending: Vec<&Migration> = MIGRATIONS.iter().filter(|m| m.version > current).collect();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let transaction = conn.unchecked_transaction()?;
+    for migration in pending {
+        println!("🔄 Applying migration {}: {}", migration.version, migration.description);
+        (migration.apply)(&transaction)?;
+        transaction.pragma_update(None, "user_version", migration.version)?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+fn column_type(conn: &Connection, table: &str, column: &str) -> Result<Option<String>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let declared = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+        .filter_map(std::result::Result::ok)
+        .find(|(name, _)| name == column)
+        .map(|(_, kind)| kind.to_uppercase());
+    Ok(declared)
+}
+
+/// Adds a column to tables created by an older version of the schema.
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    if column_type(conn, table, column)?.is_none() {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+    Ok(())
+}
+
+/// Version 1: the original all-TEXT layout. Databases created before schema
+/// versioning existed may already hold these tables, with or without
+/// `file_size`, or even the typed layout of version 2.
+fn create_initial_schema(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS metadata (
+            source_file TEXT PRIMARY KEY,
+            mod_time REAL,
+            DateTimeOriginal TEXT,
+            Model TEXT,
+            LensModel TEXT,
+            ISO TEXT,
+            ExposureTime TEXT,
+            FNumber TEXT,
+            FocalLength TEXT,
+            Flash TEXT,
+            WhiteBalance TEXT,
+            ImageWidth TEXT,
+            ImageHeight TEXT,
+            FocalLengthIn35mmFormat TEXT,
+            file_size INTEGER
+        )",
+        [],
+    )?;
+    add_column_if_missing(conn, "metadata", "file_size", "INTEGER")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS scan_errors (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            source_file TEXT NOT NULL,
+            error_kind TEXT NOT NULL,
+            message TEXT NOT NULL,
+            occurred_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Version 2: rewrites the all-TEXT metadata table with "N/A" sentinels into
+/// typed columns, parsing every stored value.
+fn migrate_metadata_to_typed_columns(conn: &Connection) -> Result<()> {
+    const TEXT_COLUMNS: [&str; 12] = [
+        "DateTimeOriginal",
+        "Model",
+        "LensModel",
+        "ISO",
+        "ExposureTime",
+        "FNumber",
+        "FocalLength",
+        "Flash",
+        "WhiteBalance",
+        "ImageWidth",
+        "ImageHeight",
+        "FocalLengthIn35mmFormat",
+    ];
+
+    if column_type(conn, "metadata", "ISO")?.as_deref() != Some("TEXT") {
+        return Ok(());
+    }
+
+    conn.execute("ALTER TABLE metadata RENAME TO metadata_text", [])?;
+    conn.execute(
+        "CREATE TABLE metadata (
+            source_file TEXT PRIMARY KEY,
+            mod_time REAL,
+            DateTimeOriginal TEXT,
+            Model TEXT,
+            LensModel TEXT,
+            ISO INTEGER,
+            ExposureTime REAL,
+            ExposureTimeDisplay TEXT,
+            FNumber REAL,
+            FocalLength REAL,
+            Flash TEXT,
+            WhiteBalance TEXT,
+            ImageWidth INTEGER,
+            ImageHeight INTEGER,
+            FocalLengthIn35mmFormat REAL,
+            file_size INTEGER
+        )",
+        [],
+    )?;
+
+    let mut select = conn.prepare(&format!(
+        "SELECT source_file, mod_time, file_size, {} FROM metadata_text",
+        TEXT_COLUMNS.join(", ")
+    ))?;
+    let mut insert = conn.prepare(
+        "INSERT INTO metadata (
+            source_file, mod_time, DateTimeOriginal, Model, LensModel, ISO, ExposureTime,
+            ExposureTimeDisplay, FNumber, FocalLength, Flash, WhiteBalance, ImageWidth,
+            ImageHeight, FocalLengthIn35mmFormat, file_size
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )?;
+
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let mut values: [Option<String>; TEXT_COLUMNS.len()] = Default::default();
+        for (index, value) in values.iter_mut().enumerate() {
+            *value = row.get::<_, Option<String>>(index + 3)?.filter(|v| v != "N/A");
+        }
+        let [date, model, lens, iso, exposure, f_number, focal, flash, white_balance, width, height, focal_35mm] =
+            values;
+        let number = |value: &Option<String>| value.as_deref().and_then(v2_parse_number);
+        let exposure_time = exposure.as_deref().and_then(v2_parse_exposure_time);
+        insert.execute(rusqlite::params![
+            row.get::<_, String>(0)?,
+            row.get::<_, Option<f64>>(1)?,
+            date.as_deref().and_then(v2_format_date),
+            model.as_deref().and_then(v2_text),
+            lens.as_deref().and_then(v2_text),
+            number(&iso).map(|iso| iso.round() as i64),
+            exposure_time,
+            exposure.as_deref().and_then(v2_text),
+            number(&f_number),
+            number(&focal),
+            flash.as_deref().and_then(v2_text),
+            white_balance.as_deref().map(v2_normalize_white_balance),
+            number(&width).map(|w| w as i64),
+            number(&height).map(|h| h as i64),
+            number(&focal_35mm),
+            row.get::<_, Option<i64>>(2)?,
+        ])?;
+    }
+    drop(rows);
+
+    conn.execute("DROP TABLE metadata_text", [])?;
+    Ok(())
+}
+
+// The parsing of the scan as of version 2, copied here so that later changes
+// to `MetadataRecord::from_json` do not alter what this migration produces.
+
+fn v2_text(value: &str) -> Option<String> {
+    Some(value.trim().to_string()).filter(|t| !t.is_empty())
+}
+
+fn v2_parse_number(value: &str) -> Option<f64> {
+    let cleaned = value.trim().to_lowercase().replace("mm", "").replace(',', ".");
+    let cleaned = cleaned.strip_prefix("f/").unwrap_or(&cleaned);
+    cleaned
+        .split_whitespace()
+        .next()
+        .and_then(|token| token.parse::<f64>().ok())
+        .filter(|number| number.is_finite())
+}
+
+fn v2_parse_exposure_time(value: &str) -> Option<f64> {
+    let cleaned = value.trim().trim_end_matches('s').trim();
+    let seconds = match cleaned.split_once('/') {
+        Some((numerator, denominator)) => {
+            let numerator = numerator.trim().parse::<f64>().ok()?;
+            let denominator = denominator.trim().parse::<f64>().ok()?;
+            numerator / denominator
+        }
+        None => v2_parse_number(cleaned)?,
+    };
+    (seconds.is_finite() && seconds > 0.0).then_some(seconds)
+}
+
+fn v2_format_date(date: &str) -> Option<String> {
+    ["%Y:%m:%d %H:%M:%S", "%Y-%m-%d %H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date.trim(), format).ok())
+        .map(|parsed| parsed.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+fn v2_normalize_white_balance(white_balance: &str) -> String {
+    match white_balance.trim().to_lowercase().as_str() {
+        "auto" | "auto (ambience priority)" => "auto".to_string(),
+        "daylight" => "daylight".to_string(),
+        "cloudy" => "cloudy".to_string(),
+        "fluorescent" => "fluorescent".to_string(),
+        "tungsten" => "tungsten".to_string(),
+        "shade" => "shade".to_string(),
+        _ => "manual".to_string(),
+    }
+}
+
+/// Version 3: the complete extractor output per file, zlib-compressed, so the
+/// normalized columns can be rebuilt without re-reading the photos.
+fn create_raw_metadata_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS raw_metadata (
+            source_file TEXT PRIMARY KEY,
+            json BLOB NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Version 4: values of the extra tags declared in the configuration file,
+/// one row per file and tag.
+fn create_metadata_tags_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS metadata_tags (
+            source_file TEXT NOT NULL,
+            tag TEXT NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY (source_file, tag)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Version 5: offset from UTC of `DateTimeOriginal`, in minutes. Existing rows
+/// are filled in by `reindex`.
+fn add_utc_offset_column(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "metadata", "UtcOffsetMinutes", "INTEGER")
+}
+
+/// Version 6: shooting sessions and the photos in each. Both tables are
+/// rebuilt as a whole by session detection.
+fn create_sessions_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            id INTEGER PRIMARY KEY,
+            start_time TEXT NOT NULL,
+            end_time TEXT NOT NULL,
+            photo_count INTEGER NOT NULL,
+            cameras TEXT NOT NULL,
+            lenses TEXT NOT NULL,
+            directory TEXT
+        );
+        CREATE TABLE IF NOT EXISTS session_photos (
+            source_file TEXT PRIMARY KEY,
+            session_id INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS session_photos_session ON session_photos (session_id);",
+    )?;
+    Ok(())
+}
+
+/// Version 7: sub-second capture time, and the JPEG files recorded together
+/// with a RAW file of the same shot. Existing rows are filled in by `reindex`.
+fn create_raw_jpeg_pairs_table(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "metadata", "SubSecTimeOriginal", "TEXT")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS raw_jpeg_pairs (
+            jpeg_file TEXT PRIMARY KEY,
+            raw_file TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Version 8: numeric GPS position, and the place each position resolves to.
+/// Existing rows are filled in by `reindex`; places are rebuilt as a whole
+/// after every scan.
+fn create_photo_places_table(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "metadata", "GPSLatitude", "REAL")?;
+    add_column_if_missing(conn, "metadata", "GPSLongitude", "REAL")?;
+    add_column_if_missing(conn, "metadata", "GPSAltitude", "REAL")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS photo_places (
+            source_file TEXT PRIMARY KEY,
+            city TEXT NOT NULL,
+            region TEXT,
+            country_code TEXT NOT NULL,
+            country TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Version 9: camera manufacturer, and the canonical name of every make and
+/// model combination. Existing rows are filled in by `reindex`; names are
+/// rebuilt as a whole after every scan.
+fn create_camera_names_table(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "metadata", "Make", "TEXT")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS camera_names (
+            make TEXT NOT NULL,
+            model TEXT NOT NULL,
+            camera TEXT NOT NULL,
+            PRIMARY KEY (make, model)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Version 10: lens name with the tag it was taken from, the maximum aperture
+/// that describes lenses without a lens tag, and the name each lens name is
+/// reported under. Existing rows keep their `LensModel` until `reindex` falls
+/// back to the other lens tags; names are rebuilt as a whole after every scan.
+fn create_lens_names_table(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "metadata", "LensName", "TEXT")?;
+    add_column_if_missing(conn, "metadata", "LensSource", "TEXT")?;
+    add_column_if_missing(conn, "metadata", "MaxApertureValue", "REAL")?;
+    if column_type(conn, "metadata", "LensModel")?.is_some() {
+        conn.execute(
+            "UPDATE metadata SET LensName = LensModel, LensSource = 'LensModel' WHERE LensModel IS NOT NULL",
+            [],
+        )?;
+    }
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS lens_names (
+            lens TEXT PRIMARY KEY,
+            name TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Version 11: serial number of the camera body and its shutter count when
+/// the photo was taken. Existing rows are filled in by `reindex`.
+fn add_body_columns(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "metadata", "SerialNumber", "TEXT")?;
+    add_column_if_missing(conn, "metadata", "ShutterCount", "INTEGER")
+}
+
+/// Version 12: the position each photo was last geocoded at, whether or not
+/// it resolved to a place, and the gazetteer used. Existing places are
+/// resolved again on the next scan, as no positions are recorded for them.
+fn create_geocoded_positions_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS geocoded_positions (
+            source_file TEXT PRIMARY KEY,
+            latitude REAL NOT NULL,
+            longitude REAL NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute("CREATE TABLE IF NOT EXISTS geocoding_state (gazetteer TEXT NOT NULL)", [])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i64 + 1);
+        }
+    }
+
+    #[test]
+    fn test_fresh_database_reaches_latest_version() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        assert_eq!(column_type(&conn, "metadata", "ISO").unwrap().as_deref(), Some("INTEGER"));
+
+        // Running again is a no-op.
+        run_migrations(&conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_newer_database_is_rejected() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+
+        let error = run_migrations(&conn).unwrap_err();
+        assert!(matches!(
+            error,
+            AppError::UnsupportedSchema { found, supported } if found == SCHEMA_VERSION + 1 && supported == SCHEMA_VERSION
+        ));
+    }
+
+    #[test]
+    fn test_migrate_unversioned_text_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE metadata (
+                source_file TEXT PRIMARY KEY, mod_time REAL, DateTimeOriginal TEXT, Model TEXT,
+                LensModel TEXT, ISO TEXT, ExposureTime TEXT, FNumber TEXT, FocalLength TEXT,
+                Flash TEXT, WhiteBalance TEXT, ImageWidth TEXT, ImageHeight TEXT,
+                FocalLengthIn35mmFormat TEXT
+            );
+            INSERT INTO metadata VALUES ('a.jpg', 1.0, '2023-06-12 15:30:00', 'Canon EOS R5',
+                'RF 24-70mm', '200', '1/250', '4.0', '50 mm', 'Off', 'auto', '8192', '5464', '50');
+            INSERT INTO metadata VALUES ('b.jpg', 2.0, 'N/A', 'N/A', 'N/A', '1000', 'N/A', 'N/A',
+                'N/A', 'N/A', 'manual', 'N/A', 'N/A', 'N/A');",
+        )
+        .unwrap();
+
+        run_migrations(&conn).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        assert_eq!(column_type(&conn, "metadata", "ISO").unwrap().as_deref(), Some("INTEGER"));
+        let (iso, exposure, display, focal): (i64, f64, String, f64) = conn
+            .query_row(
+                "SELECT ISO, ExposureTime, ExposureTimeDisplay, FocalLength FROM metadata WHERE source_file = 'a.jpg'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!((iso, exposure, display.as_str(), focal), (200, 0.004, "1/250", 50.0));
+
+        let (model, date): (Option<String>, Option<String>) = conn
+            .query_row(
+                "SELECT Model, DateTimeOriginal FROM metadata WHERE source_file = 'b.jpg'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((model, date), (None, None));
+
+        let ordered: Vec<i64> = conn
+            .prepare("SELECT ISO FROM metadata ORDER BY ISO")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .filter_map(std::result::Result::ok)
+            .collect();
+        assert_eq!(ordered, vec![200, 1000]);
+    }
+}
@@ -0,0 +1,821 @@
+//! Pure-Rust EXIF extraction.
+//!
+//! Reads the TIFF/EXIF IFDs embedded in JPEG files and in TIFF-based RAW formats
+//! (DNG, CR2, NEF, ARW) and renders the values the way ExifTool prints them, so
+//! the output can be stored without going through the `exiftool` binary.
+//! Files the parser does not understand are handed to a fallback extractor.
+
+use crate::errors::Result;
+use crate::exiftool::ExifToolExtractor;
+use crate::extractor::MetadataExtractor;
+use crate::utils::format_exposure_time;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+const TAG_IMAGE_WIDTH: u16 = 0x0100;
+const TAG_IMAGE_HEIGHT: u16 = 0x0101;
+const TAG_MAKE: u16 = 0x010f;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_SUB_IFDS: u16 = 0x014a;
+const TAG_EXPOSURE_TIME: u16 = 0x829a;
+const TAG_FNUMBER: u16 = 0x829d;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_ISO: u16 = 0x8827;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const TAG_SUB_SEC_TIME_ORIGINAL: u16 = 0x9291;
+const TAG_MAX_APERTURE: u16 = 0x9205;
+const TAG_FLASH: u16 = 0x9209;
+const TAG_FOCAL_LENGTH: u16 = 0x920a;
+const TAG_PIXEL_X_DIMENSION: u16 = 0xa002;
+const TAG_PIXEL_Y_DIMENSION: u16 = 0xa003;
+const TAG_WHITE_BALANCE: u16 = 0xa403;
+const TAG_FOCAL_LENGTH_35MM: u16 = 0xa405;
+const TAG_BODY_SERIAL_NUMBER: u16 = 0xa431;
+const TAG_LENS_SPECIFICATION: u16 = 0xa432;
+const TAG_LENS_MODEL: u16 = 0xa434;
+const TAG_GPS_IFD: u16 = 0x8825;
+
+// Tags of the GPS IFD, whose numbers overlap with those of the other IFDs.
+const TAG_GPS_LATITUDE_REF: u16 = 0x0001;
+const TAG_GPS_LATITUDE: u16 = 0x0002;
+const TAG_GPS_LONGITUDE_REF: u16 = 0x0003;
+const TAG_GPS_LONGITUDE: u16 = 0x0004;
+const TAG_GPS_ALTITUDE_REF: u16 = 0x0005;
+const TAG_GPS_ALTITUDE: u16 = 0x0006;
+
+const GPS_TAGS: [u16; 6] = [
+    TAG_GPS_LATITUDE_REF,
+    TAG_GPS_LATITUDE,
+    TAG_GPS_LONGITUDE_REF,
+    TAG_GPS_LONGITUDE,
+    TAG_GPS_ALTITUDE_REF,
+    TAG_GPS_ALTITUDE,
+];
+
+const WANTED_TAGS: [u16; 23] = [
+    TAG_IMAGE_WIDTH,
+    TAG_IMAGE_HEIGHT,
+    TAG_MAKE,
+    TAG_MODEL,
+    TAG_SUB_IFDS,
+    TAG_EXPOSURE_TIME,
+    TAG_FNUMBER,
+    TAG_EXIF_IFD,
+    TAG_ISO,
+    TAG_DATE_TIME_ORIGINAL,
+    TAG_OFFSET_TIME_ORIGINAL,
+    TAG_SUB_SEC_TIME_ORIGINAL,
+    TAG_MAX_APERTURE,
+    TAG_FLASH,
+    TAG_FOCAL_LENGTH,
+    TAG_PIXEL_X_DIMENSION,
+    TAG_PIXEL_Y_DIMENSION,
+    TAG_WHITE_BALANCE,
+    TAG_FOCAL_LENGTH_35MM,
+    TAG_BODY_SERIAL_NUMBER,
+    TAG_LENS_SPECIFICATION,
+    TAG_LENS_MODEL,
+    TAG_GPS_IFD,
+];
+
+/// Upper bounds that keep corrupt files from triggering huge reads.
+const MAX_IFD_ENTRIES: usize = 1024;
+const MAX_VALUE_BYTES: usize = 64 * 1024;
+const MAX_SUB_IFDS: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TagValue {
+    Ascii(String),
+    Unsigned(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    SignedRational(Vec<(i32, i32)>),
+    Other,
+}
+
+impl TagValue {
+    fn as_u32(&self) -> Option<u32> {
+        match self {
+            TagValue::Unsigned(values) => values.first().copied(),
+            _ => None,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            TagValue::Unsigned(values) => values.first().map(|v| f64::from(*v)),
+            TagValue::Rational(values) => values
+                .first()
+                .filter(|(_, d)| *d != 0)
+                .map(|(n, d)| f64::from(*n) / f64::from(*d)),
+            TagValue::SignedRational(values) => values
+                .first()
+                .filter(|(_, d)| *d != 0)
+                .map(|(n, d)| f64::from(*n) / f64::from(*d)),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            TagValue::Ascii(value) if !value.is_empty() => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IfdEntry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    value: [u8; 4],
+}
+
+#[derive(Debug, Default)]
+struct CollectedTags {
+    tags: HashMap<u16, TagValue>,
+    /// Tags of the GPS IFD
+    gps: HashMap<u16, TagValue>,
+    dimensions: Vec<(u32, u32)>,
+}
+
+struct TiffReader<R> {
+    reader: R,
+    base: u64,
+    order: ByteOrder,
+}
+
+impl<R: Read + Seek> TiffReader<R> {
+    /// Returns `None` when no TIFF header is found at `base`.
+    fn new(mut reader: R, base: u64) -> io::Result<Option<Self>> {
+        reader.seek(SeekFrom::Start(base))?;
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header)?;
+        let order = match header {
+            [b'I', b'I', 42, 0] => ByteOrder::LittleEndian,
+            [b'M', b'M', 0, 42] => ByteOrder::BigEndian,
+            _ => return Ok(None),
+        };
+        Ok(Some(Self { reader, base, order }))
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self.order {
+            ByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self.order {
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn read_bytes_at(&mut self, offset: u32, len: usize) -> io::Result<Vec<u8>> {
+        self.reader.seek(SeekFrom::Start(self.base + u64::from(offset)))?;
+        let mut buffer = vec![0u8; len];
+        self.reader.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn first_ifd_offset(&mut self) -> io::Result<u32> {
+        let bytes = self.read_bytes_at(4, 4)?;
+        Ok(self.u32(&bytes))
+    }
+
+    fn read_ifd(&mut self, offset: u32) -> io::Result<Vec<IfdEntry>> {
+        let count_bytes = self.read_bytes_at(offset, 2)?;
+        let count = usize::from(self.u16(&count_bytes));
+        if count > MAX_IFD_ENTRIES {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "IFD entry count out of range"));
+        }
+        let data = self.read_bytes_at(offset + 2, count * 12)?;
+        Ok(data
+            .chunks_exact(12)
+            .map(|raw| IfdEntry {
+                tag: self.u16(&raw[0..2]),
+                kind: self.u16(&raw[2..4]),
+                count: self.u32(&raw[4..8]),
+                value: [raw[8], raw[9], raw[10], raw[11]],
+            })
+            .collect())
+    }
+
+    fn read_value(&mut self, entry: &IfdEntry) -> io::Result<TagValue> {
+        let unit = match entry.kind {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 13 => 4,
+            5 | 10 => 8,
+            _ => return Ok(TagValue::Other),
+        };
+        let len = unit * entry.count as usize;
+        if len == 0 || len > MAX_VALUE_BYTES {
+            return Ok(TagValue::Other);
+        }
+        let bytes = if len <= 4 {
+            entry.value[..len].to_vec()
+        } else {
+            let offset = self.u32(&entry.value);
+            self.read_bytes_at(offset, len)?
+        };
+
+        Ok(match entry.kind {
+            2 => {
+                let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                TagValue::Ascii(String::from_utf8_lossy(&bytes[..end]).trim().to_string())
+            }
+            1 => TagValue::Unsigned(bytes.iter().map(|b| u32::from(*b)).collect()),
+            3 => TagValue::Unsigned(bytes.chunks_exact(2).map(|b| u32::from(self.u16(b))).collect()),
+            4 | 13 => TagValue::Unsigned(bytes.chunks_exact(4).map(|b| self.u32(b)).collect()),
+            5 => TagValue::Rational(
+                bytes
+                    .chunks_exact(8)
+                    .map(|b| (self.u32(&b[0..4]), self.u32(&b[4..8])))
+                    .collect(),
+            ),
+            10 => TagValue::SignedRational(
+                bytes
+                    .chunks_exact(8)
+                    .map(|b| (self.u32(&b[0..4]) as i32, self.u32(&b[4..8]) as i32))
+                    .collect(),
+            ),
+            _ => TagValue::Other,
+        })
+    }
+
+    /// Collects the tags of IFD0, the EXIF IFD, any SubIFDs and the GPS IFD.
+    /// Dimensions are kept per IFD because RAW files store a thumbnail next to
+    /// the full image.
+    fn collect_tags(&mut self) -> io::Result<CollectedTags> {
+        let mut collected = CollectedTags::default();
+
+        let ifd0_offset = self.first_ifd_offset()?;
+        let mut pending = vec![ifd0_offset];
+        let mut visited = Vec::new();
+        let mut gps_offset = None;
+
+        while let Some(offset) = pending.pop() {
+            if visited.contains(&offset) || visited.len() > MAX_SUB_IFDS + 2 {
+                continue;
+            }
+            visited.push(offset);
+
+            let entries = self.read_ifd(offset)?;
+            let mut width = None;
+            let mut height = None;
+            for entry in entries.iter().filter(|e| WANTED_TAGS.contains(&e.tag)) {
+                let value = self.read_value(entry)?;
+                match entry.tag {
+                    TAG_EXIF_IFD => pending.extend(value.as_u32()),
+                    TAG_GPS_IFD => gps_offset = gps_offset.or(value.as_u32()),
+                    TAG_SUB_IFDS => {
+                        if let TagValue::Unsigned(offsets) = &value {
+                            pending.extend(offsets.iter().take(MAX_SUB_IFDS));
+                        }
+                    }
+                    TAG_IMAGE_WIDTH => width = value.as_u32(),
+                    TAG_IMAGE_HEIGHT => height = value.as_u32(),
+                    _ => {
+                        collected.tags.entry(entry.tag).or_insert(value);
+                    }
+                }
+            }
+            if let (Some(width), Some(height)) = (width, height) {
+                collected.dimensions.push((width, height));
+            }
+        }
+
+        if let Some(offset) = gps_offset {
+            for entry in self.read_ifd(offset)?.iter().filter(|e| GPS_TAGS.contains(&e.tag)) {
+                let value = self.read_value(entry)?;
+                collected.gps.insert(entry.tag, value);
+            }
+        }
+
+        Ok(collected)
+    }
+}
+
+/// Locates the TIFF header inside the APP1 `Exif` segment of a JPEG file.
+fn find_jpeg_exif<R: Read + Seek>(reader: &mut R) -> io::Result<Option<u64>> {
+    reader.seek(SeekFrom::Start(2))?;
+    loop {
+        let mut marker = [0u8; 2];
+        reader.read_exact(&mut marker)?;
+        if marker[0] != 0xFF {
+            return Ok(None);
+        }
+        match marker[1] {
+            0xFF => {
+                reader.seek(SeekFrom::Current(-1))?;
+                continue;
+            }
+            0x01 | 0xD0..=0xD7 => continue,
+            0xD9 | 0xDA => return Ok(None),
+            _ => {}
+        }
+
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length)?;
+        let length = u16::from_be_bytes(length);
+        if length < 2 {
+            return Ok(None);
+        }
+
+        if marker[1] == 0xE1 && length >= 8 {
+            let mut identifier = [0u8; 6];
+            reader.read_exact(&mut identifier)?;
+            if &identifier == b"Exif\0\0" {
+                return reader.stream_position().map(Some);
+            }
+            reader.seek(SeekFrom::Current(i64::from(length) - 8))?;
+        } else {
+            reader.seek(SeekFrom::Current(i64::from(length) - 2))?;
+        }
+    }
+}
+
+fn format_flash(value: u32) -> String {
+    match value {
+        0x00 => "No Flash".to_string(),
+        0x01 => "Fired".to_string(),
+        0x05 => "Fired, Return not detected".to_string(),
+        0x07 => "Fired, Return detected".to_string(),
+        0x08 => "On, Did not fire".to_string(),
+        0x09 => "On, Fired".to_string(),
+        0x0d => "On, Return not detected".to_string(),
+        0x0f => "On, Return detected".to_string(),
+        0x10 => "Off, Did not fire".to_string(),
+        0x14 => "Off, Did not fire, Return not detected".to_string(),
+        0x18 => "Auto, Did not fire".to_string(),
+        0x19 => "Auto, Fired".to_string(),
+        0x1d => "Auto, Fired, Return not detected".to_string(),
+        0x1f => "Auto, Fired, Return detected".to_string(),
+        0x20 => "No flash function".to_string(),
+        0x30 => "Off, No flash function".to_string(),
+        0x41 => "Fired, Red-eye reduction".to_string(),
+        0x49 => "On, Red-eye reduction".to_string(),
+        0x50 => "Off, Red-eye reduction".to_string(),
+        0x58 => "Auto, Did not fire, Red-eye reduction".to_string(),
+        0x59 => "Auto, Fired, Red-eye reduction".to_string(),
+        other if other & 0x01 != 0 => "Fired".to_string(),
+        _ => "No Flash".to_string(),
+    }
+}
+
+fn format_white_balance(value: u32) -> String {
+    match value {
+        0 => "Auto".to_string(),
+        1 => "Manual".to_string(),
+        other => format!("Unknown ({})", other),
+    }
+}
+
+/// Formats the minimum and maximum focal length and the widest aperture at
+/// each as ExifTool's `LensInfo`: `24-70mm f/2.8`, `18-55mm f/3.5-5.6`.
+fn format_lens_info(value: &TagValue) -> Option<String> {
+    let TagValue::Rational(parts) = value else {
+        return None;
+    };
+    let number = |index: usize| {
+        parts.get(index).filter(|(_, d)| *d != 0).map(|(n, d)| f64::from(*n) / f64::from(*d)).filter(|v| *v > 0.0)
+    };
+    let short = |value: f64| format!("{}", (value * 10.0).round() / 10.0);
+
+    let (min_focal, max_focal) = (number(0)?, number(1).unwrap_or(number(0)?));
+    let focal = if min_focal == max_focal { short(min_focal) } else { format!("{}-{}", short(min_focal), short(max_focal)) };
+    let aperture = match (number(2), number(3)) {
+        (Some(wide), Some(tele)) if wide != tele => format!("{}-{}", short(wide), short(tele)),
+        (Some(wide), _) => short(wide),
+        (None, _) => "?".to_string(),
+    };
+    Some(format!("{}mm f/{}", focal, aperture))
+}
+
+/// Converts degrees, minutes and seconds to decimal degrees.
+fn gps_degrees(value: &TagValue) -> Option<f64> {
+    let TagValue::Rational(parts) = value else {
+        return None;
+    };
+    let degrees: f64 = parts
+        .iter()
+        .zip([1.0, 60.0, 3600.0])
+        .map(|((n, d), unit)| if *d == 0 { 0.0 } else { f64::from(*n) / f64::from(*d) / unit })
+        .sum();
+    degrees.is_finite().then_some(degrees)
+}
+
+/// Renders the collected tags with ExifTool's print conversions.
+fn build_metadata(file_path: &str, collected: &CollectedTags) -> Value {
+    let CollectedTags { tags, gps, dimensions } = collected;
+    let mut map = Map::new();
+    map.insert("SourceFile".to_string(), Value::from(file_path));
+
+    let mut insert = |key: &str, value: Option<String>| {
+        if let Some(value) = value {
+            map.insert(key.to_string(), Value::from(value));
+        }
+    };
+    let tag = |id: u16| tags.get(&id);
+
+    insert(
+        "DateTimeOriginal",
+        tag(TAG_DATE_TIME_ORIGINAL).and_then(TagValue::as_str).map(str::to_string),
+    );
+    insert(
+        "OffsetTimeOriginal",
+        tag(TAG_OFFSET_TIME_ORIGINAL).and_then(TagValue::as_str).map(str::to_string),
+    );
+    insert(
+        "SubSecTimeOriginal",
+        tag(TAG_SUB_SEC_TIME_ORIGINAL).and_then(TagValue::as_str).map(str::to_string),
+    );
+    insert("Make", tag(TAG_MAKE).and_then(TagValue::as_str).map(str::to_string));
+    insert("Model", tag(TAG_MODEL).and_then(TagValue::as_str).map(str::to_string));
+    insert(
+        "SerialNumber",
+        tag(TAG_BODY_SERIAL_NUMBER).and_then(TagValue::as_str).map(str::to_string),
+    );
+    insert(
+        "LensModel",
+        tag(TAG_LENS_MODEL).and_then(TagValue::as_str).map(str::to_string),
+    );
+    insert("LensInfo", tag(TAG_LENS_SPECIFICATION).and_then(format_lens_info));
+    insert(
+        "MaxApertureValue",
+        tag(TAG_MAX_APERTURE).and_then(TagValue::as_f64).map(|apex| format!("{:.1}", 2f64.powf(apex / 2.0))),
+    );
+    insert("ISO", tag(TAG_ISO).and_then(TagValue::as_u32).map(|v| v.to_string()));
+    insert(
+        "ExposureTime",
+        tag(TAG_EXPOSURE_TIME).and_then(TagValue::as_f64).map(format_exposure_time),
+    );
+    insert(
+        "FNumber",
+        tag(TAG_FNUMBER).and_then(TagValue::as_f64).map(|v| format!("{:.1}", v)),
+    );
+    insert(
+        "FocalLength",
+        tag(TAG_FOCAL_LENGTH).and_then(TagValue::as_f64).map(|v| format!("{:.1} mm", v)),
+    );
+    insert("Flash", tag(TAG_FLASH).and_then(TagValue::as_u32).map(format_flash));
+    insert(
+        "WhiteBalance",
+        tag(TAG_WHITE_BALANCE).and_then(TagValue::as_u32).map(format_white_balance),
+    );
+    insert(
+        "FocalLengthIn35mmFormat",
+        tag(TAG_FOCAL_LENGTH_35MM).and_then(TagValue::as_u32).map(|v| format!("{} mm", v)),
+    );
+
+    let exif_dimensions = tag(TAG_PIXEL_X_DIMENSION)
+        .and_then(TagValue::as_u32)
+        .zip(tag(TAG_PIXEL_Y_DIMENSION).and_then(TagValue::as_u32));
+    let largest = dimensions
+        .iter()
+        .copied()
+        .max_by_key(|(w, h)| u64::from(*w) * u64::from(*h));
+    if let Some((width, height)) = exif_dimensions.or(largest) {
+        insert("ImageWidth", Some(width.to_string()));
+        insert("ImageHeight", Some(height.to_string()));
+    }
+
+    // GPS values in decimal degrees and meters, with the raw references.
+    let gps = |id: u16| gps.get(&id);
+    let numbers = [
+        ("GPSLatitude", gps(TAG_GPS_LATITUDE).and_then(gps_degrees)),
+        ("GPSLongitude", gps(TAG_GPS_LONGITUDE).and_then(gps_degrees)),
+        ("GPSAltitude", gps(TAG_GPS_ALTITUDE).and_then(TagValue::as_f64)),
+    ];
+    for (key, value) in numbers {
+        if let Some(value) = value {
+            map.insert(key.to_string(), Value::from(value));
+        }
+    }
+    if let Some(below) = gps(TAG_GPS_ALTITUDE_REF).and_then(TagValue::as_u32) {
+        map.insert("GPSAltitudeRef".to_string(), Value::from(below));
+    }
+    for (key, id) in [("GPSLatitudeRef", TAG_GPS_LATITUDE_REF), ("GPSLongitudeRef", TAG_GPS_LONGITUDE_REF)] {
+        if let Some(reference) = gps(id).and_then(TagValue::as_str) {
+            map.insert(key.to_string(), Value::from(reference));
+        }
+    }
+
+    Value::Object(map)
+}
+
+/// Reads EXIF metadata from a JPEG or TIFF-based file.
+///
+/// Returns `Ok(None)` when the file is in a format the parser does not handle
+/// or carries no EXIF block.
+pub fn read_metadata(file_path: &str) -> Result<Option<Value>> {
+    let mut reader = BufReader::new(File::open(file_path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+
+    let base = match magic {
+        [0xFF, 0xD8, _, _] => match find_jpeg_exif(&mut reader)? {
+            Some(base) => base,
+            None => return Ok(None),
+        },
+        [b'I', b'I', 42, 0] | [b'M', b'M', 0, 42] => 0,
+        _ => return Ok(None),
+    };
+
+    let Some(mut tiff) = TiffReader::new(reader, base)? else {
+        return Ok(None);
+    };
+    let collected = tiff.collect_tags()?;
+
+    Ok(Some(build_metadata(file_path, &collected)))
+}
+
+/// [`MetadataExtractor`] using the built-in parser, delegating files it
+/// cannot read to a fallback extractor (ExifTool by default).
+pub struct NativeExtractor {
+    fallback: Box<dyn MetadataExtractor>,
+}
+
+impl NativeExtractor {
+    pub fn with_fallback(fallback: Box<dyn MetadataExtractor>) -> Self {
+        Self { fallback }
+    }
+}
+
+impl Default for NativeExtractor {
+    fn default() -> Self {
+        Self::with_fallback(Box::new(ExifToolExtractor::default()))
+    }
+}
+
+impl MetadataExtractor for NativeExtractor {
+    fn extract(&self, file_paths: &[String]) -> Result<Vec<Value>> {
+        let mut results = Vec::with_capacity(file_paths.len());
+        let mut unsupported = Vec::new();
+
+        for file_path in file_paths {
+            match read_metadata(file_path) {
+                Ok(Some(metadata)) => results.push(metadata),
+                Ok(None) | Err(_) => unsupported.push(file_path.clone()),
+            }
+        }
+
+        if !unsupported.is_empty() {
+            results.extend(self.fallback.extract(&unsupported)?);
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+    use tempfile::tempdir;
+
+    type RawEntry = (u16, u16, u32, Vec<u8>);
+
+    fn ascii(tag: u16, value: &str) -> RawEntry {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        (tag, 2, bytes.len() as u32, bytes)
+    }
+
+    fn short(tag: u16, value: u16) -> RawEntry {
+        (tag, 3, 1, value.to_le_bytes().to_vec())
+    }
+
+    fn rational(tag: u16, numerator: u32, denominator: u32) -> RawEntry {
+        let mut bytes = numerator.to_le_bytes().to_vec();
+        bytes.extend(denominator.to_le_bytes());
+        (tag, 5, 1, bytes)
+    }
+
+    /// Builds a little-endian TIFF with IFD0 pointing at an EXIF IFD.
+    fn build_tiff(mut ifd0: Vec<RawEntry>, exif: Vec<RawEntry>) -> Vec<u8> {
+        fn ifd_size(entries: usize) -> u32 {
+            2 + 12 * entries as u32 + 4
+        }
+        fn data_size(entries: &[RawEntry]) -> u32 {
+            entries.iter().filter(|e| e.3.len() > 4).map(|e| e.3.len() as u32).sum()
+        }
+        fn write_ifd(out: &mut Vec<u8>, entries: &[RawEntry], offset: u32) {
+            let mut data_offset = offset + ifd_size(entries.len());
+            let mut data: Vec<u8> = Vec::new();
+            out.extend((entries.len() as u16).to_le_bytes());
+            for (tag, kind, count, bytes) in entries {
+                out.extend(tag.to_le_bytes());
+                out.extend(kind.to_le_bytes());
+                out.extend(count.to_le_bytes());
+                if bytes.len() <= 4 {
+                    let mut inline = bytes.clone();
+                    inline.resize(4, 0);
+                    out.extend(inline);
+                } else {
+                    out.extend(data_offset.to_le_bytes());
+                    data_offset += bytes.len() as u32;
+                    data.extend(bytes);
+                }
+            }
+            out.extend(0u32.to_le_bytes());
+            out.extend(data);
+        }
+
+        ifd0.push((TAG_EXIF_IFD, 4, 1, vec![0; 4]));
+        let exif_offset = 8 + ifd_size(ifd0.len()) + data_size(&ifd0);
+        if let Some(entry) = ifd0.last_mut() {
+            entry.3 = exif_offset.to_le_bytes().to_vec();
+        }
+
+        let mut out = vec![b'I', b'I', 42, 0];
+        out.extend(8u32.to_le_bytes());
+        write_ifd(&mut out, &ifd0, 8);
+        write_ifd(&mut out, &exif, exif_offset);
+        out
+    }
+
+    fn sample_tiff() -> Vec<u8> {
+        build_tiff(
+            vec![
+                ascii(TAG_MAKE, "Canon"),
+                ascii(TAG_MODEL, "Canon EOS R5"),
+                short(TAG_IMAGE_WIDTH, 160),
+                short(TAG_IMAGE_HEIGHT, 120),
+            ],
+            vec![
+                rational(TAG_EXPOSURE_TIME, 1, 200),
+                rational(TAG_FNUMBER, 28, 10),
+                short(TAG_ISO, 400),
+                ascii(TAG_DATE_TIME_ORIGINAL, "2023:06:12 15:30:00"),
+                short(TAG_FLASH, 0x10),
+                rational(TAG_FOCAL_LENGTH, 50, 1),
+                short(TAG_WHITE_BALANCE, 0),
+                short(TAG_FOCAL_LENGTH_35MM, 50),
+                ascii(TAG_BODY_SERIAL_NUMBER, "032021001234"),
+                ascii(TAG_LENS_MODEL, "RF24-70mm F2.8 L IS USM"),
+                short(TAG_PIXEL_X_DIMENSION, 8192),
+                short(TAG_PIXEL_Y_DIMENSION, 5464),
+            ],
+        )
+    }
+
+    fn wrap_in_jpeg(tiff: &[u8]) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        // An unrelated APP0 segment before the EXIF block.
+        jpeg.extend([0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00]);
+        jpeg.extend([0xFF, 0xE1]);
+        jpeg.extend(((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(tiff);
+        jpeg.extend([0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn test_read_tiff_metadata() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("image.dng");
+        std::fs::write(&path, sample_tiff()).unwrap();
+
+        let metadata = read_metadata(path.to_str().unwrap()).unwrap().unwrap();
+        assert_eq!(metadata["SourceFile"], path.to_str().unwrap());
+        assert_eq!(metadata["Make"], "Canon");
+        assert_eq!(metadata["Model"], "Canon EOS R5");
+        assert_eq!(metadata["LensModel"], "RF24-70mm F2.8 L IS USM");
+        assert_eq!(metadata["SerialNumber"], "032021001234");
+        assert_eq!(metadata["DateTimeOriginal"], "2023:06:12 15:30:00");
+        assert_eq!(metadata["ISO"], "400");
+        assert_eq!(metadata["ExposureTime"], "1/200");
+        assert_eq!(metadata["FNumber"], "2.8");
+        assert_eq!(metadata["FocalLength"], "50.0 mm");
+        assert_eq!(metadata["Flash"], "Off, Did not fire");
+        assert_eq!(metadata["WhiteBalance"], "Auto");
+        assert_eq!(metadata["FocalLengthIn35mmFormat"], "50 mm");
+        assert_eq!(metadata["ImageWidth"], "8192");
+        assert_eq!(metadata["ImageHeight"], "5464");
+    }
+
+    #[test]
+    fn test_read_jpeg_metadata() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("image.jpg");
+        std::fs::write(&path, wrap_in_jpeg(&sample_tiff())).unwrap();
+
+        let metadata = read_metadata(path.to_str().unwrap()).unwrap().unwrap();
+        assert_eq!(metadata["Model"], "Canon EOS R5");
+        assert_eq!(metadata["ExposureTime"], "1/200");
+    }
+
+    #[test]
+    fn test_gps_tags() {
+        let collected = CollectedTags {
+            gps: HashMap::from([
+                (TAG_GPS_LATITUDE_REF, TagValue::Ascii("S".to_string())),
+                (TAG_GPS_LATITUDE, TagValue::Rational(vec![(33, 1), (52, 1), (1200, 100)])),
+                (TAG_GPS_LONGITUDE_REF, TagValue::Ascii("E".to_string())),
+                // Decimal degrees in the first rational.
+                (TAG_GPS_LONGITUDE, TagValue::Rational(vec![(15121, 100), (0, 1), (0, 1)])),
+                (TAG_GPS_ALTITUDE_REF, TagValue::Unsigned(vec![0])),
+                (TAG_GPS_ALTITUDE, TagValue::Rational(vec![(585, 10)])),
+            ]),
+            ..CollectedTags::default()
+        };
+
+        let metadata = build_metadata("image.jpg", &collected);
+        assert!((metadata["GPSLatitude"].as_f64().unwrap() - 33.87).abs() < 1e-9);
+        assert_eq!(metadata["GPSLatitudeRef"], "S");
+        assert!((metadata["GPSLongitude"].as_f64().unwrap() - 151.21).abs() < 1e-9);
+        assert_eq!(metadata["GPSAltitude"], 58.5);
+        assert_eq!(metadata["GPSAltitudeRef"], 0);
+
+        let record = crate::database::MetadataRecord::from_json(&metadata);
+        assert!((record.gps_latitude.unwrap() + 33.87).abs() < 1e-9);
+        assert!((record.gps_longitude.unwrap() - 151.21).abs() < 1e-9);
+        assert_eq!(record.gps_altitude, Some(58.5));
+    }
+
+    #[test]
+    fn test_lens_info_tags() {
+        let collected = CollectedTags {
+            tags: HashMap::from([
+                (TAG_LENS_SPECIFICATION, TagValue::Rational(vec![(18, 1), (55, 1), (35, 10), (56, 10)])),
+                (TAG_MAX_APERTURE, TagValue::Rational(vec![(3625, 1000)])),
+                (TAG_FOCAL_LENGTH, TagValue::Rational(vec![(35, 1)])),
+            ]),
+            ..CollectedTags::default()
+        };
+        let metadata = build_metadata("image.jpg", &collected);
+        assert_eq!(metadata["LensInfo"], "18-55mm f/3.5-5.6");
+        assert_eq!(metadata["MaxApertureValue"], "3.5");
+
+        let prime = TagValue::Rational(vec![(50, 1), (50, 1), (18, 10), (18, 10)]);
+        assert_eq!(format_lens_info(&prime).as_deref(), Some("50mm f/1.8"));
+        let unknown = TagValue::Rational(vec![(0, 0), (0, 0), (0, 0), (0, 0)]);
+        assert_eq!(format_lens_info(&unknown), None);
+    }
+
+    #[test]
+    fn test_unsupported_format() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("image.cr3");
+        std::fs::write(&path, b"\0\0\0\x18ftypcrx ").unwrap();
+
+        assert!(read_metadata(path.to_str().unwrap()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_jpeg_without_exif() {
+        let mut reader = Cursor::new(vec![0xFF, 0xD8, 0xFF, 0xDA, 0x00, 0x02]);
+        assert_eq!(find_jpeg_exif(&mut reader).unwrap(), None);
+    }
+
+    struct RecordingExtractor {
+        requested: Mutex<Vec<String>>,
+    }
+
+    impl MetadataExtractor for RecordingExtractor {
+        fn extract(&self, file_paths: &[String]) -> Result<Vec<Value>> {
+            self.requested.lock().unwrap().extend(file_paths.iter().cloned());
+            Ok(file_paths
+                .iter()
+                .map(|path| serde_json::json!({ "SourceFile": path, "Model": "Fallback" }))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_native_extractor_falls_back_for_unsupported_files() {
+        let temp_dir = tempdir().unwrap();
+        let dng = temp_dir.path().join("a.dng");
+        let cr3 = temp_dir.path().join("b.cr3");
+        std::fs::write(&dng, sample_tiff()).unwrap();
+        std::fs::write(&cr3, b"\0\0\0\x18ftypcrx ").unwrap();
+        let files = vec![dng.display().to_string(), cr3.display().to_string()];
+
+        let fallback = Arc::new(RecordingExtractor { requested: Mutex::new(Vec::new()) });
+        let extractor = NativeExtractor::with_fallback(Box::new(fallback.clone()));
+
+        let metadata = extractor.extract(&files).unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata[0]["Model"], "Canon EOS R5");
+        assert_eq!(metadata[1]["Model"], "Fallback");
+        assert_eq!(*fallback.requested.lock().unwrap(), vec![files[1].clone()]);
+    }
+}
@@ -0,0 +1,120 @@
+//! Detection of RAW+JPEG pairs, so a shot recorded in both formats counts once.
+//!
+//! A JPEG is paired with a RAW file in the same directory and with the same
+//! file stem ("DSC_0042.NEF" and "DSC_0042.JPG") when both were taken at the
+//! same `DateTimeOriginal`, sub-second time and camera model. Statistics count
+//! the RAW file only, unless `--count-files` is given.
+
+use crate::errors::Result;
+use crate::files::{JPEG_EXTENSIONS, RAW_EXTENSIONS};
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Raw,
+    Jpeg,
+}
+
+#[derive(Debug)]
+struct CatalogedFile {
+    source_file: String,
+    format: Format,
+    /// (DateTimeOriginal, SubSecTimeOriginal, Model)
+    shot: (Option<String>, Option<String>, Option<String>),
+}
+
+fn format_of(path: &Path) -> Option<Format> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    if RAW_EXTENSIONS.contains(&extension.as_str()) {
+        Some(Format::Raw)
+    } else if JPEG_EXTENSIONS.contains(&extension.as_str()) {
+        Some(Format::Jpeg)
+    } else {
+        None
+    }
+}
+
+/// Rebuilds the `raw_jpeg_pairs` table over the whole catalog and returns the
+/// number of pairs found.
+pub fn detect_pairs(conn: &Connection) -> Result<usize> {
+    let mut stmt = conn.prepare("SELECT source_file, DateTimeOriginal, SubSecTimeOriginal, Model FROM metadata")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, (row.get(1)?, row.get(2)?, row.get(3)?))))?;
+
+    // Files grouped by directory and case-insensitive stem.
+    let mut groups: HashMap<(String, String), Vec<CatalogedFile>> = HashMap::new();
+    for row in rows {
+        let (source_file, shot) = row?;
+        let path = Path::new(&source_file);
+        let (Some(format), Some(stem)) = (format_of(path), path.file_stem()) else {
+            continue;
+        };
+        let key = (
+            path.parent().map(|parent| parent.to_string_lossy().into_owned()).unwrap_or_default(),
+            stem.to_string_lossy().to_lowercase(),
+        );
+        groups.entry(key).or_default().push(CatalogedFile { source_file, format, shot });
+    }
+
+    let mut pairs = Vec::new();
+    for files in groups.values() {
+        let raws: Vec<&CatalogedFile> = files.iter().filter(|file| file.format == Format::Raw).collect();
+        for jpeg in files.iter().filter(|file| file.format == Format::Jpeg) {
+            // An undated file cannot be confirmed as the same shot.
+            if jpeg.shot.0.is_none() {
+                continue;
+            }
+            if let Some(raw) = raws.iter().find(|raw| raw.shot == jpeg.shot) {
+                pairs.push((jpeg.source_file.as_str(), raw.source_file.as_str()));
+            }
+        }
+    }
+
+    let transaction = conn.unchecked_transaction()?;
+    transaction.execute("DELETE FROM raw_jpeg_pairs", [])?;
+    for (jpeg, raw) in &pairs {
+        transaction.execute("INSERT INTO raw_jpeg_pairs (jpeg_file, raw_file) VALUES (?, ?)", [jpeg, raw])?;
+    }
+    transaction.commit()?;
+
+    Ok(pairs.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::insert_metadata;
+    use crate::migrations::run_migrations;
+    use serde_json::json;
+
+    #[test]
+    fn test_pairs_need_same_stem_directory_and_shot() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let shot = json!({ "DateTimeOriginal": "2023:05:01 09:00:00", "SubSecTimeOriginal": "42", "Model": "Z 6" });
+        let photos = [
+            ("/a/DSC_0001.NEF", shot.clone()),
+            ("/a/DSC_0001.JPG", shot.clone()),
+            // Same stem, but a different frame in the same second.
+            ("/a/DSC_0002.NEF", shot.clone()),
+            ("/a/DSC_0002.jpg", json!({ "DateTimeOriginal": "2023:05:01 09:00:00", "SubSecTimeOriginal": "57", "Model": "Z 6" })),
+            // Same shot, but in another directory.
+            ("/b/DSC_0001.jpg", shot.clone()),
+            ("/a/IMG_0003.cr3", json!({})),
+            ("/a/IMG_0003.jpg", json!({})),
+        ];
+        for (file, metadata) in photos {
+            insert_metadata(&conn, file, 0.0, 0, &metadata).unwrap();
+        }
+
+        assert_eq!(detect_pairs(&conn).unwrap(), 1);
+        let (jpeg, raw): (String, String) = conn
+            .query_row("SELECT jpeg_file, raw_file FROM raw_jpeg_pairs", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!((jpeg.as_str(), raw.as_str()), ("/a/DSC_0001.JPG", "/a/DSC_0001.NEF"));
+
+        // Detection is repeatable.
+        assert_eq!(detect_pairs(&conn).unwrap(), 1);
+    }
+}
@@ -0,0 +1,241 @@
+//! Removal of catalog entries for files that are no longer on disk.
+//!
+//! Pruning refuses to run when the scanned root cannot be read, or when it is
+//! empty while the catalog still holds files under it, so that an unmounted
+//! volume is never mistaken for a library whose photos were all deleted.
+//!
+//! Files are matched by their canonical absolute path, as stored by the scan,
+//! so the result does not depend on the working directory. Rows stored with
+//! relative paths by earlier versions are rewritten by the next scan of their
+//! directory and left alone until then.
+
+use crate::errors::{AppError, Result};
+use crate::files::canonical_path;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+/// Tables and columns holding the path of a cataloged file.
+const FILE_COLUMNS: [(&str, &str); 9] = [
+    ("metadata", "source_file"),
+    ("raw_metadata", "source_file"),
+    ("metadata_tags", "source_file"),
+    ("scan_errors", "source_file"),
+    ("session_photos", "source_file"),
+    ("raw_jpeg_pairs", "jpeg_file"),
+    ("raw_jpeg_pairs", "raw_file"),
+    ("photo_places", "source_file"),
+    ("geocoded_positions", "source_file"),
+];
+
+#[derive(Debug, Default, Serialize)]
+pub struct PruneReport {
+    /// Cataloged files under the root that no longer exist
+    pub missing: Vec<String>,
+    /// Number of catalog entries removed (always 0 for a dry run)
+    pub removed: usize,
+}
+
+fn is_missing(file: &str) -> bool {
+    // Anything other than "not found" (e.g. a permission error) keeps the entry.
+    matches!(std::fs::symlink_metadata(file), Err(err) if err.kind() == ErrorKind::NotFound)
+}
+
+fn cataloged_files(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT source_file FROM metadata ORDER BY source_file")?;
+    let files = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .filter_map(std::result::Result::ok)
+        .collect();
+    Ok(files)
+}
+
+fn cataloged_files_under(conn: &Connection, root: &Path) -> Result<Vec<String>> {
+    Ok(cataloged_files(conn)?
+        .into_iter()
+        .filter(|file| Path::new(file).is_absolute() && Path::new(file).starts_with(root))
+        .collect())
+}
+
+fn is_canonical(file: &str) -> bool {
+    let path = Path::new(file);
+    let normalized: PathBuf = path.components().collect();
+    path.is_absolute()
+        && normalized.as_os_str() == path.as_os_str()
+        && !path.components().any(|component| component == Component::ParentDir)
+}
+
+/// Rewrites the rows of files under `root` that were cataloged by a relative or
+/// non-normalized path to their canonical path, resolving them against the
+/// working directory. Rows whose file cannot be found are left unchanged, and
+/// rows already cataloged under the canonical path are dropped. Returns the
+/// number of files rewritten.
+pub fn rebase_cataloged_files(conn: &Connection, root: &Path) -> Result<usize> {
+    let mut renames = Vec::new();
+    for file in cataloged_files(conn)?.into_iter().filter(|file| !is_canonical(file)) {
+        match canonical_path(&file) {
+            Ok(canonical) if canonical.starts_with(root) => {
+                renames.push((file, canonical.display().to_string()));
+            }
+            _ => {}
+        }
+    }
+    if renames.is_empty() {
+        return Ok(0);
+    }
+
+    let transaction = conn.unchecked_transaction()?;
+    for (file, canonical) in &renames {
+        let cataloged: bool = transaction.query_row(
+            "SELECT EXISTS (SELECT 1 FROM metadata WHERE source_file = ?)",
+            [canonical],
+            |row| row.get(0),
+        )?;
+        for (table, column) in FILE_COLUMNS {
+            if cataloged {
+                transaction.execute(&format!("DELETE FROM {} WHERE {} = ?", table, column), [file])?;
+            } else {
+                transaction.execute(
+                    &format!("UPDATE {} SET {} = ?1 WHERE {} = ?2", table, column, column),
+                    [canonical, file],
+                )?;
+            }
+        }
+    }
+    transaction.commit()?;
+
+    Ok(renames.len())
+}
+
+/// Finds cataloged files under `root` that no longer exist and, unless
+/// `dry_run` is set, removes their rows.
+pub fn prune_missing_files(conn: &Connection, root: &str, dry_run: bool) -> Result<PruneReport> {
+    let unreachable = |err: std::io::Error| {
+        AppError::Processing(format!("Refusing to prune: {} is not reachable ({})", root, err))
+    };
+    let root_path = canonical_path(root).map_err(unreachable)?;
+    let mut entries = std::fs::read_dir(&root_path).map_err(unreachable)?;
+
+    let cataloged = cataloged_files_under(conn, &root_path)?;
+    if entries.next().is_none() && !cataloged.is_empty() {
+        return Err(AppError::Processing(format!(
+            "Refusing to prune: {} is empty but {} cataloged files are under it. Is the volume mounted?",
+            root,
+            cataloged.len()
+        )));
+    }
+
+    let missing: Vec<String> = cataloged.into_iter().filter(|file| is_missing(file)).collect();
+    if dry_run || missing.is_empty() {
+        return Ok(PruneReport { missing, removed: 0 });
+    }
+
+    let transaction = conn.unchecked_transaction()?;
+    let mut removed = 0;
+    for file in &missing {
+        for (table, column) in FILE_COLUMNS {
+            let deleted = transaction.execute(&format!("DELETE FROM {} WHERE {} = ?", table, column), [file])?;
+            if table == "metadata" {
+                removed += deleted;
+            }
+        }
+    }
+    transaction.commit()?;
+
+    Ok(PruneReport { missing, removed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::insert_metadata;
+    use crate::migrations::run_migrations;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn catalog(conn: &Connection, files: &[String]) {
+        for file in files {
+            insert_metadata(conn, file, 1234567890.0, 0, &json!({})).unwrap();
+        }
+    }
+
+    fn count_rows(conn: &Connection) -> i64 {
+        conn.query_row("SELECT COUNT(*) FROM metadata", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_prune_removes_deleted_files() {
+        let temp_dir = tempdir().unwrap();
+        let temp_dir_path = canonical_path(temp_dir.path().to_str().unwrap()).unwrap();
+        let kept = temp_dir_path.join("kept.jpg");
+        std::fs::write(&kept, "test").unwrap();
+        let deleted = temp_dir_path.join("deleted.jpg").display().to_string();
+        let outside = "/elsewhere/other.jpg".to_string();
+
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        catalog(&conn, &[kept.display().to_string(), deleted.clone(), outside]);
+
+        let root = temp_dir.path().to_str().unwrap();
+        let dry_run = prune_missing_files(&conn, root, true).unwrap();
+        assert_eq!(dry_run.missing, vec![deleted.clone()]);
+        assert_eq!(dry_run.removed, 0);
+        assert_eq!(count_rows(&conn), 3);
+
+        let report = prune_missing_files(&conn, root, false).unwrap();
+        assert_eq!(report.missing, vec![deleted]);
+        assert_eq!(report.removed, 1);
+        assert_eq!(count_rows(&conn), 2);
+    }
+
+    #[test]
+    fn test_prune_refuses_unreachable_root() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        catalog(&conn, &["/mnt/nas/photo.jpg".to_string()]);
+
+        let result = prune_missing_files(&conn, "/nonexistent/mount/point", false);
+        assert!(result.is_err());
+        assert_eq!(count_rows(&conn), 1);
+    }
+
+    #[test]
+    fn test_prune_refuses_empty_root_with_cataloged_files() {
+        let temp_dir = tempdir().unwrap();
+        let photo = canonical_path(temp_dir.path().to_str().unwrap()).unwrap().join("photo.jpg").display().to_string();
+
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        catalog(&conn, &[photo]);
+
+        let result = prune_missing_files(&conn, temp_dir.path().to_str().unwrap(), false);
+        assert!(result.is_err());
+        assert_eq!(count_rows(&conn), 1);
+    }
+
+    #[test]
+    fn test_rebase_rewrites_non_canonical_paths() {
+        let temp_dir = tempdir().unwrap();
+        let root = canonical_path(temp_dir.path().to_str().unwrap()).unwrap();
+        std::fs::create_dir(root.join("trip")).unwrap();
+        std::fs::write(root.join("trip/a.jpg"), "test").unwrap();
+        std::fs::write(root.join("trip/b.jpg"), "test").unwrap();
+        let a = root.join("trip/a.jpg").display().to_string();
+        let b = root.join("trip/b.jpg").display().to_string();
+        let legacy_a = root.join("trip/../trip/a.jpg").display().to_string();
+        let legacy_b = root.join("trip/./b.jpg").display().to_string();
+        let deleted = root.join("trip/../gone.jpg").display().to_string();
+
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        catalog(&conn, &[legacy_a, legacy_b, b.clone(), deleted.clone()]);
+
+        assert_eq!(rebase_cataloged_files(&conn, &root).unwrap(), 2);
+        let mut stmt = conn.prepare("SELECT source_file FROM metadata ORDER BY source_file").unwrap();
+        let files: Vec<String> = stmt.query_map([], |row| row.get(0)).unwrap().map(|f| f.unwrap()).collect();
+        let mut expected = vec![a, b, deleted];
+        expected.sort();
+        assert_eq!(files, expected);
+    }
+}
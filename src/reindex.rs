@@ -0,0 +1,93 @@
+//! Rebuilding of the normalized `metadata` columns from the stored raw
+//! extractor output, without reading the photos again.
+
+use crate::database::{insert_metadata_tags, load_raw_metadata, write_record, MetadataRecord};
+use crate::errors::Result;
+use crate::pairs::detect_pairs;
+use rusqlite::Connection;
+use serde::Serialize;
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReindexReport {
+    /// Files whose columns were rebuilt
+    pub reindexed: usize,
+    /// Cataloged files without stored raw metadata; rescan them with `--force`
+    pub missing_raw: Vec<String>,
+}
+
+/// Re-derives every cataloged file's columns and `extra_tags` values from its
+/// raw JSON, then redetects the RAW+JPEG pairs. Modification times and sizes
+/// are kept, so the next scan still skips unchanged files.
+pub fn reindex_catalog(conn: &Connection, extra_tags: &[String]) -> Result<ReindexReport> {
+    let mut stmt = conn.prepare("SELECT source_file, mod_time, file_size FROM metadata ORDER BY source_file")?;
+    let files = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<f64>>(1)?.unwrap_or(0.0),
+                row.get::<_, Option<i64>>(2)?,
+            ))
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let mut report = ReindexReport::default();
+    let transaction = conn.unchecked_transaction()?;
+    // Values of tags no longer configured are dropped.
+    transaction.execute("DELETE FROM metadata_tags", [])?;
+    for (file, mod_time, file_size) in files {
+        match load_raw_metadata(&transaction, &file)? {
+            Some(raw) => {
+                write_record(&transaction, &file, mod_time, file_size, &MetadataRecord::from_json(&raw))?;
+                insert_metadata_tags(&transaction, &file, &raw, extra_tags)?;
+                report.reindexed += 1;
+            }
+            None => report.missing_raw.push(file),
+        }
+    }
+    transaction.commit()?;
+    detect_pairs(conn)?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{insert_metadata, load_file_stamps};
+    use crate::migrations::run_migrations;
+    use serde_json::json;
+
+    #[test]
+    fn test_reindex_rebuilds_columns_from_raw_json() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let raw = json!({ "ISO": 200, "Model": "Canon EOS R5", "MeteringMode": "Spot" });
+        insert_metadata(&conn, "a.jpg", 1234567890.0, 2048, &raw).unwrap();
+        conn.execute(
+            "INSERT INTO metadata (source_file, mod_time, ISO) VALUES ('legacy.jpg', 1.0, 100)",
+            [],
+        )
+        .unwrap();
+
+        // Simulate a column written by an older, buggier normalization.
+        conn.execute("UPDATE metadata SET ISO = NULL, Model = 'wrong'", []).unwrap();
+
+        let report = reindex_catalog(&conn, &["MeteringMode".to_string()]).unwrap();
+        assert_eq!(report.reindexed, 1);
+        assert_eq!(report.missing_raw, vec!["legacy.jpg".to_string()]);
+
+        let (iso, model): (i64, String) = conn
+            .query_row("SELECT ISO, Model FROM metadata WHERE source_file = 'a.jpg'", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((iso, model.as_str()), (200, "Canon EOS R5"));
+        assert_eq!(load_file_stamps(&conn).unwrap().get("a.jpg"), Some(&(1234567890.0, Some(2048))));
+
+        // Tags added to the configuration are picked up without a rescan.
+        let metering: String = conn
+            .query_row("SELECT value FROM metadata_tags WHERE source_file = 'a.jpg' AND tag = 'MeteringMode'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(metering, "Spot");
+    }
+}
@@ -0,0 +1,340 @@
+//! Grouping of photos into shooting sessions.
+//!
+//! Photos are ordered by `DateTimeOriginal` and a new session starts whenever
+//! the time since the previous photo exceeds the configured gap or, when
+//! enabled, the camera moved farther than the configured distance. Detected
+//! sessions are stored in the `sessions` and `session_photos` tables, replacing
+//! the previous detection, so statistics can be filtered per session.
+
+use crate::cameras::CAMERA_SQL;
+use crate::lenses::LENS_SQL;
+use crate::database::load_positions;
+use crate::errors::{AppError, Result};
+use chrono::NaiveDateTime;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+/// Session settings, read from the `sessions` section of the configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Minutes without photos after which a new session starts
+    pub gap_minutes: u32,
+    /// Also start a new session when consecutive geotagged photos are farther
+    /// apart than this many kilometers
+    pub split_distance_km: Option<f64>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self { gap_minutes: 120, split_distance_km: None }
+    }
+}
+
+impl SessionConfig {
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.gap_minutes == 0 {
+            return Err("gap_minutes must be positive".to_string());
+        }
+        if self.split_distance_km.is_some_and(|km| !km.is_finite() || km <= 0.0) {
+            return Err("split_distance_km must be a positive number".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Session {
+    pub id: i64,
+    /// Camera local time of the first photo, "2023-06-12 15:30:00"
+    pub start: String,
+    /// Camera local time of the last photo
+    pub end: String,
+    pub photo_count: i32,
+    /// Camera models used, sorted by name
+    pub cameras: Vec<String>,
+    /// Lenses used, sorted by name
+    pub lenses: Vec<String>,
+    /// Directory holding most of the session's photos
+    pub directory: Option<String>,
+}
+
+/// Sessions started in one month.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionTrend {
+    /// "2023-05"
+    pub period: String,
+    pub sessions: i32,
+    pub photos: i32,
+    pub average_photos: f64,
+}
+
+struct SessionPhoto {
+    source_file: String,
+    taken: NaiveDateTime,
+    model: Option<String>,
+    lens: Option<String>,
+}
+
+/// Great-circle distance in kilometers between two `(latitude, longitude)`
+/// positions in degrees.
+pub fn distance_km(a: (f64, f64), b: (f64, f64)) -> f64 {
+    const EARTH_RADIUS_KM: f64 = 6371.0;
+    let (lat1, lat2) = (a.0.to_radians(), b.0.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (b.1 - a.1).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+}
+
+fn load_dated_photos(conn: &Connection) -> Result<Vec<SessionPhoto>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT source_file, DateTimeOriginal, {}, {} FROM metadata
+         WHERE DateTimeOriginal IS NOT NULL
+         ORDER BY DateTimeOriginal, source_file",
+        CAMERA_SQL, LENS_SQL
+    ))?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get(2)?, row.get(3)?))
+    })?;
+
+    let mut photos = Vec::new();
+    for row in rows {
+        let (source_file, taken, model, lens) = row?;
+        let taken = NaiveDateTime::parse_from_str(&taken, "%Y-%m-%d %H:%M:%S")
+            .map_err(|err| AppError::Processing(format!("Invalid DateTimeOriginal {:?}: {}", taken, err)))?;
+        photos.push(SessionPhoto { source_file, taken, model, lens });
+    }
+    Ok(photos)
+}
+
+/// Splits photos ordered by time into sessions, as ranges of indices.
+fn split_sessions(
+    photos: &[SessionPhoto],
+    positions: &HashMap<String, (f64, f64)>,
+    config: &SessionConfig,
+) -> Vec<std::ops::Range<usize>> {
+    let gap = chrono::Duration::minutes(i64::from(config.gap_minutes));
+    let mut sessions = Vec::new();
+    let mut start = 0;
+    let mut last_position: Option<(f64, f64)> = None;
+
+    for (index, photo) in photos.iter().enumerate() {
+        let position = positions.get(&photo.source_file).copied();
+        if index > start {
+            let long_pause = photo.taken - photos[index - 1].taken > gap;
+            let moved = match (config.split_distance_km, last_position, position) {
+                (Some(limit), Some(from), Some(to)) => distance_km(from, to) > limit,
+                _ => false,
+            };
+            if long_pause || moved {
+                sessions.push(start..index);
+                start = index;
+                last_position = None;
+            }
+        }
+        last_position = position.or(last_position);
+    }
+    if start < photos.len() {
+        sessions.push(start..photos.len());
+    }
+    sessions
+}
+
+fn dominant_directory(photos: &[SessionPhoto]) -> Option<String> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for photo in photos {
+        if let Some(parent) = Path::new(&photo.source_file).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            *counts.entry(parent.to_string_lossy().into_owned()).or_default() += 1;
+        }
+    }
+    // The first directory in name order wins ties.
+    counts.into_iter().rev().max_by_key(|(_, count)| *count).map(|(directory, _)| directory)
+}
+
+/// Detects the sessions over every dated photo in the catalog and stores
+/// them, replacing the previous detection. Sessions are numbered from 1 in
+/// chronological order.
+pub fn detect_sessions(conn: &Connection, config: &SessionConfig) -> Result<Vec<Session>> {
+    let photos = load_dated_photos(conn)?;
+    // The JPEG half of a RAW+JPEG pair belongs to the session but is not counted.
+    let paired_jpegs: HashSet<String> = conn
+        .prepare("SELECT jpeg_file FROM raw_jpeg_pairs")?
+        .query_map([], |row| row.get(0))?
+        .collect::<std::result::Result<_, _>>()?;
+    let positions = if config.split_distance_km.is_some() {
+        load_positions(conn)?
+    } else {
+        HashMap::new()
+    };
+
+    let mut sessions = Vec::new();
+    let transaction = conn.unchecked_transaction()?;
+    transaction.execute("DELETE FROM session_photos", [])?;
+    transaction.execute("DELETE FROM sessions", [])?;
+    for (index, range) in split_sessions(&photos, &positions, config).into_iter().enumerate() {
+        let members = &photos[range];
+        let session = Session {
+            id: index as i64 + 1,
+            start: members[0].taken.format("%Y-%m-%d %H:%M:%S").to_string(),
+            end: members[members.len() - 1].taken.format("%Y-%m-%d %H:%M:%S").to_string(),
+            photo_count: members.iter().filter(|photo| !paired_jpegs.contains(&photo.source_file)).count() as i32,
+            cameras: members.iter().filter_map(|photo| photo.model.clone()).collect::<BTreeSet<_>>().into_iter().collect(),
+            lenses: members.iter().filter_map(|photo| photo.lens.clone()).collect::<BTreeSet<_>>().into_iter().collect(),
+            directory: dominant_directory(members),
+        };
+
+        transaction.execute(
+            "INSERT INTO sessions (id, start_time, end_time, photo_count, cameras, lenses, directory)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![
+                session.id,
+                session.start,
+                session.end,
+                session.photo_count,
+                serde_json::to_string(&session.cameras)?,
+                serde_json::to_string(&session.lenses)?,
+                session.directory,
+            ],
+        )?;
+        for photo in members {
+            transaction.execute(
+                "INSERT INTO session_photos (source_file, session_id) VALUES (?, ?)",
+                params![photo.source_file, session.id],
+            )?;
+        }
+        sessions.push(session);
+    }
+    transaction.commit()?;
+
+    Ok(sessions)
+}
+
+/// Loads the stored sessions in chronological order.
+pub fn load_sessions(conn: &Connection) -> Result<Vec<Session>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, start_time, end_time, photo_count, cameras, lenses, directory FROM sessions ORDER BY id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, i32>(3)?,
+            row.get::<_, String>(4)?,
+            row.get::<_, String>(5)?,
+            row.get::<_, Option<String>>(6)?,
+        ))
+    })?;
+
+    let mut sessions = Vec::new();
+    for row in rows {
+        let (id, start, end, photo_count, cameras, lenses, directory) = row?;
+        sessions.push(Session {
+            id,
+            start,
+            end,
+            photo_count,
+            cameras: serde_json::from_str(&cameras)?,
+            lenses: serde_json::from_str(&lenses)?,
+            directory,
+        });
+    }
+    Ok(sessions)
+}
+
+/// Number of sessions and average photos per session for each month, by the
+/// month each session started in.
+pub fn session_trend(sessions: &[Session]) -> Vec<SessionTrend> {
+    let mut months: BTreeMap<&str, (i32, i32)> = BTreeMap::new();
+    for session in sessions {
+        let month = months.entry(&session.start[..7]).or_default();
+        month.0 += 1;
+        month.1 += session.photo_count;
+    }
+    months
+        .into_iter()
+        .map(|(period, (sessions, photos))| SessionTrend {
+            period: period.to_string(),
+            sessions,
+            photos,
+            average_photos: f64::from(photos) / f64::from(sessions),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::insert_metadata;
+    use crate::filter::StatisticsFilter;
+    use crate::migrations::run_migrations;
+    use serde_json::json;
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let photos = [
+            ("/trips/lisbon/a.jpg", json!({ "DateTimeOriginal": "2023:05:01 09:00:00", "Model": "X100V", "GPSLatitude": 38.7, "GPSLongitude": -9.1333 })),
+            ("/trips/lisbon/b.jpg", json!({ "DateTimeOriginal": "2023:05:01 10:30:00", "Model": "Z 6", "LensModel": "24-70", "GPSLatitude": 38.7167, "GPSLongitude": -9.15 })),
+            // Same morning, but in Sintra, 25 km away.
+            ("/trips/sintra/c.jpg", json!({ "DateTimeOriginal": "2023:05:01 11:45:00", "Model": "Z 6", "GPSLatitude": 38.8, "GPSLongitude": -9.39 })),
+            ("/trips/lisbon/d.jpg", json!({ "DateTimeOriginal": "2023:05:01 18:00:00", "Model": "Z 6" })),
+            ("/home/e.jpg", json!({ "DateTimeOriginal": "2023:06:10 12:00:00" })),
+            ("/home/undated.jpg", json!({})),
+        ];
+        for (file, metadata) in photos {
+            insert_metadata(&conn, file, 0.0, 0, &metadata).unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn test_sessions_split_on_time_gap() {
+        let conn = setup();
+        let sessions = detect_sessions(&conn, &SessionConfig::default()).unwrap();
+
+        assert_eq!(sessions.len(), 3);
+        assert_eq!(sessions[0].start, "2023-05-01 09:00:00");
+        assert_eq!(sessions[0].end, "2023-05-01 11:45:00");
+        assert_eq!(sessions[0].photo_count, 3);
+        assert_eq!(sessions[0].cameras, vec!["X100V".to_string(), "Z 6".to_string()]);
+        assert_eq!(sessions[0].lenses, vec!["24-70".to_string()]);
+        assert_eq!(sessions[0].directory.as_deref(), Some("/trips/lisbon"));
+        assert_eq!(load_sessions(&conn).unwrap(), sessions);
+
+        let trend = session_trend(&sessions);
+        assert_eq!(trend[0], SessionTrend { period: "2023-05".to_string(), sessions: 2, photos: 4, average_photos: 2.0 });
+        assert_eq!(trend[1].period, "2023-06");
+    }
+
+    #[test]
+    fn test_sessions_split_on_distance() {
+        let conn = setup();
+        let config = SessionConfig { split_distance_km: Some(10.0), ..SessionConfig::default() };
+        let sessions = detect_sessions(&conn, &config).unwrap();
+        assert_eq!(sessions.iter().map(|session| session.photo_count).collect::<Vec<_>>(), vec![2, 1, 1, 1]);
+
+        // Redetection replaces the stored sessions, and filters can select one.
+        let filter = StatisticsFilter { session: Some(2), ..StatisticsFilter::default() };
+        let (condition, params) = filter.to_sql();
+        let file: String = conn
+            .query_row(
+                &format!("SELECT source_file FROM metadata WHERE {}", condition),
+                rusqlite::params_from_iter(params),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(file, "/trips/sintra/c.jpg");
+    }
+
+    #[test]
+    fn test_distance_km() {
+        let lisbon = (38.7223, -9.1393);
+        let madrid = (40.4168, -3.7038);
+        assert!((distance_km(lisbon, madrid) - 503.0).abs() < 5.0);
+        assert_eq!(distance_km(lisbon, lisbon), 0.0);
+    }
+}
@@ -3,52 +3,117 @@
 //! This module generates various statistics from the metadata stored in the database,
 //! such as camera models, ISO usage, and more.
 
-use rusqlite::Connection;
-use std::collections::HashMap;
+use crate::cameras::CAMERA_SQL;
+use crate::bodies::{generate_body_statistics, BodyConfig, BodyUsage};
+use crate::buckets::{aperture_stop, bucketize, focal_range, iso_stop, shutter_stop, BucketConfig, NOT_AVAILABLE};
+use crate::crop::{crop_factors, equivalent_focal_length_sql, CropFactor};
+use crate::distribution::{Distribution, OutputConfig, SortOrder};
+use crate::export::{generate_photo_map, PhotoMap, TrackGrouping};
+use crate::filter::StatisticsFilter;
+use crate::lenses::{generate_lens_report, LensReport, LENS_SQL};
+use crate::insights::{generate_gear_insights, GearInsights, InsightsConfig};
+use crate::summary::{generate_summary, GroupSummary, SummaryGroup, SummaryStatistics};
+use crate::timeseries::{generate_time_series, TimeSeries};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection};
+use crate::utils::format_exposure_time;
+use clap::ValueEnum;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+/// Country of a `metadata` row, from the places found by geocoding.
+const COUNTRY_SQL: &str = "(SELECT country FROM photo_places WHERE photo_places.source_file = metadata.source_file)";
+
+/// "City, Region, Country" of a `metadata` row; the region is left out when it
+/// is unknown or named after the city.
+const CITY_SQL: &str = "(SELECT city || CASE WHEN region IS NULL OR region = city THEN '' ELSE ', ' || region END || ', ' || country
+    FROM photo_places WHERE photo_places.source_file = metadata.source_file)";
+
+/// Every distribution is ordered for display; see [`OutputConfig`].
 #[derive(Debug, Serialize)]
 pub struct Statistics {
-    pub photos_per_year: HashMap<String, i32>,
-    pub camera_models: HashMap<String, i32>,
-    pub lens_models: HashMap<String, i32>,
-    pub iso: HashMap<String, i32>,
-    pub shutter_speed: HashMap<String, i32>,
-    pub aperture: HashMap<String, i32>,
-    pub focal_length: HashMap<String, i32>,
-    pub white_balance: HashMap<String, i32>,
-    pub flash_usage: HashMap<String, i32>,
+    pub photos_per_year: Distribution,
+    pub camera_models: Distribution,
+    pub lens_models: Distribution,
+    pub iso: Distribution,
+    pub shutter_speed: Distribution,
+    pub aperture: Distribution,
+    pub focal_length: Distribution,
+    pub white_balance: Distribution,
+    pub flash_usage: Distribution,
+    /// Distributions of the extra tags from the configuration file, by tag name
+    pub extra: BTreeMap<String, Distribution>,
+    /// ISO grouped into full stops, in ascending order
+    pub iso_stops: Distribution,
+    /// Shutter speeds grouped into full stops, fastest first
+    pub shutter_stops: Distribution,
+    /// Apertures grouped into full or third stops, widest first
+    pub aperture_stops: Distribution,
+    /// Focal lengths grouped into the configured ranges, shortest first
+    pub focal_ranges: Distribution,
+    /// Photos per month and week, by weekday and hour, and per day of a year
+    pub time_series: TimeSeries,
+    /// Count, range, mean, median, percentiles and mode of the numeric fields
+    pub summary: SummaryStatistics,
+    /// 35mm-equivalent focal lengths, rounded to the mm, so bodies with
+    /// different sensor sizes can be compared
+    pub equivalent_focal_length: Distribution,
+    /// Crop factor of each camera model, configured or inferred, used to derive
+    /// missing equivalent focal lengths
+    pub crop_factors: Vec<CropFactor>,
+    /// Photos per country their GPS position resolves to
+    pub countries: Distribution,
+    /// Photos per city, as "City, Region, Country"
+    pub cities: Distribution,
 }
 
 pub fn generate_statistics(connection: &Connection) -> Result<Statistics, anyhow::Error> {
-    let mut stats = Statistics {
-        photos_per_year: HashMap::new(),
-        camera_models: HashMap::new(),
-        lens_models: HashMap::new(),
-        iso: HashMap::new(),
-        shutter_speed: HashMap::new(),
-        aperture: HashMap::new(),
-        focal_length: HashMap::new(),
-        white_balance: HashMap::new(),
-        flash_usage: HashMap::new(),
-    };
-
-    let queries = vec![
-        ("Photos Per Year", "SELECT COALESCE(strftime('%Y', DateTimeOriginal), 'N/A'), COUNT(*) FROM metadata GROUP BY strftime('%Y', DateTimeOriginal)"),
-        ("Camera Models", "SELECT COALESCE(Model, 'N/A'), COUNT(*) FROM metadata GROUP BY Model"),
-        ("Lens Models", "SELECT COALESCE(LensModel, 'N/A'), COUNT(*) FROM metadata GROUP BY LensModel"),
-        ("ISO", "SELECT COALESCE(ISO, 'N/A'), COUNT(*) FROM metadata GROUP BY ISO"),
-        ("Shutter Speed", "SELECT COALESCE(ExposureTime, 'N/A'), COUNT(*) FROM metadata GROUP BY ExposureTime"),
-        ("Aperture", "SELECT COALESCE(FNumber, 'N/A'), COUNT(*) FROM metadata GROUP BY FNumber"),
-        ("Focal Length", "SELECT COALESCE(FocalLength, 'N/A'), COUNT(*) FROM metadata GROUP BY FocalLength"),
-        ("White Balance", "SELECT COALESCE(WhiteBalance, 'N/A'), COUNT(*) FROM metadata GROUP BY WhiteBalance"),
-        ("Flash Usage", "SELECT COALESCE(Flash, 'N/A'), COUNT(*) FROM metadata GROUP BY Flash"),
+    generate_filtered_statistics(
+        connection,
+        &StatisticsFilter::default(),
+        &BucketConfig::default(),
+        &OutputConfig::default(),
+        &BTreeMap::new(),
+    )
+}
+
+/// Computes every distribution over the photos matching `filter`.
+/// `configured_crop_factors` maps camera models to their crop factor and
+/// overrides the inferred ones.
+pub fn generate_filtered_statistics(
+    connection: &Connection,
+    filter: &StatisticsFilter,
+    buckets: &BucketConfig,
+    output: &OutputConfig,
+    configured_crop_factors: &BTreeMap<String, f64>,
+) -> Result<Statistics, anyhow::Error> {
+    // (field, default order, label, grouping)
+    let queries = [
+        ("photos_per_year", SortOrder::Chronological, "COALESCE(strftime('%Y', DateTimeOriginal), 'N/A')", "strftime('%Y', DateTimeOriginal)"),
+        ("camera_models", SortOrder::CountDesc, &format!("COALESCE({}, 'N/A')", CAMERA_SQL), "1"),
+        ("lens_models", SortOrder::CountDesc, &format!("COALESCE({}, 'N/A')", LENS_SQL), "1"),
+        ("iso", SortOrder::Natural, "COALESCE(CAST(ISO AS TEXT), 'N/A')", "ISO"),
+        // Exposure times are grouped on their value in seconds, so "1/250" and "0.004" are one entry.
+        ("shutter_speed", SortOrder::Natural, "COALESCE(MIN(ExposureTimeDisplay), 'N/A')", "ExposureTime"),
+        ("aperture", SortOrder::Natural, "CASE WHEN FNumber IS NULL THEN 'N/A' ELSE printf('%.1f', FNumber) END", "FNumber"),
+        ("focal_length", SortOrder::Natural, "CASE WHEN FocalLength IS NULL THEN 'N/A' WHEN FocalLength = CAST(FocalLength AS INTEGER) THEN printf('%d mm', CAST(FocalLength AS INTEGER)) ELSE printf('%.1f mm', FocalLength) END", "FocalLength"),
+        ("white_balance", SortOrder::CountDesc, "COALESCE(WhiteBalance, 'N/A')", "WhiteBalance"),
+        ("flash_usage", SortOrder::CountDesc, "COALESCE(Flash, 'N/A')", "Flash"),
+        ("countries", SortOrder::CountDesc, &format!("COALESCE({}, 'N/A')", COUNTRY_SQL), "1"),
+        ("cities", SortOrder::CountDesc, &format!("COALESCE({}, 'N/A')", CITY_SQL), "1"),
     ];
 
-    for (title, query) in queries {
-        let mut stmt = connection.prepare(query)?;
-        let rows = stmt.query_map([], |row| {
+    let (condition, params) = filter.to_sql();
+    let crop_factors = crop_factors(connection, configured_crop_factors)?;
+    let equivalent = equivalent_focal_length_sql(&crop_factors);
+
+    let mut distributions: HashMap<&str, Distribution> = HashMap::new();
+    for (field, default_order, label, grouping) in queries {
+        let query = format!("SELECT {}, COUNT(*) FROM metadata WHERE {} GROUP BY {}", label, condition, grouping);
+        let mut stmt = connection.prepare(&query)?;
+        let rows = stmt.query_map(params_from_iter(&params), |row| {
             Ok((
                 row.get::<_, String>(0).unwrap_or_else(|_| "Unknown".to_string()),
                 row.get(1).unwrap_or(0)
@@ -59,21 +124,381 @@ pub fn generate_statistics(connection: &Connection) -> Result<Statistics, anyhow
             .filter_map(Result::ok)
             .collect();
 
-        match title {
-            "Photos Per Year" => stats.photos_per_year = results,
-            "Camera Models" => stats.camera_models = results,
-            "Lens Models" => stats.lens_models = results,
-            "ISO" => stats.iso = results,
-            "Shutter Speed" => stats.shutter_speed = results,
-            "Aperture" => stats.aperture = results,
-            "Focal Length" => stats.focal_length = results,
-            "White Balance" => stats.white_balance = results,
-            "Flash Usage" => stats.flash_usage = results,
-            _ => {},
+        let order = output.sort_for(field, default_order);
+        distributions.insert(field, Distribution::from_counts(results, order, output.top));
+    }
+
+    let equivalent_counts: HashMap<String, i32> = connection
+        .prepare(&format!(
+            "SELECT CASE WHEN {0} IS NULL THEN 'N/A' ELSE printf('%d mm', CAST(ROUND({0}) AS INTEGER)) END, COUNT(*)
+             FROM metadata WHERE {1} GROUP BY 1",
+            equivalent, condition
+        ))?
+        .query_map(params_from_iter(&params), |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    let equivalent_order = output.sort_for("equivalent_focal_length", SortOrder::Natural);
+    let mut take = |field: &str| distributions.remove(field).unwrap_or_default();
+
+    let extra = extra_tag_statistics(connection, filter)?
+        .into_iter()
+        .map(|(tag, counts)| {
+            let order = output.sort_for(&tag, SortOrder::CountDesc);
+            (tag, Distribution::from_counts(counts, order, output.top))
+        })
+        .collect();
+
+    let aperture_stops = buckets.aperture_stops;
+    Ok(Statistics {
+        photos_per_year: take("photos_per_year"),
+        camera_models: take("camera_models"),
+        lens_models: take("lens_models"),
+        iso: take("iso"),
+        shutter_speed: take("shutter_speed"),
+        aperture: take("aperture"),
+        focal_length: take("focal_length"),
+        white_balance: take("white_balance"),
+        flash_usage: take("flash_usage"),
+        extra,
+        iso_stops: bucketize(&numeric_counts(connection, "ISO", &condition, &params)?, iso_stop).into(),
+        shutter_stops: bucketize(&numeric_counts(connection, "ExposureTime", &condition, &params)?, shutter_stop).into(),
+        aperture_stops: bucketize(&numeric_counts(connection, "FNumber", &condition, &params)?, |f| {
+            aperture_stop(f, aperture_stops)
+        })
+        .into(),
+        focal_ranges: bucketize(&numeric_counts(connection, "FocalLength", &condition, &params)?, |f| {
+            focal_range(f, &buckets.focal_ranges)
+        })
+        .into(),
+        time_series: generate_time_series(connection, &condition, &params, output)?,
+        summary: generate_summary(connection, &condition, &params, None, &equivalent)?
+            .pop()
+            .map(|group| group.summary)
+            .unwrap_or_default(),
+        equivalent_focal_length: Distribution::from_counts(equivalent_counts, equivalent_order, output.top),
+        crop_factors,
+        countries: take("countries"),
+        cities: take("cities"),
+    })
+}
+
+/// Computes the summary statistics over the photos matching `filter`, overall
+/// or per camera or lens.
+pub fn generate_filtered_summary(
+    connection: &Connection,
+    filter: &StatisticsFilter,
+    group_by: Option<SummaryGroup>,
+    configured_crop_factors: &BTreeMap<String, f64>,
+) -> Result<Vec<GroupSummary>, anyhow::Error> {
+    let (condition, params) = filter.to_sql();
+    let equivalent = equivalent_focal_length_sql(&crop_factors(connection, configured_crop_factors)?);
+    generate_summary(connection, &condition, &params, group_by, &equivalent)
+}
+
+/// Analyzes the lens usage of the photos matching `filter`; see
+/// [`crate::insights`].
+pub fn generate_filtered_gear_insights(
+    connection: &Connection,
+    filter: &StatisticsFilter,
+    config: &InsightsConfig,
+    today: chrono::NaiveDate,
+) -> Result<GearInsights, anyhow::Error> {
+    let (condition, params) = filter.to_sql();
+    generate_gear_insights(connection, &condition, &params, config, today)
+}
+
+/// Lists the lenses of the photos matching `filter` and the photos whose lens
+/// could not be identified; see [`crate::lenses`].
+pub fn generate_filtered_lens_report(connection: &Connection, filter: &StatisticsFilter) -> Result<LensReport, anyhow::Error> {
+    let (condition, params) = filter.to_sql();
+    Ok(generate_lens_report(connection, &condition, &params)?)
+}
+
+/// Reports the usage and shutter wear of each camera body among the photos
+/// matching `filter`; see [`crate::bodies`].
+pub fn generate_filtered_body_statistics(
+    connection: &Connection,
+    filter: &StatisticsFilter,
+    config: &BodyConfig,
+) -> Result<Vec<BodyUsage>, anyhow::Error> {
+    let (condition, params) = filter.to_sql();
+    generate_body_statistics(connection, &condition, &params, config)
+}
+
+/// Loads the geotagged photos matching `filter` for export, with the tracks
+/// of each day or session when `tracks` is set; see [`crate::export`].
+pub fn generate_filtered_photo_map(
+    connection: &Connection,
+    filter: &StatisticsFilter,
+    tracks: Option<TrackGrouping>,
+) -> Result<PhotoMap, anyhow::Error> {
+    let (condition, params) = filter.to_sql();
+    generate_photo_map(connection, &condition, &params, tracks)
+}
+
+/// Counts the photos per distinct value of a numeric column.
+fn numeric_counts(
+    connection: &Connection,
+    column: &str,
+    condition: &str,
+    params: &[SqlValue],
+) -> Result<Vec<(Option<f64>, i32)>, anyhow::Error> {
+    let mut stmt = connection.prepare(&format!(
+        "SELECT {column}, COUNT(*) FROM metadata WHERE {condition} GROUP BY {column}"
+    ))?;
+    let counts = stmt
+        .query_map(params_from_iter(params), |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(counts)
+}
+
+/// Counts the values of every stored extra tag. Cataloged files without the
+/// tag are counted as "N/A", as for the built-in distributions.
+fn extra_tag_statistics(
+    connection: &Connection,
+    filter: &StatisticsFilter,
+) -> Result<BTreeMap<String, HashMap<String, i32>>, anyhow::Error> {
+    let (condition, params) = filter.to_sql();
+    let total: i32 = connection.query_row(
+        &format!("SELECT COUNT(*) FROM metadata WHERE {}", condition),
+        params_from_iter(&params),
+        |row| row.get(0),
+    )?;
+
+    let mut stmt = connection.prepare(&format!(
+        "SELECT tag, value, COUNT(*) FROM metadata_tags
+         WHERE source_file IN (SELECT source_file FROM metadata WHERE {})
+         GROUP BY tag, value",
+        condition
+    ))?;
+    let rows = stmt.query_map(params_from_iter(&params), |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i32>(2)?)))?;
+
+    let mut extra: BTreeMap<String, HashMap<String, i32>> = BTreeMap::new();
+    for (tag, value, count) in rows.filter_map(Result::ok) {
+        extra.entry(tag).or_default().insert(value, count);
+    }
+    for counts in extra.values_mut() {
+        let missing = total - counts.values().sum::<i32>();
+        if missing > 0 {
+            counts.insert("N/A".to_string(), missing);
+        }
+    }
+    Ok(extra)
+}
+
+/// A value photos can be grouped by in a [`PivotTable`]. Numeric dimensions
+/// come raw or bucketed as in [`Statistics`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dimension {
+    Year,
+    Month,
+    Hour,
+    Camera,
+    Lens,
+    Iso,
+    IsoStop,
+    ShutterSpeed,
+    ShutterStop,
+    Aperture,
+    ApertureStop,
+    FocalLength,
+    FocalRange,
+    WhiteBalance,
+    Flash,
+    Country,
+    City,
+}
+
+impl Dimension {
+    fn column(self) -> &'static str {
+        match self {
+            Dimension::Year => "strftime('%Y', DateTimeOriginal)",
+            Dimension::Month => "strftime('%Y-%m', DateTimeOriginal)",
+            Dimension::Hour => "strftime('%H', DateTimeOriginal)",
+            Dimension::Camera => CAMERA_SQL,
+            Dimension::Lens => LENS_SQL,
+            Dimension::Iso | Dimension::IsoStop => "ISO",
+            Dimension::ShutterSpeed | Dimension::ShutterStop => "ExposureTime",
+            Dimension::Aperture | Dimension::ApertureStop => "FNumber",
+            Dimension::FocalLength | Dimension::FocalRange => "FocalLength",
+            Dimension::WhiteBalance => "WhiteBalance",
+            Dimension::Flash => "Flash",
+            Dimension::Country => COUNTRY_SQL,
+            Dimension::City => CITY_SQL,
+        }
+    }
+
+    /// Label and numeric sort key of a stored value; `None` for NULL.
+    fn classify(self, value: &SqlValue, buckets: &BucketConfig) -> Option<(String, Option<f64>)> {
+        let number = match value {
+            SqlValue::Null => return None,
+            SqlValue::Text(text) => return Some((text.clone(), None)),
+            SqlValue::Integer(number) => *number as f64,
+            SqlValue::Real(number) => *number,
+            SqlValue::Blob(_) => return None,
+        };
+        let bucket = |(position, label): (i64, String)| Some((label, Some(position as f64)));
+        match self {
+            Dimension::IsoStop => bucket(iso_stop(number)),
+            Dimension::ShutterStop => bucket(shutter_stop(number)),
+            Dimension::ApertureStop => bucket(aperture_stop(number, buckets.aperture_stops)),
+            Dimension::FocalRange => bucket(focal_range(number, &buckets.focal_ranges)),
+            Dimension::ShutterSpeed => Some((format_exposure_time(number), Some(number))),
+            Dimension::Aperture => Some((format!("{:.1}", number), Some(number))),
+            Dimension::FocalLength if number.fract() == 0.0 => Some((format!("{} mm", number as i64), Some(number))),
+            Dimension::FocalLength => Some((format!("{:.1} mm", number), Some(number))),
+            _ => Some((format!("{}", number), Some(number))),
+        }
+    }
+
+    /// Whether labels are ordered chronologically rather than by frequency
+    /// when they carry no numeric key.
+    fn is_chronological(self) -> bool {
+        matches!(self, Dimension::Year | Dimension::Month | Dimension::Hour)
+    }
+}
+
+/// Predefined cross-tabulations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PivotPreset {
+    /// At which focal lengths each lens is used
+    LensFocalRange,
+    /// Which body is pushed to high ISO
+    CameraIsoStop,
+    /// Which apertures each lens is used at
+    LensApertureStop,
+    /// Which camera was used in each year
+    YearCamera,
+}
+
+impl PivotPreset {
+    /// Row and column dimensions of the preset.
+    pub fn dimensions(self) -> (Dimension, Dimension) {
+        match self {
+            PivotPreset::LensFocalRange => (Dimension::Lens, Dimension::FocalRange),
+            PivotPreset::CameraIsoStop => (Dimension::Camera, Dimension::IsoStop),
+            PivotPreset::LensApertureStop => (Dimension::Lens, Dimension::ApertureStop),
+            PivotPreset::YearCamera => (Dimension::Year, Dimension::Camera),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PivotCell {
+    pub row: usize,
+    pub column: usize,
+    pub count: i32,
+}
+
+/// Photo counts for every combination of two dimensions. Only non-empty
+/// combinations are listed in `cells`, indexing into the label vectors.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PivotTable {
+    pub rows: Dimension,
+    pub columns: Dimension,
+    pub row_labels: Vec<String>,
+    pub column_labels: Vec<String>,
+    pub cells: Vec<PivotCell>,
+    pub row_totals: Vec<i32>,
+    pub column_totals: Vec<i32>,
+    pub total: i32,
+}
+
+impl PivotTable {
+    /// Count of one combination, 0 when no photo has it.
+    pub fn count(&self, row: &str, column: &str) -> i32 {
+        let row = self.row_labels.iter().position(|label| label == row);
+        let column = self.column_labels.iter().position(|label| label == column);
+        self.cells
+            .iter()
+            .find(|cell| Some(cell.row) == row && Some(cell.column) == column)
+            .map_or(0, |cell| cell.count)
+    }
+}
+
+#[derive(Default)]
+struct AxisEntry {
+    key: Option<f64>,
+    total: i32,
+}
+
+/// Orders the labels of one axis: numeric values and buckets ascending,
+/// dates chronologically, anything else most frequent first. "N/A" is last.
+fn order_axis(dimension: Dimension, axis: HashMap<String, AxisEntry>) -> Vec<(String, i32)> {
+    let mut entries: Vec<(String, AxisEntry)> = axis.into_iter().collect();
+    entries.sort_by(|(a_label, a), (b_label, b)| {
+        let missing = (a_label == NOT_AVAILABLE).cmp(&(b_label == NOT_AVAILABLE));
+        let by_value = match (a.key, b.key) {
+            (Some(x), Some(y)) => x.total_cmp(&y),
+            _ if dimension.is_chronological() => Ordering::Equal,
+            _ => b.total.cmp(&a.total),
+        };
+        missing.then(by_value).then_with(|| a_label.cmp(b_label))
+    });
+    entries.into_iter().map(|(label, entry)| (label, entry.total)).collect()
+}
+
+/// Cross-tabulates the photos matching `filter` by two dimensions.
+pub fn generate_pivot(
+    connection: &Connection,
+    rows: Dimension,
+    columns: Dimension,
+    filter: &StatisticsFilter,
+    buckets: &BucketConfig,
+) -> Result<PivotTable, anyhow::Error> {
+    let (condition, params) = filter.to_sql();
+    let mut stmt = connection.prepare(&format!(
+        "SELECT {}, {}, COUNT(*) FROM metadata WHERE {} GROUP BY 1, 2",
+        rows.column(),
+        columns.column(),
+        condition
+    ))?;
+    let values = stmt
+        .query_map(params_from_iter(&params), |row| {
+            Ok((row.get::<_, SqlValue>(0)?, row.get::<_, SqlValue>(1)?, row.get::<_, i32>(2)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut row_axis: HashMap<String, AxisEntry> = HashMap::new();
+    let mut column_axis: HashMap<String, AxisEntry> = HashMap::new();
+    let mut counts: HashMap<(String, String), i32> = HashMap::new();
+    for (row_value, column_value, count) in values {
+        let (row_label, row_key) = rows.classify(&row_value, buckets).unwrap_or((NOT_AVAILABLE.to_string(), None));
+        let (column_label, column_key) =
+            columns.classify(&column_value, buckets).unwrap_or((NOT_AVAILABLE.to_string(), None));
+
+        let row_entry = row_axis.entry(row_label.clone()).or_default();
+        row_entry.key = row_key;
+        row_entry.total += count;
+        let column_entry = column_axis.entry(column_label.clone()).or_default();
+        column_entry.key = column_key;
+        column_entry.total += count;
+        *counts.entry((row_label, column_label)).or_default() += count;
+    }
+
+    let (row_labels, row_totals): (Vec<String>, Vec<i32>) = order_axis(rows, row_axis).into_iter().unzip();
+    let (column_labels, column_totals): (Vec<String>, Vec<i32>) = order_axis(columns, column_axis).into_iter().unzip();
+
+    let mut cells = Vec::with_capacity(counts.len());
+    for (row, row_label) in row_labels.iter().enumerate() {
+        for (column, column_label) in column_labels.iter().enumerate() {
+            if let Some(count) = counts.get(&(row_label.clone(), column_label.clone())) {
+                cells.push(PivotCell { row, column, count: *count });
+            }
         }
     }
 
-    Ok(stats)
+    Ok(PivotTable {
+        rows,
+        columns,
+        total: row_totals.iter().sum(),
+        row_labels,
+        column_labels,
+        cells,
+        row_totals,
+        column_totals,
+    })
 }
 
 #[cfg(test)]
@@ -81,13 +506,19 @@ mod tests {
 
     use rusqlite::Connection;
     use serde_json::json;
-    use std::collections::HashMap;
-    use crate::database::{create_tables_if_needed, insert_metadata};
-    use crate::statistics::generate_statistics;
+    use std::collections::{BTreeMap, HashMap};
+    use crate::database::{insert_metadata, insert_metadata_tags};
+    use crate::migrations::run_migrations;
+    use crate::buckets::{ApertureStops, Bucket, BucketConfig};
+    use crate::distribution::{Distribution, OutputConfig, SortOrder};
+    use crate::filter::StatisticsFilter;
+    use crate::geocode::{geocode_catalog, GeocodingConfig};
+    use crate::lenses::{normalize_lenses, LensNames};
+    use crate::statistics::{generate_filtered_statistics, generate_pivot, generate_statistics, Dimension, PivotPreset};
 
     fn setup_test_db() -> Connection {
         let conn = Connection::open_in_memory().unwrap();
-        create_tables_if_needed(&conn).unwrap();
+        run_migrations(&conn).unwrap();
         conn
     }
 
@@ -98,9 +529,9 @@ mod tests {
         let metadata_2021 = json!({ "DateTimeOriginal": "2021:05:10 14:30:00" });
         let metadata_2022 = json!({ "DateTimeOriginal": "2022:07:22 10:15:00" });
 
-        insert_metadata(&conn, "photo1.jpg", 1234567890.0, &metadata_2021).unwrap();
-        insert_metadata(&conn, "photo2.jpg", 1234567890.0, &metadata_2022).unwrap();
-        insert_metadata(&conn, "photo3.jpg", 1234567890.0, &metadata_2022).unwrap();
+        insert_metadata(&conn, "photo1.jpg", 1234567890.0, 0, &metadata_2021).unwrap();
+        insert_metadata(&conn, "photo2.jpg", 1234567890.0, 0, &metadata_2022).unwrap();
+        insert_metadata(&conn, "photo3.jpg", 1234567890.0, 0, &metadata_2022).unwrap();
 
         let mut stmt = conn.prepare("SELECT strftime('%Y', DateTimeOriginal), COUNT(*) FROM metadata GROUP BY strftime('%Y', DateTimeOriginal)").unwrap();
         let counts: HashMap<String, i32> = stmt
@@ -118,7 +549,7 @@ mod tests {
         let conn = setup_test_db();
 
         let metadata_no_date = json!({});
-        insert_metadata(&conn, "photo1.jpg", 1234567890.0, &metadata_no_date).unwrap();
+        insert_metadata(&conn, "photo1.jpg", 1234567890.0, 0, &metadata_no_date).unwrap();
 
         let mut stmt = conn.prepare("SELECT COALESCE(strftime('%Y', DateTimeOriginal), 'N/A'), COUNT(*) FROM metadata GROUP BY strftime('%Y', DateTimeOriginal)").unwrap();
         let counts: HashMap<String, i32> = stmt
@@ -130,11 +561,256 @@ mod tests {
         assert_eq!(counts.get("N/A"), Some(&1)); // ✅ Should default to "N/A"
     }
 
+    #[test]
+    fn test_statistics_on_typed_columns() {
+        let conn = setup_test_db();
+
+        let fast = json!({ "ISO": 1000, "ExposureTime": "1/250", "FNumber": 2.8, "FocalLength": "50.0 mm" });
+        let same_speed = json!({ "ISO": "200", "ExposureTime": 0.004, "FNumber": 8, "FocalLength": "24.5 mm" });
+        insert_metadata(&conn, "photo1.jpg", 1234567890.0, 0, &fast).unwrap();
+        insert_metadata(&conn, "photo2.jpg", 1234567890.0, 0, &same_speed).unwrap();
+        insert_metadata(&conn, "photo3.jpg", 1234567890.0, 0, &json!({})).unwrap();
+
+        let stats = generate_statistics(&conn).unwrap();
+        assert_eq!(stats.iso.get("1000"), Some(1));
+        assert_eq!(stats.iso.get("200"), Some(1));
+        assert_eq!(stats.iso.get("N/A"), Some(1));
+        assert_eq!(stats.shutter_speed.get("1/250"), Some(2));
+        assert_eq!(stats.aperture.get("2.8"), Some(1));
+        assert_eq!(stats.aperture.get("8.0"), Some(1));
+        assert_eq!(stats.focal_length.get("50 mm"), Some(1));
+        assert_eq!(stats.focal_length.get("24.5 mm"), Some(1));
+    }
+
+    #[test]
+    fn test_extra_tag_statistics() {
+        let conn = setup_test_db();
+        let tags = vec!["MeteringMode".to_string()];
+
+        for (file, metadata) in [
+            ("photo1.jpg", json!({ "MeteringMode": "Spot" })),
+            ("photo2.jpg", json!({ "MeteringMode": "Spot" })),
+            ("photo3.jpg", json!({ "MeteringMode": "Multi-segment" })),
+            ("photo4.jpg", json!({})),
+        ] {
+            insert_metadata(&conn, file, 1234567890.0, 0, &metadata).unwrap();
+            insert_metadata_tags(&conn, file, &metadata, &tags).unwrap();
+        }
+
+        let stats = generate_statistics(&conn).unwrap();
+        let metering = &stats.extra["MeteringMode"];
+        assert_eq!(metering.get("Spot"), Some(2));
+        assert_eq!(metering.get("Multi-segment"), Some(1));
+        assert_eq!(metering.get("N/A"), Some(1));
+    }
+
+    #[test]
+    fn test_filtered_statistics() {
+        let conn = setup_test_db();
+        let tags = vec!["MeteringMode".to_string()];
+        for (file, metadata) in [
+            ("/trips/iceland/a.raf", json!({ "DateTimeOriginal": "2023:03:01 10:00:00", "Model": "X-T5", "LensModel": "XF16-55mm", "ISO": 200, "MeteringMode": "Spot" })),
+            ("/trips/iceland/b.raf", json!({ "DateTimeOriginal": "2022:03:01 10:00:00", "Model": "X-T5", "LensModel": "XF56mm", "ISO": 800 })),
+            ("/home/c.nef", json!({ "DateTimeOriginal": "2023:05:01 10:00:00", "Model": "NIKON Z 6_2", "LensModel": "NIKKOR Z 50mm", "ISO": 200 })),
+        ] {
+            insert_metadata(&conn, file, 1234567890.0, 0, &metadata).unwrap();
+            insert_metadata_tags(&conn, file, &metadata, &tags).unwrap();
+        }
+
+        let filter = StatisticsFilter {
+            cameras: vec!["X-T5".to_string()],
+            date_from: chrono::NaiveDate::from_ymd_opt(2023, 1, 1),
+            ..StatisticsFilter::default()
+        };
+        let stats = generate_filtered_statistics(&conn, &filter, &BucketConfig::default(), &OutputConfig::default(), &BTreeMap::new()).unwrap();
+        assert_eq!(stats.lens_models.labels(), vec!["XF16-55mm"]);
+        assert_eq!(stats.iso.labels(), vec!["200"]);
+        assert_eq!(stats.photos_per_year.labels(), vec!["2023"]);
+        assert_eq!(stats.extra["MeteringMode"].labels(), vec!["Spot"]);
+
+        let by_path = StatisticsFilter {
+            path_prefix: Some("/trips/iceland".to_string()),
+            ..StatisticsFilter::default()
+        };
+        let stats = generate_filtered_statistics(&conn, &by_path, &BucketConfig::default(), &OutputConfig::default(), &BTreeMap::new()).unwrap();
+        assert_eq!(stats.camera_models.labels(), vec!["X-T5"]);
+        assert_eq!(stats.camera_models.get("X-T5"), Some(2));
+        assert_eq!(stats.extra["MeteringMode"].get("N/A"), Some(1));
+    }
+
+    #[test]
+    fn test_bucketed_statistics() {
+        let conn = setup_test_db();
+        for (file, metadata) in [
+            ("photo1.jpg", json!({ "ISO": 100, "ExposureTime": "1/250", "FNumber": 3.2, "FocalLength": "18 mm" })),
+            ("photo2.jpg", json!({ "ISO": 125, "ExposureTime": 0.004, "FNumber": 2.8, "FocalLength": "23.5 mm" })),
+            ("photo3.jpg", json!({ "ISO": 3200, "ExposureTime": "1/30", "FNumber": 8, "FocalLength": "55 mm" })),
+            ("photo4.jpg", json!({})),
+        ] {
+            insert_metadata(&conn, file, 1234567890.0, 0, &metadata).unwrap();
+        }
+        let bucket = |label: &str, count| Bucket { label: label.to_string(), count };
+        let buckets = |distribution: &Distribution| -> Vec<Bucket> {
+            distribution.entries.iter().map(|entry| bucket(&entry.label, entry.count)).collect()
+        };
+
+        let config = BucketConfig { focal_ranges: vec![14.0, 24.0, 35.0, 70.0], aperture_stops: ApertureStops::Third };
+        let stats = generate_filtered_statistics(&conn, &StatisticsFilter::default(), &config, &OutputConfig::default(), &BTreeMap::new()).unwrap();
+        assert_eq!(buckets(&stats.iso_stops), vec![bucket("100", 2), bucket("3200", 1), bucket("N/A", 1)]);
+        assert_eq!(buckets(&stats.shutter_stops), vec![bucket("1/250", 2), bucket("1/30", 1), bucket("N/A", 1)]);
+        assert_eq!(buckets(&stats.aperture_stops), vec![bucket("f/2.8", 1), bucket("f/3.2", 1), bucket("f/8", 1), bucket("N/A", 1)]);
+        assert_eq!(buckets(&stats.focal_ranges), vec![bucket("14-24 mm", 2), bucket("35-70 mm", 1), bucket("N/A", 1)]);
+
+        let stats = generate_statistics(&conn).unwrap();
+        assert_eq!(buckets(&stats.aperture_stops)[0], bucket("f/2.8", 2));
+    }
+
+    #[test]
+    fn test_statistics_are_ordered() {
+        let conn = setup_test_db();
+        for (file, iso, year) in [("a.jpg", 1000, 2023), ("b.jpg", 100, 2021), ("c.jpg", 200, 2022), ("d.jpg", 100, 2023)] {
+            let metadata = json!({ "ISO": iso, "DateTimeOriginal": format!("{}:01:01 12:00:00", year), "Model": format!("Camera {}", iso) });
+            insert_metadata(&conn, file, 1234567890.0, 0, &metadata).unwrap();
+        }
+
+        let stats = generate_statistics(&conn).unwrap();
+        assert_eq!(stats.iso.labels(), vec!["100", "200", "1000"]);
+        assert_eq!(stats.photos_per_year.labels(), vec!["2021", "2022", "2023"]);
+        assert_eq!(stats.camera_models.labels()[0], "Camera 100");
+        assert_eq!(stats.iso.entries[0].percent, 50.0);
+
+        let output = OutputConfig {
+            sort: HashMap::from([("photos_per_year".to_string(), SortOrder::CountDesc)]),
+            top: Some(1),
+            ..OutputConfig::default()
+        };
+        let stats = generate_filtered_statistics(&conn, &StatisticsFilter::default(), &BucketConfig::default(), &output, &BTreeMap::new()).unwrap();
+        assert_eq!(stats.photos_per_year.labels(), vec!["2023", "Other"]);
+        assert_eq!(stats.iso.labels(), vec!["100", "Other"]);
+    }
+
+    #[test]
+    fn test_equivalent_focal_length_uses_crop_factors() {
+        let conn = setup_test_db();
+        let photos = [
+            ("a.raf", json!({ "Model": "X-T5", "FocalLength": "33 mm", "FocalLengthIn35mmFormat": "50 mm" })),
+            ("b.raf", json!({ "Model": "X-T5", "FocalLength": "23 mm" })),
+            ("c.nef", json!({ "Model": "Z 6", "FocalLength": "35 mm" })),
+            ("d.jpg", json!({})),
+        ];
+        for (file, metadata) in photos {
+            insert_metadata(&conn, file, 1234567890.0, 0, &metadata).unwrap();
+        }
+
+        let stats = generate_statistics(&conn).unwrap();
+        assert_eq!(stats.equivalent_focal_length.labels(), vec!["35 mm", "50 mm", "N/A"]);
+        assert_eq!(stats.crop_factors.len(), 1);
+
+        let configured = BTreeMap::from([("Z 6".to_string(), 1.0)]);
+        let stats = generate_filtered_statistics(&conn, &StatisticsFilter::default(), &BucketConfig::default(), &OutputConfig::default(), &configured).unwrap();
+        assert_eq!(stats.equivalent_focal_length.get("35 mm"), Some(2));
+        assert_eq!(stats.summary.focal_length_35mm.unwrap().count, 3);
+    }
+
+    #[test]
+    fn test_photos_per_country_and_city() {
+        let conn = setup_test_db();
+        let photos = [
+            ("a.jpg", json!({ "GPSLatitude": 38.72, "GPSLongitude": -9.14, "DateTimeOriginal": "2023:05:01 09:00:00" })),
+            ("b.jpg", json!({ "GPSLatitude": 38.8, "GPSLongitude": -9.38, "DateTimeOriginal": "2023:05:01 11:00:00" })),
+            ("c.jpg", json!({ "GPSLatitude": 45.52, "GPSLongitude": -122.68, "DateTimeOriginal": "2024:07:01 09:00:00" })),
+            ("d.jpg", json!({ "DateTimeOriginal": "2024:07:02 09:00:00" })),
+        ];
+        for (file, metadata) in photos {
+            insert_metadata(&conn, file, 1234567890.0, 0, &metadata).unwrap();
+        }
+        geocode_catalog(&conn, &GeocodingConfig::default()).unwrap();
+
+        let stats = generate_statistics(&conn).unwrap();
+        assert_eq!(stats.countries.labels(), vec!["Portugal", "United States", "N/A"]);
+        assert_eq!(stats.countries.get("Portugal"), Some(2));
+        assert_eq!(stats.cities.get("Lisbon, Portugal"), Some(1));
+        assert_eq!(stats.cities.get("Sintra, Lisbon, Portugal"), Some(1));
+        assert_eq!(stats.cities.get("Portland, Oregon, United States"), Some(1));
+
+        let pivot = generate_pivot(&conn, Dimension::Year, Dimension::Country, &StatisticsFilter::default(), &BucketConfig::default()).unwrap();
+        assert_eq!(pivot.count("2023", "Portugal"), 2);
+        assert_eq!(pivot.count("2024", "N/A"), 1);
+    }
+
+    #[test]
+    fn test_pivot_lens_by_focal_range() {
+        let conn = setup_test_db();
+        for (file, lens, focal) in [
+            ("a.jpg", "24-70mm", "24 mm"),
+            ("b.jpg", "24-70mm", "28 mm"),
+            ("c.jpg", "24-70mm", "70 mm"),
+            ("d.jpg", "70-200mm", "200 mm"),
+            ("e.jpg", "70-200mm", "180 mm"),
+        ] {
+            insert_metadata(&conn, file, 1234567890.0, 0, &json!({ "LensModel": lens, "FocalLength": focal })).unwrap();
+        }
+        insert_metadata(&conn, "f.jpg", 1234567890.0, 0, &json!({ "FocalLength": "35 mm" })).unwrap();
+        normalize_lenses(&conn, &LensNames::default()).unwrap();
+
+        let (rows, columns) = PivotPreset::LensFocalRange.dimensions();
+        let pivot = generate_pivot(&conn, rows, columns, &StatisticsFilter::default(), &BucketConfig::default()).unwrap();
+
+        // Without a lens tag, the lens is described by its focal length range.
+        assert_eq!(pivot.row_labels, vec!["24-70mm", "70-200mm", "Unknown 35mm"]);
+        assert_eq!(pivot.column_labels, vec!["24-35 mm", "35-70 mm", "70-135 mm", "135-200 mm", "200-400 mm"]);
+        assert_eq!(pivot.count("24-70mm", "24-35 mm"), 2);
+        assert_eq!(pivot.count("70-200mm", "200-400 mm"), 1);
+        assert_eq!(pivot.count("70-200mm", "24-35 mm"), 0);
+        assert_eq!(pivot.row_totals, vec![3, 2, 1]);
+        assert_eq!(pivot.column_totals, vec![2, 1, 1, 1, 1]);
+        assert_eq!(pivot.total, 6);
+        assert_eq!(pivot.cells.len(), 5);
+    }
+
+    #[test]
+    fn test_pivot_raw_dimensions() {
+        let conn = setup_test_db();
+        for (file, model, iso, exposure) in [
+            ("a.jpg", "Body A", 100, "1/250"),
+            ("b.jpg", "Body A", 6400, "0.004"),
+            ("c.jpg", "Body B", 6400, "1/30"),
+        ] {
+            let metadata = json!({ "Model": model, "ISO": iso, "ExposureTime": exposure });
+            insert_metadata(&conn, file, 1234567890.0, 0, &metadata).unwrap();
+        }
+
+        let pivot = generate_pivot(&conn, Dimension::Iso, Dimension::ShutterSpeed, &StatisticsFilter::default(), &BucketConfig::default()).unwrap();
+        assert_eq!(pivot.row_labels, vec!["100", "6400"]);
+        assert_eq!(pivot.column_labels, vec!["1/250", "1/30"]);
+        assert_eq!(pivot.count("6400", "1/250"), 1);
+
+        let filter = StatisticsFilter { cameras: vec!["Body A".to_string()], ..StatisticsFilter::default() };
+        let pivot = generate_pivot(&conn, Dimension::Camera, Dimension::IsoStop, &filter, &BucketConfig::default()).unwrap();
+        assert_eq!(pivot.row_labels, vec!["Body A"]);
+        assert_eq!(pivot.column_labels, vec!["100", "6400"]);
+    }
+
     #[test]
     fn test_empty_statistics() {
         let conn = setup_test_db();
-        create_tables_if_needed(&conn).unwrap();
-        let _ = generate_statistics(&conn);
+        let stats = generate_statistics(&conn).expect("Statistics of an empty catalog should succeed");
+        for distribution in [
+            &stats.photos_per_year,
+            &stats.camera_models,
+            &stats.lens_models,
+            &stats.iso,
+            &stats.shutter_speed,
+            &stats.aperture,
+            &stats.focal_length,
+            &stats.white_balance,
+            &stats.flash_usage,
+            &stats.equivalent_focal_length,
+        ] {
+            assert!(distribution.entries.is_empty());
+        }
+        assert!(stats.extra.is_empty());
+        assert!(stats.crop_factors.is_empty());
     }
 
 }
@@ -0,0 +1,256 @@
+//! Summary statistics of the numeric EXIF values.
+//!
+//! Where the distributions answer "how many photos at ISO 400", these answer
+//! "what is the median ISO on this camera". Each summary is computed from the
+//! typed `metadata` columns, ignoring photos without the value.
+
+use crate::cameras::CAMERA_SQL;
+use crate::lenses::LENS_SQL;
+use clap::ValueEnum;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Count, range and central values of one numeric field.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NumericSummary {
+    /// Photos with the value
+    pub count: i32,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    /// 10th percentile
+    pub p10: f64,
+    /// 90th percentile
+    pub p90: f64,
+    /// Most frequent value; the smallest one on ties
+    pub mode: f64,
+}
+
+/// Summaries of the numeric fields. A field is `None` when no photo has it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SummaryStatistics {
+    pub iso: Option<NumericSummary>,
+    /// Exposure time in seconds
+    pub shutter_speed: Option<NumericSummary>,
+    pub aperture: Option<NumericSummary>,
+    /// Focal length in mm
+    pub focal_length: Option<NumericSummary>,
+    /// 35mm-equivalent focal length in mm, stored or derived from the crop factor
+    pub focal_length_35mm: Option<NumericSummary>,
+}
+
+/// Numeric field summarized into one [`SummaryStatistics`] field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Metric {
+    Iso,
+    ShutterSpeed,
+    Aperture,
+    FocalLength,
+    FocalLength35mm,
+}
+
+impl Metric {
+    const ALL: [Metric; 5] = [
+        Metric::Iso,
+        Metric::ShutterSpeed,
+        Metric::Aperture,
+        Metric::FocalLength,
+        Metric::FocalLength35mm,
+    ];
+
+    /// SQL expression of the value, given the one of the 35mm-equivalent focal length.
+    fn expression(self, equivalent_focal_length: &str) -> &str {
+        match self {
+            Metric::Iso => "ISO",
+            Metric::ShutterSpeed => "ExposureTime",
+            Metric::Aperture => "FNumber",
+            Metric::FocalLength => "FocalLength",
+            Metric::FocalLength35mm => equivalent_focal_length,
+        }
+    }
+
+    fn field(self, summary: &mut SummaryStatistics) -> &mut Option<NumericSummary> {
+        match self {
+            Metric::Iso => &mut summary.iso,
+            Metric::ShutterSpeed => &mut summary.shutter_speed,
+            Metric::Aperture => &mut summary.aperture,
+            Metric::FocalLength => &mut summary.focal_length,
+            Metric::FocalLength35mm => &mut summary.focal_length_35mm,
+        }
+    }
+}
+
+/// Field the photos are grouped by before summarizing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryGroup {
+    Camera,
+    Lens,
+}
+
+impl SummaryGroup {
+    fn expression(self) -> String {
+        match self {
+            SummaryGroup::Camera => format!("COALESCE({}, 'N/A')", CAMERA_SQL),
+            SummaryGroup::Lens => format!("COALESCE({}, 'N/A')", LENS_SQL),
+        }
+    }
+}
+
+/// Summaries of the photos sharing one camera or lens.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GroupSummary {
+    /// Camera model or lens, "All" when not grouped
+    pub group: String,
+    pub photos: i32,
+    pub summary: SummaryStatistics,
+}
+
+/// Value at fraction `p` of the sorted `(value, count)` pairs, interpolating
+/// linearly between the two nearest photos.
+fn percentile(values: &[(f64, i32)], total: i32, p: f64) -> f64 {
+    let rank = p * f64::from(total - 1);
+    let value_at = |index: i64| {
+        let mut seen = 0i64;
+        for (value, count) in values {
+            seen += i64::from(*count);
+            if index < seen {
+                return *value;
+            }
+        }
+        values.last().map_or(0.0, |(value, _)| *value)
+    };
+    let lower = value_at(rank.floor() as i64);
+    let upper = value_at(rank.ceil() as i64);
+    lower + (upper - lower) * rank.fract()
+}
+
+/// Summarizes `(value, count)` pairs sorted by value.
+pub fn summarize(values: &[(f64, i32)]) -> Option<NumericSummary> {
+    let count: i32 = values.iter().map(|(_, count)| count).sum();
+    if count == 0 {
+        return None;
+    }
+    let sum: f64 = values.iter().map(|(value, count)| value * f64::from(*count)).sum();
+    let mode = values
+        .iter()
+        .fold(None, |best: Option<(f64, i32)>, &(value, count)| match best {
+            Some((_, most)) if most >= count => best,
+            _ => Some((value, count)),
+        })
+        .map_or(0.0, |(value, _)| value);
+
+    Some(NumericSummary {
+        count,
+        min: values.first()?.0,
+        max: values.last()?.0,
+        mean: sum / f64::from(count),
+        median: percentile(values, count, 0.5),
+        p10: percentile(values, count, 0.1),
+        p90: percentile(values, count, 0.9),
+        mode,
+    })
+}
+
+/// Computes the summaries of the photos matching `condition`, in one group or
+/// per camera or lens. Groups are listed by photo count, largest first.
+/// `equivalent_focal_length` is the SQL expression of the 35mm-equivalent
+/// focal length (see [`crate::crop::equivalent_focal_length_sql`]).
+pub fn generate_summary(
+    connection: &Connection,
+    condition: &str,
+    params: &[SqlValue],
+    group_by: Option<SummaryGroup>,
+    equivalent_focal_length: &str,
+) -> Result<Vec<GroupSummary>, anyhow::Error> {
+    let group = group_by.map_or_else(|| "'All'".to_string(), SummaryGroup::expression);
+
+    let mut stmt = connection.prepare(&format!("SELECT {}, COUNT(*) FROM metadata WHERE {} GROUP BY 1", group, condition))?;
+    let mut groups: Vec<GroupSummary> = stmt
+        .query_map(params_from_iter(params), |row| {
+            Ok(GroupSummary { group: row.get(0)?, photos: row.get(1)?, summary: SummaryStatistics::default() })
+        })?
+        .collect::<Result<_, _>>()?;
+    groups.sort_by(|a, b| b.photos.cmp(&a.photos).then_with(|| a.group.cmp(&b.group)));
+
+    for metric in Metric::ALL {
+        let field = metric.expression(equivalent_focal_length);
+        let mut stmt = connection.prepare(&format!(
+            "SELECT {group}, {field}, COUNT(*) FROM metadata
+             WHERE {condition} AND {field} > 0
+             GROUP BY 1, 2 ORDER BY 1, 2"
+        ))?;
+        let rows = stmt.query_map(params_from_iter(params), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?, row.get::<_, i32>(2)?))
+        })?;
+        let mut values: HashMap<String, Vec<(f64, i32)>> = HashMap::new();
+        for row in rows {
+            let (group, value, count) = row?;
+            values.entry(group).or_default().push((value, count));
+        }
+
+        for group in &mut groups {
+            *metric.field(&mut group.summary) = values.get(&group.group).and_then(|values| summarize(values));
+        }
+    }
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::insert_metadata;
+    use crate::migrations::run_migrations;
+    use serde_json::json;
+
+    #[test]
+    fn test_summarize_weighted_values() {
+        let summary = summarize(&[(100.0, 3), (400.0, 1), (1600.0, 1)]).unwrap();
+        assert_eq!(summary.count, 5);
+        assert_eq!(summary.min, 100.0);
+        assert_eq!(summary.max, 1600.0);
+        assert_eq!(summary.mean, 460.0);
+        assert_eq!(summary.median, 100.0);
+        assert_eq!(summary.mode, 100.0);
+        // Ranks 0.4 and 3.6 of 100, 100, 100, 400, 1600.
+        assert_eq!(summary.p10, 100.0);
+        assert!((summary.p90 - 1120.0).abs() < 1e-9);
+
+        let even = summarize(&[(24.0, 1), (70.0, 1)]).unwrap();
+        assert_eq!(even.median, 47.0);
+        assert_eq!(even.mode, 24.0);
+        assert_eq!(summarize(&[]), None);
+    }
+
+    #[test]
+    fn test_summary_grouped_by_camera() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let photos = [
+            ("a.jpg", json!({ "Model": "Z 6", "ISO": 100, "FocalLength": "24.0 mm" })),
+            ("b.jpg", json!({ "Model": "Z 6", "ISO": 400, "FocalLength": "70.0 mm" })),
+            ("c.jpg", json!({ "Model": "Z 6", "ISO": 3200 })),
+            ("d.jpg", json!({ "Model": "X100V", "ISO": 160, "FocalLength": "23.0 mm", "FocalLengthIn35mmFormat": "35 mm" })),
+        ];
+        for (file, metadata) in photos {
+            insert_metadata(&conn, file, 0.0, 0, &metadata).unwrap();
+        }
+
+        let overall = generate_summary(&conn, "1", &[], None, "FocalLengthIn35mmFormat").unwrap();
+        assert_eq!(overall.len(), 1);
+        assert_eq!(overall[0].group, "All");
+        assert_eq!(overall[0].summary.iso.as_ref().unwrap().median, 280.0);
+        assert_eq!(overall[0].summary.focal_length_35mm.as_ref().unwrap().count, 1);
+
+        let by_camera = generate_summary(&conn, "1", &[], Some(SummaryGroup::Camera), "FocalLengthIn35mmFormat").unwrap();
+        assert_eq!(by_camera.iter().map(|group| group.group.as_str()).collect::<Vec<_>>(), vec!["Z 6", "X100V"]);
+        let z6 = &by_camera[0].summary;
+        assert_eq!(z6.iso.as_ref().unwrap().median, 400.0);
+        assert_eq!(z6.focal_length.as_ref().unwrap().count, 2);
+        assert_eq!(z6.focal_length_35mm, None);
+        assert_eq!(z6.aperture, None);
+    }
+}
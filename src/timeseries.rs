@@ -0,0 +1,207 @@
+//! Photo counts over time, derived from `DateTimeOriginal`.
+//!
+//! Dates are stored in the camera's local time (see
+//! [`crate::database::MetadataRecord`]), so a photo taken at 23:30 in New York
+//! counts towards that evening, whatever the timezone of the machine running
+//! the statistics.
+
+use crate::distribution::{Distribution, OutputConfig, SortOrder};
+use anyhow::Context;
+use chrono::{Datelike, NaiveDate};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// Photo counts per day of the week (rows, Monday first) and hour of the day
+/// (columns, 0 to 23).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Heatmap {
+    pub rows: Vec<String>,
+    pub columns: Vec<String>,
+    pub counts: Vec<Vec<i32>>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self {
+            rows: WEEKDAYS.iter().map(|day| day.to_string()).collect(),
+            columns: (0..24).map(|hour| format!("{:02}", hour)).collect(),
+            counts: vec![vec![0; 24]; 7],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CalendarDay {
+    /// "2023-06-12"
+    pub date: String,
+    pub count: i32,
+}
+
+/// Photo counts for every day of one year, including days without photos.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Calendar {
+    pub year: i32,
+    pub days: Vec<CalendarDay>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct TimeSeries {
+    /// "2023-05"
+    pub photos_per_month: Distribution,
+    /// ISO 8601 weeks, "2023-W05"
+    pub photos_per_week: Distribution,
+    pub weekday_hour: Heatmap,
+    /// The configured calendar year, or the latest year with photos
+    pub calendar: Option<Calendar>,
+}
+
+/// Counts the photos matching `condition` per day and hour.
+fn hourly_counts(
+    connection: &Connection,
+    condition: &str,
+    params: &[SqlValue],
+) -> Result<Vec<(NaiveDate, u32, i32)>, anyhow::Error> {
+    let mut stmt = connection.prepare(&format!(
+        "SELECT substr(DateTimeOriginal, 1, 13), COUNT(*) FROM metadata
+         WHERE {} AND DateTimeOriginal IS NOT NULL
+         GROUP BY 1",
+        condition
+    ))?;
+    let rows = stmt.query_map(params_from_iter(params), |row| Ok((row.get::<_, String>(0)?, row.get::<_, i32>(1)?)))?;
+
+    let mut counts = Vec::new();
+    for row in rows {
+        let (day_hour, count) = row?;
+        let date = NaiveDate::parse_from_str(&day_hour[..10], "%Y-%m-%d")
+            .with_context(|| format!("Invalid DateTimeOriginal {:?}", day_hour))?;
+        let hour = day_hour[11..].parse::<u32>().unwrap_or(0);
+        counts.push((date, hour, count));
+    }
+    Ok(counts)
+}
+
+fn calendar_for(year: i32, per_day: &BTreeMap<NaiveDate, i32>) -> Option<Calendar> {
+    let first = NaiveDate::from_ymd_opt(year, 1, 1)?;
+    let days = first
+        .iter_days()
+        .take_while(|date| date.year() == year)
+        .map(|date| CalendarDay {
+            date: date.format("%Y-%m-%d").to_string(),
+            count: per_day.get(&date).copied().unwrap_or(0),
+        })
+        .collect();
+    Some(Calendar { year, days })
+}
+
+/// Computes the time series over the photos matching `condition`. Photos
+/// without a date are counted as "N/A" in the month and week distributions.
+pub fn generate_time_series(
+    connection: &Connection,
+    condition: &str,
+    params: &[SqlValue],
+    output: &OutputConfig,
+) -> Result<TimeSeries, anyhow::Error> {
+    let undated: i32 = connection.query_row(
+        &format!("SELECT COUNT(*) FROM metadata WHERE {} AND DateTimeOriginal IS NULL", condition),
+        params_from_iter(params),
+        |row| row.get(0),
+    )?;
+
+    let mut months: HashMap<String, i32> = HashMap::new();
+    let mut weeks: HashMap<String, i32> = HashMap::new();
+    let mut per_day: BTreeMap<NaiveDate, i32> = BTreeMap::new();
+    let mut heatmap = Heatmap::default();
+
+    for (date, hour, count) in hourly_counts(connection, condition, params)? {
+        *months.entry(date.format("%Y-%m").to_string()).or_default() += count;
+        let week = date.iso_week();
+        *weeks.entry(format!("{}-W{:02}", week.year(), week.week())).or_default() += count;
+        *per_day.entry(date).or_default() += count;
+        heatmap.counts[date.weekday().num_days_from_monday() as usize][hour.min(23) as usize] += count;
+    }
+    if undated > 0 {
+        months.insert(crate::buckets::NOT_AVAILABLE.to_string(), undated);
+        weeks.insert(crate::buckets::NOT_AVAILABLE.to_string(), undated);
+    }
+
+    let calendar_year = output.calendar_year.or_else(|| per_day.keys().next_back().map(|date| date.year()));
+
+    Ok(TimeSeries {
+        photos_per_month: Distribution::from_counts(months, output.sort_for("photos_per_month", SortOrder::Chronological), None),
+        photos_per_week: Distribution::from_counts(weeks, output.sort_for("photos_per_week", SortOrder::Chronological), None),
+        weekday_hour: heatmap,
+        calendar: calendar_year.and_then(|year| calendar_for(year, &per_day)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::insert_metadata;
+    use crate::migrations::run_migrations;
+    use chrono::Weekday;
+    use serde_json::json;
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let photos = [
+            ("a.jpg", json!({ "DateTimeOriginal": "2023:01:01 10:15:00" })),
+            ("b.jpg", json!({ "DateTimeOriginal": "2023:01:02 10:45:00" })),
+            ("c.jpg", json!({ "DateTimeOriginal": "2023:02:10 22:00:00" })),
+            // 03:30 UTC on Saturday is 23:30 on Friday at the camera.
+            ("d.jpg", json!({ "DateTimeOriginal": "2023:02:11 03:30:00Z", "OffsetTimeOriginal": "-04:00" })),
+            ("e.jpg", json!({})),
+        ];
+        for (file, metadata) in photos {
+            insert_metadata(&conn, file, 0.0, 0, &metadata).unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn test_months_and_iso_weeks() {
+        let conn = setup();
+        let series = generate_time_series(&conn, "1", &[], &OutputConfig::default()).unwrap();
+
+        assert_eq!(series.photos_per_month.labels(), vec!["2023-01", "2023-02", "N/A"]);
+        assert_eq!(series.photos_per_month.get("2023-02"), Some(2));
+        // 2023-01-01 is a Sunday and belongs to the last ISO week of 2022.
+        assert_eq!(series.photos_per_week.labels(), vec!["2022-W52", "2023-W01", "2023-W06", "N/A"]);
+    }
+
+    #[test]
+    fn test_weekday_hour_heatmap_uses_camera_time() {
+        let conn = setup();
+        let series = generate_time_series(&conn, "1", &[], &OutputConfig::default()).unwrap();
+        let row = |weekday: Weekday| series.weekday_hour.counts[weekday.num_days_from_monday() as usize].clone();
+
+        assert_eq!(row(Weekday::Sun)[10], 1);
+        assert_eq!(row(Weekday::Mon)[10], 1);
+        assert_eq!(row(Weekday::Fri)[22], 1);
+        assert_eq!(row(Weekday::Fri)[23], 1);
+        assert_eq!(row(Weekday::Sat).iter().sum::<i32>(), 0);
+        assert_eq!(series.weekday_hour.rows[4], "Fri");
+    }
+
+    #[test]
+    fn test_calendar_covers_whole_year() {
+        let conn = setup();
+        let series = generate_time_series(&conn, "1", &[], &OutputConfig::default()).unwrap();
+        let calendar = series.calendar.unwrap();
+        assert_eq!(calendar.year, 2023);
+        assert_eq!(calendar.days.len(), 365);
+        assert_eq!(calendar.days[0], CalendarDay { date: "2023-01-01".to_string(), count: 1 });
+        assert_eq!(calendar.days.iter().find(|day| day.date == "2023-02-10").unwrap().count, 2);
+
+        let output = OutputConfig { calendar_year: Some(2024), ..OutputConfig::default() };
+        let series = generate_time_series(&conn, "1", &[], &output).unwrap();
+        let calendar = series.calendar.unwrap();
+        assert_eq!(calendar.days.len(), 366);
+        assert!(calendar.days.iter().all(|day| day.count == 0));
+    }
+}
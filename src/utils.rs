@@ -1,21 +1,90 @@
-pub fn normalize_focal_length(focal: Option<&str>) -> String {
-    if let Some(focal) = focal {
-        let focal_clean = focal
-            .trim()
-            .to_lowercase()
-            .replace("mm", "")
-            .replace(",", ".")
-            .trim()
-            .to_string();
-        if let Ok(value) = focal_clean.parse::<f64>() {
-            return if value.fract() == 0.0 {
-                format!("{} mm", value as i64)
-            } else {
-                format!("{:.1} mm", value)
-            };
+use serde_json::Value;
+
+/// Parses the leading number of an ExifTool value such as `"50.0 mm"`,
+/// `"f/2.8"` or `"100"`, accepting `,` as the decimal separator.
+pub fn parse_number(value: &str) -> Option<f64> {
+    let cleaned = value.trim().to_lowercase().replace("mm", "").replace(',', ".");
+    let cleaned = cleaned.strip_prefix("f/").unwrap_or(&cleaned);
+    cleaned
+        .split_whitespace()
+        .next()
+        .and_then(|token| token.parse::<f64>().ok())
+        .filter(|number| number.is_finite())
+}
+
+/// Reads a value that ExifTool prints either as a JSON number or as a string.
+pub fn json_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => parse_number(text),
+        _ => None,
+    }
+}
+
+/// Parses an exposure time such as `"1/200"`, `"0.5"` or `"30"` into seconds.
+pub fn parse_exposure_time(value: &str) -> Option<f64> {
+    let cleaned = value.trim().trim_end_matches('s').trim();
+    let seconds = match cleaned.split_once('/') {
+        Some((numerator, denominator)) => {
+            let numerator = numerator.trim().parse::<f64>().ok()?;
+            let denominator = denominator.trim().parse::<f64>().ok()?;
+            numerator / denominator
         }
+        None => parse_number(cleaned)?,
+    };
+    (seconds.is_finite() && seconds > 0.0).then_some(seconds)
+}
+
+/// Formats an exposure time in seconds the way ExifTool prints it.
+pub fn format_exposure_time(seconds: f64) -> String {
+    if seconds > 0.0 && seconds < 0.25001 {
+        format!("1/{}", (1.0 / seconds).round())
+    } else {
+        let formatted = format!("{:.4}", seconds);
+        formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+/// Reads a JSON number or a numeric string, with an optional sign.
+fn json_decimal(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
     }
-    "N/A".to_string()
+    .filter(|number: &f64| number.is_finite())
+}
+
+/// Reads a GPS coordinate in decimal degrees, signed as ExifTool prints it with
+/// `-c %+.6f` (`-9.133300`) or unsigned with a separate reference. Southern
+/// and western coordinates, marked by `reference` (`"S"`, `"South"`, `"W"` or
+/// `"West"`), are negative.
+pub fn json_coordinate(value: &Value, reference: Option<&Value>) -> Option<f64> {
+    let degrees = json_decimal(value)?;
+    let hemisphere = reference.and_then(Value::as_str).and_then(|r| r.trim().chars().next());
+    let degrees = match hemisphere.map(|c| c.to_ascii_uppercase()) {
+        Some('S') | Some('W') => -degrees.abs(),
+        _ => degrees,
+    };
+    (degrees.abs() <= 180.0).then_some(degrees)
+}
+
+/// Reads a GPS altitude in meters, given as a number or printed by ExifTool
+/// (`123.4 m`, `12 m Below Sea Level`). Altitudes below sea level, marked in
+/// the value itself or by `reference` (`"Below Sea Level"` or `1`), are
+/// negative.
+pub fn json_altitude(value: &Value, reference: Option<&Value>) -> Option<f64> {
+    let (meters, below) = match value {
+        Value::String(text) => (json_decimal(&Value::from(text.split_whitespace().next()?))?, text.contains("Below")),
+        other => (json_decimal(other)?, false),
+    };
+    let below = below
+        || match reference {
+            Some(Value::String(text)) if text.trim().starts_with("Below") => true,
+            Some(reference) => json_decimal(reference) == Some(1.0),
+            None => false,
+        };
+    Some(if below { -meters.abs() } else { meters })
 }
 
 pub fn normalize_white_balance(wb: Option<&str>) -> String {
@@ -40,26 +109,62 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_normalize_focal_length() {
-        assert_eq!(normalize_focal_length(Some("50mm")), "50 mm");
-        assert_eq!(normalize_focal_length(Some("50.5mm")), "50.5 mm");
-        assert_eq!(normalize_focal_length(Some(" 85 MM ")), "85 mm");
-        assert_eq!(normalize_focal_length(Some("abc")), "N/A"); // Invalid input
-        assert_eq!(normalize_focal_length(None), "N/A"); // Null input
+    fn test_json_coordinate() {
+        use serde_json::json;
+        assert_eq!(json_coordinate(&json!(40.5), None), Some(40.5));
+        assert_eq!(json_coordinate(&json!(3.7), Some(&json!("W"))), Some(-3.7));
+        assert_eq!(json_coordinate(&json!("33.87"), Some(&json!("S"))), Some(-33.87));
+        assert_eq!(json_coordinate(&json!(-12.25), Some(&json!("N"))), Some(-12.25));
+        assert_eq!(json_coordinate(&json!("-9.133300"), Some(&json!("West"))), Some(-9.1333));
+        assert_eq!(json_coordinate(&json!("+38.716700"), Some(&json!("North"))), Some(38.7167));
+        assert_eq!(json_coordinate(&json!("40 deg 30' 0.00\" N"), None), None);
+        assert_eq!(json_coordinate(&json!(""), None), None);
+    }
+
+    #[test]
+    fn test_json_altitude() {
+        use serde_json::json;
+        assert_eq!(json_altitude(&json!(123.4), None), Some(123.4));
+        assert_eq!(json_altitude(&json!(123.4), Some(&json!(0))), Some(123.4));
+        assert_eq!(json_altitude(&json!(30.5), Some(&json!(1))), Some(-30.5));
+        assert_eq!(json_altitude(&json!("12"), Some(&json!("1"))), Some(-12.0));
+        assert_eq!(json_altitude(&json!("123.4 m Above Sea Level"), None), Some(123.4));
+        assert_eq!(json_altitude(&json!("12 m Below Sea Level"), None), Some(-12.0));
+        assert_eq!(json_altitude(&json!("12 m"), Some(&json!("Below Sea Level"))), Some(-12.0));
+        assert_eq!(json_altitude(&json!("undef"), None), None);
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("50.0 mm"), Some(50.0));
+        assert_eq!(parse_number("f/2.8"), Some(2.8));
+        assert_eq!(parse_number("100 (Hi)"), Some(100.0));
+        assert_eq!(parse_number("24,5mm"), Some(24.5));
+        assert_eq!(parse_number("N/A"), None);
+    }
+
+    #[test]
+    fn test_json_number() {
+        assert_eq!(json_number(&serde_json::json!(100)), Some(100.0));
+        assert_eq!(json_number(&serde_json::json!("2.8")), Some(2.8));
+        assert_eq!(json_number(&Value::Null), None);
     }
 
     #[test]
-    fn test_focal_length_with_spaces() {
-        assert_eq!(normalize_focal_length(Some("50 mm")), "50 mm");
-        assert_eq!(normalize_focal_length(Some("  24  mm  ")), "24 mm");
-        assert_eq!(normalize_focal_length(Some("70.0 mm")), "70 mm");
+    fn test_parse_exposure_time() {
+        assert_eq!(parse_exposure_time("1/200"), Some(0.005));
+        assert_eq!(parse_exposure_time("0.5"), Some(0.5));
+        assert_eq!(parse_exposure_time("30"), Some(30.0));
+        assert_eq!(parse_exposure_time("1/0"), None);
+        assert_eq!(parse_exposure_time("N/A"), None);
     }
 
     #[test]
-    fn test_focal_length_with_different_decimal_separators() {
-        assert_eq!(normalize_focal_length(Some("50,5mm")), "50.5 mm"); // European decimal separator
-        assert_eq!(normalize_focal_length(Some("24,0 mm")), "24 mm");
-        assert_eq!(normalize_focal_length(Some("100,5")), "100.5 mm");
+    fn test_format_exposure_time() {
+        assert_eq!(format_exposure_time(0.005), "1/200");
+        assert_eq!(format_exposure_time(1.0 / 8000.0), "1/8000");
+        assert_eq!(format_exposure_time(0.5), "0.5");
+        assert_eq!(format_exposure_time(30.0), "30");
     }
 
     #[test]
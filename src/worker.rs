@@ -8,17 +8,123 @@ use std::sync::Mutex;
 use std::time::UNIX_EPOCH;
 use rusqlite::Transaction;
 use rusqlite::Connection; // Added this explicit import to match usage
+use crate::cameras::{normalize_cameras, CameraNames};
 use crate::config::Config;
-use crate::exiftool::run_exiftool;
-use crate::database::insert_metadata;
+use crate::geocode::geocode_catalog;
+use crate::lenses::{normalize_lenses, LensNames};
+use crate::pairs::detect_pairs;
+use crate::extractor::{create_extractor, MetadataExtractor};
+use crate::files::canonical_path;
+use crate::prune::rebase_cataloged_files;
+use crate::database::{
+    clear_scan_errors, insert_metadata, insert_metadata_tags, insert_scan_error, load_file_stamps, open_database,
+    update_file_size,
+};
+use serde::Serialize;
 use serde_json::Value;
+use std::collections::HashMap;
 use crate::errors::{AppError, Result};
 
+/// Outcome of a scan, counted per file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ScanSummary {
+    /// Files whose metadata was stored
+    pub succeeded: usize,
+    /// Files that could not be processed or for which the extractor returned
+    /// no metadata, recorded in `scan_errors`
+    pub failed: usize,
+    /// Files not yet in the catalog
+    pub new: usize,
+    /// Cataloged files whose modification time or size changed
+    pub changed: usize,
+    /// Cataloged files left untouched because they did not change
+    pub unchanged: usize,
+}
+
+impl ScanSummary {
+    fn merge(self, other: Self) -> Self {
+        Self {
+            succeeded: self.succeeded + other.succeeded,
+            failed: self.failed + other.failed,
+            new: self.new + other.new,
+            changed: self.changed + other.changed,
+            unchanged: self.unchanged + other.unchanged,
+        }
+    }
+}
+
+/// Outcome of the catalog-wide steps run after the files of a scan are stored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FinishReport {
+    /// RAW+JPEG pairs counted as one shot each
+    pub pairs: usize,
+    /// Canonical camera names in the catalog
+    pub cameras: usize,
+    /// Lens names in the catalog
+    pub lenses: usize,
+    /// Photo positions resolved to places
+    pub placed: usize,
+}
+
+/// Files selected for extraction, with the new/changed/unchanged breakdown.
+#[derive(Debug, Default)]
+pub struct ScanPlan {
+    pub to_process: Vec<String>,
+    pub new: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+}
+
+/// Compares each file's modification time and size with the cached row and
+/// keeps only new or changed files, unless `force` is set.
+pub fn plan_scan(conn: &Connection, files: Vec<String>, force: bool) -> Result<ScanPlan> {
+    let stamps = load_file_stamps(conn)?;
+    let mut plan = ScanPlan::default();
+
+    for file in files {
+        let Some((cached_mod_time, cached_size)) = stamps.get(&file) else {
+            plan.new += 1;
+            plan.to_process.push(file);
+            continue;
+        };
+
+        let mod_time = get_file_mod_time_secs(&file);
+        let size = get_file_size(&file);
+        let same_mod_time = (mod_time - cached_mod_time).abs() < 1e-6;
+        let unchanged = match cached_size {
+            Some(cached_size) => same_mod_time && *cached_size == size,
+            None => {
+                // Rows from before sizes were tracked: trust the mtime and backfill the size.
+                if same_mod_time {
+                    update_file_size(conn, &file, size)?;
+                }
+                same_mod_time
+            }
+        };
+
+        if unchanged {
+            plan.unchanged += 1;
+            if force {
+                plan.to_process.push(file);
+            }
+        } else {
+            plan.changed += 1;
+            plan.to_process.push(file);
+        }
+    }
+
+    Ok(plan)
+}
+
 pub fn init_database_connection(db_path: &std::path::Path) -> Result<Mutex<Connection>> {
-    let conn = Connection::open(db_path)?;
+    let conn = open_database(db_path)?;
     Ok(Mutex::new(conn))
 }
 
+pub fn get_file_size(file_path: &str) -> i64 {
+    std::fs::metadata(file_path).map_or(0, |m| m.len() as i64)
+}
+
 pub fn get_file_mod_time_secs(file_path: &str) -> f64 {
     let mod_time = std::fs::metadata(file_path)
         .and_then(|m| m.modified())
@@ -33,53 +139,167 @@ pub fn get_file_mod_time_secs(file_path: &str) -> f64 {
     }
 }
 
-pub fn process_chunk(chunk: &[String], conn: &Mutex<Connection>) -> Result<()> {
-    let metadata = run_exiftool(chunk)?;
+/// Normalizes a path so that ExifTool's `SourceFile` can be compared with the
+/// path it was given: separators become `/`, and `./` prefixes and repeated
+/// separators are dropped.
+pub fn normalize_source_path(path: &str) -> String {
+    let mut normalized = path.trim().replace('\\', "/");
+    while normalized.contains("//") {
+        normalized = normalized.replace("//", "/");
+    }
+    while let Some(stripped) = normalized.strip_prefix("./") {
+        normalized = stripped.to_string();
+    }
+    normalized.replace("/./", "/")
+}
+
+/// Extracts and stores the metadata of one chunk, returning the files for which
+/// the extractor produced no metadata.
+pub fn process_chunk(
+    chunk: &[String],
+    conn: &Mutex<Connection>,
+    extractor: &dyn MetadataExtractor,
+    extra_tags: &[String],
+) -> Result<Vec<String>> {
+    let metadata = extractor.extract(chunk)?;
     
     let mut db_conn = conn.lock().map_err(|_| AppError::Processing("Database mutex poisoned".to_string()))?;
 
     let transaction = db_conn.transaction()?;
 
-    process_files_in_transaction(&transaction, chunk, &metadata)?;
+    let missing = process_files_in_transaction(&transaction, chunk, &metadata, extra_tags)?;
 
     transaction.commit()?;
 
-    Ok(())
+    Ok(missing)
 }
 
-pub fn process_files_in_transaction(transaction: &Transaction, files: &[String], metadata: &[Value]) -> Result<()> {
-    for (file, data) in files.iter().zip(metadata.iter()) {
+/// Stores each file's metadata, matched on the `SourceFile` field rather than
+/// by position, together with the configured `extra_tags`, and clears the
+/// errors recorded for them by earlier scans. Returns the files that had no
+/// matching entry.
+pub fn process_files_in_transaction(
+    transaction: &Transaction,
+    files: &[String],
+    metadata: &[Value],
+    extra_tags: &[String],
+) -> Result<Vec<String>> {
+    let mut by_source: HashMap<String, &Value> = metadata
+        .iter()
+        .filter_map(|data| {
+            data["SourceFile"]
+                .as_str()
+                .map(|source| (normalize_source_path(source), data))
+        })
+        .collect();
+
+    let mut missing = Vec::new();
+    for file in files {
+        let Some(data) = by_source.remove(&normalize_source_path(file)) else {
+            missing.push(file.clone());
+            continue;
+        };
         let mod_time_secs = get_file_mod_time_secs(file);
+        let file_size = get_file_size(file);
 
-        insert_metadata(transaction, file, mod_time_secs, data)?;
+        insert_metadata(transaction, file, mod_time_secs, file_size, data)?;
+        insert_metadata_tags(transaction, file, data, extra_tags)?;
+        clear_scan_errors(transaction, file)?;
     }
 
-    Ok(())
+    Ok(missing)
+}
+
+fn record_scan_error(conn: &Mutex<Connection>, file: &str, error: &AppError) -> Result<()> {
+    let db_conn = conn.lock().map_err(|_| AppError::Processing("Database mutex poisoned".to_string()))?;
+    insert_scan_error(&db_conn, file, error)
+}
+
+/// Processes a chunk, splitting it in half on failure until the failing files
+/// are isolated and recorded in `scan_errors`. Only errors while recording a
+/// failure are returned.
+pub fn process_chunk_isolating_failures(
+    chunk: &[String],
+    conn: &Mutex<Connection>,
+    extractor: &dyn MetadataExtractor,
+    extra_tags: &[String],
+) -> Result<ScanSummary> {
+    match process_chunk(chunk, conn, extractor, extra_tags) {
+        Ok(missing) => {
+            let no_metadata = AppError::ExifTool("No metadata returned".to_string());
+            for file in &missing {
+                record_scan_error(conn, file, &no_metadata)?;
+            }
+            Ok(ScanSummary {
+                succeeded: chunk.len() - missing.len(),
+                failed: missing.len(),
+                ..ScanSummary::default()
+            })
+        }
+        Err(error) if chunk.len() == 1 => {
+            record_scan_error(conn, &chunk[0], &error)?;
+            Ok(ScanSummary {
+                failed: 1,
+                ..ScanSummary::default()
+            })
+        }
+        Err(_) => {
+            let (left, right) = chunk.split_at(chunk.len() / 2);
+            let left = process_chunk_isolating_failures(left, conn, extractor, extra_tags)?;
+            let right = process_chunk_isolating_failures(right, conn, extractor, extra_tags)?;
+            Ok(left.merge(right))
+        }
+    }
 }
 
-pub fn process_files_in_parallel(files: Vec<String>, config: &Config) -> Result<()> {
+/// Extracts and stores metadata for the new and changed files among `files`
+/// (or all of them when `config.force` is set).
+pub fn process_files_in_parallel(files: Vec<String>, config: &Config) -> Result<ScanSummary> {
     if files.is_empty() {
-        return Ok(());  
+        return Ok(ScanSummary::default());
     }
 
     let conn = init_database_connection(&config.database_path)?;
+    let extractor = create_extractor(config.backend, &config.extra_tags);
 
-    // We collect errors to detect if any chunk failed, though ideally we might want to report all errors.
-    // For now, we terminate on the first error found during iteration if we were to loop sequentially, 
-    // but with par_chunks we need to decide how to aggregate.
-    // The previous implementation suppressed errors.
-    // We will use try_for_each to propagate the first error encountered.
-    
-    files.par_chunks(50).try_for_each(|chunk| {
-        process_chunk(chunk, &conn)
-    })?;
+    let plan = {
+        let db_conn = conn.lock().map_err(|_| AppError::Processing("Database mutex poisoned".to_string()))?;
+        if let Ok(root) = canonical_path(&config.directory) {
+            rebase_cataloged_files(&db_conn, &root)?;
+        }
+        plan_scan(&db_conn, files, config.force)?
+    };
+
+    let summary = plan
+        .to_process
+        .par_chunks(50)
+        .map(|chunk| process_chunk_isolating_failures(chunk, &conn, extractor.as_ref(), &config.extra_tags))
+        .try_reduce(ScanSummary::default, |a, b| Ok(a.merge(b)))?;
+
+    Ok(ScanSummary {
+        new: plan.new,
+        changed: plan.changed,
+        unchanged: plan.unchanged,
+        ..summary
+    })
+}
 
-    Ok(())
+/// Brings the derived tables up to date with a finished scan: detects
+/// RAW+JPEG pairs, maps camera and lens names, and geocodes the new and moved
+/// photos.
+pub fn finish_scan(conn: &Connection, config: &Config) -> Result<FinishReport> {
+    Ok(FinishReport {
+        pairs: detect_pairs(conn)?,
+        cameras: normalize_cameras(conn, &CameraNames::new(&config.camera_aliases))?,
+        lenses: normalize_lenses(conn, &LensNames::new(&config.lens_aliases))?,
+        placed: geocode_catalog(conn, &config.geocoding)?,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::migrations::run_migrations;
     use tempfile::tempdir;
     use std::fs::File;
     use std::io::Write;
@@ -87,55 +307,48 @@ mod tests {
     fn setup_test_db() -> (tempfile::TempDir, std::path::PathBuf) {
         let temp_dir = tempdir().unwrap();
         let db_path = temp_dir.path().join("test.db");
-        let conn = Connection::open(&db_path).unwrap();
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS metadata (
-                source_file TEXT PRIMARY KEY,
-                mod_time REAL,
-                DateTimeOriginal TEXT,
-                Model TEXT,
-                LensModel TEXT,
-                ISO TEXT,
-                ExposureTime TEXT,
-                FNumber TEXT,
-                FocalLength TEXT,
-                Flash TEXT,
-                WhiteBalance TEXT,
-                ImageWidth TEXT,
-                ImageHeight TEXT,
-                FocalLengthIn35mmFormat TEXT
-            )",
-            [],
-        ).unwrap();
+        open_database(&db_path).unwrap();
 
         (temp_dir, db_path)
     }
 
-    fn test_config() -> Config {
+    fn test_config(database_path: &std::path::Path) -> Config {
         Config {
-            database_path: std::path::PathBuf::from("test.db"),
-            directory: ".".to_string(),
+            database_path: database_path.to_path_buf(),
+            ..Config::default()
         }
     }
 
     #[test]
     fn test_worker_parallel_processing() {
-        let _result = process_files_in_parallel(vec!["test1.jpg".to_string(), "test2.jpg".to_string()], &test_config());
-        // Now that we propagate errors, this will fail because the files don't exist
-        assert!(_result.is_err(), "Parallel processing should fail for nonexistent files");
+        let (_temp_dir, db_path) = setup_test_db();
+        let result = process_files_in_parallel(vec!["test1.jpg".to_string(), "test2.jpg".to_string()], &test_config(&db_path));
+        // Failures are recorded per file instead of aborting the scan
+        let summary = result.expect("Parallel processing should finish despite failing files");
+        assert_eq!(summary.failed, 2);
+        assert_eq!(summary.succeeded, 0);
     }
 
     #[test]
     fn test_empty_files_array() {
-        let result = process_files_in_parallel(vec![], &test_config());
+        let (_temp_dir, db_path) = setup_test_db();
+        let result = process_files_in_parallel(vec![], &test_config(&db_path));
         assert!(result.is_ok(), "Processing empty files array should succeed");
+        assert_eq!(result.unwrap(), ScanSummary::default());
     }
 
     #[test]
     fn test_nonexistent_files() {
-        let result = process_files_in_parallel(vec!["nonexistent1.jpg".to_string(), "nonexistent2.jpg".to_string()], &test_config());
-        assert!(result.is_err(), "Processing nonexistent files should fail");
+        let (_temp_dir, db_path) = setup_test_db();
+        let files = vec!["nonexistent1.jpg".to_string(), "nonexistent2.jpg".to_string()];
+        let summary = process_files_in_parallel(files, &test_config(&db_path)).unwrap();
+        assert_eq!(summary.failed, 2, "Nonexistent files should be reported as failed");
+
+        let conn = Connection::open(&db_path).unwrap();
+        let recorded: i64 = conn
+            .query_row("SELECT COUNT(*) FROM scan_errors", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(recorded, 2);
     }
 
     #[test]
@@ -148,7 +361,7 @@ mod tests {
         
         let config = Config {
             database_path: invalid_db_path,
-            directory: ".".to_string(),
+            ..Config::default()
         };
 
         let result = process_files_in_parallel(vec!["test1.jpg".to_string()], &config);
@@ -197,12 +410,191 @@ mod tests {
 
         let files = vec!["test1.jpg".to_string()];
         let metadata = vec![serde_json::json!({
+            "SourceFile": "test1.jpg",
             "DateTimeOriginal": "2023:01:01 12:00:00",
             "Model": "Test Camera"
         })];
 
 
-        let result = process_files_in_transaction(&transaction, &files, &metadata);
+        let result = process_files_in_transaction(&transaction, &files, &metadata, &[]);
         assert!(result.is_ok(), "Transaction should succeed");
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_plan_scan_skips_unchanged_files() {
+        let temp_dir = tempdir().unwrap();
+        let unchanged = temp_dir.path().join("unchanged.jpg");
+        let changed = temp_dir.path().join("changed.jpg");
+        let new = temp_dir.path().join("new.jpg");
+        for path in [&unchanged, &changed, &new] {
+            std::fs::write(path, "test").unwrap();
+        }
+        let files: Vec<String> = [&unchanged, &changed, &new].iter().map(|p| p.display().to_string()).collect();
+
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let empty = serde_json::json!({});
+        insert_metadata(&conn, &files[0], get_file_mod_time_secs(&files[0]), 4, &empty).unwrap();
+        insert_metadata(&conn, &files[1], get_file_mod_time_secs(&files[1]), 3, &empty).unwrap();
+
+        let plan = plan_scan(&conn, files.clone(), false).unwrap();
+        assert_eq!((plan.new, plan.changed, plan.unchanged), (1, 1, 1));
+        assert_eq!(plan.to_process, vec![files[1].clone(), files[2].clone()]);
+
+        let forced = plan_scan(&conn, files.clone(), true).unwrap();
+        assert_eq!((forced.new, forced.changed, forced.unchanged), (1, 1, 1));
+        assert_eq!(forced.to_process, files);
+    }
+
+    #[test]
+    fn test_normalize_source_path() {
+        assert_eq!(normalize_source_path("./photos/a.jpg"), "photos/a.jpg");
+        assert_eq!(normalize_source_path("C:\\photos\\a.jpg"), "C:/photos/a.jpg");
+        assert_eq!(normalize_source_path("/nas//photos/./a.jpg"), "/nas/photos/a.jpg");
+    }
+
+    /// Returns metadata in reverse order and silently drops one file, the way
+    /// ExifTool behaves when it cannot read a file.
+    struct DroppingExtractor {
+        dropped: String,
+    }
+
+    impl MetadataExtractor for DroppingExtractor {
+        fn extract(&self, file_paths: &[String]) -> Result<Vec<Value>> {
+            Ok(file_paths
+                .iter()
+                .rev()
+                .filter(|path| **path != self.dropped)
+                .map(|path| serde_json::json!({
+                    "SourceFile": path.replace('\\', "/"),
+                    "Model": format!("Camera for {}", path),
+                }))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_process_chunk_matches_metadata_by_source_file() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let conn = Mutex::new(conn);
+
+        let files: Vec<String> = ["a.jpg", "b.jpg", "c.jpg", "d.jpg"].iter().map(|f| f.to_string()).collect();
+        let extractor = DroppingExtractor { dropped: "b.jpg".to_string() };
+
+        let missing = process_chunk(&files, &conn, &extractor, &[]).unwrap();
+        assert_eq!(missing, vec!["b.jpg".to_string()]);
+
+        let conn = conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT source_file, Model FROM metadata ORDER BY source_file").unwrap();
+        let rows: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .filter_map(std::result::Result::ok)
+            .collect();
+
+        assert_eq!(rows.len(), 3);
+        for (file, model) in rows {
+            assert_eq!(model, format!("Camera for {}", file));
+        }
+    }
+
+    /// Fails every request that contains the corrupt file.
+    struct CorruptFileExtractor {
+        corrupt: String,
+    }
+
+    impl MetadataExtractor for CorruptFileExtractor {
+        fn extract(&self, file_paths: &[String]) -> Result<Vec<Value>> {
+            if file_paths.contains(&self.corrupt) {
+                return Err(AppError::ExifTool(format!("Corrupt file {}", self.corrupt)));
+            }
+            Ok(file_paths.iter().map(|path| serde_json::json!({ "SourceFile": path })).collect())
+        }
+    }
+
+    #[test]
+    fn test_failing_chunk_is_split_until_file_is_isolated() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let conn = Mutex::new(conn);
+
+        let files: Vec<String> = (0..7).map(|i| format!("photo{}.cr3", i)).collect();
+        let extractor = CorruptFileExtractor { corrupt: "photo4.cr3".to_string() };
+
+        let summary = process_chunk_isolating_failures(&files, &conn, &extractor, &[]).unwrap();
+        assert_eq!(summary.succeeded, 6);
+        assert_eq!(summary.failed, 1);
+
+        let conn = conn.lock().unwrap();
+        let (file, kind): (String, String) = conn
+            .query_row("SELECT source_file, error_kind FROM scan_errors", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(file, "photo4.cr3");
+        assert_eq!(kind, "ExifTool");
+    }
+
+    #[test]
+    fn test_rescanning_broken_file_keeps_one_error() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let conn = Mutex::new(conn);
+
+        let files = vec!["broken.cr3".to_string()];
+        let extractor = CorruptFileExtractor { corrupt: "broken.cr3".to_string() };
+        for _ in 0..2 {
+            let summary = process_chunk_isolating_failures(&files, &conn, &extractor, &[]).unwrap();
+            assert_eq!(summary.failed, 1);
+        }
+
+        let conn = conn.lock().unwrap();
+        let errors: i64 = conn
+            .query_row("SELECT COUNT(*) FROM scan_errors WHERE source_file = 'broken.cr3'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn test_missing_metadata_fails_and_rescan_clears_error() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let conn = Mutex::new(conn);
+
+        let files: Vec<String> = ["a.jpg", "b.jpg", "c.jpg"].iter().map(|f| f.to_string()).collect();
+        let dropping = DroppingExtractor { dropped: "b.jpg".to_string() };
+        let summary = process_chunk_isolating_failures(&files, &conn, &dropping, &[]).unwrap();
+        assert_eq!((summary.succeeded, summary.failed), (2, 1));
+
+        let alone = process_chunk_isolating_failures(&files[1..2], &conn, &dropping, &[]).unwrap();
+        assert_eq!(alone.failed, 1);
+
+        let complete = DroppingExtractor { dropped: String::new() };
+        let summary = process_chunk_isolating_failures(&files[1..2], &conn, &complete, &[]).unwrap();
+        assert_eq!((summary.succeeded, summary.failed), (1, 0));
+
+        let conn = conn.lock().unwrap();
+        let errors: i64 = conn
+            .query_row("SELECT COUNT(*) FROM scan_errors", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(errors, 0);
+    }
+
+    #[test]
+    fn test_finish_scan_updates_derived_tables() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let shot = serde_json::json!({
+            "DateTimeOriginal": "2023:05:01 09:00:00",
+            "Make": "NIKON CORPORATION",
+            "Model": "NIKON Z 6",
+            "LensModel": "NIKKOR Z 24-70mm f/4 S",
+        });
+        for file in ["/a/DSC_0001.NEF", "/a/DSC_0001.JPG"] {
+            insert_metadata(&conn, file, 0.0, 0, &shot).unwrap();
+        }
+
+        let finished = finish_scan(&conn, &Config::default()).unwrap();
+        assert_eq!(finished, FinishReport { pairs: 1, cameras: 1, lenses: 1, placed: 0 });
     }
 }
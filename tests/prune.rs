@@ -0,0 +1,50 @@
+use assert_cmd::Command;
+use photo_statistics::database::{insert_metadata, open_database};
+use photo_statistics::files::canonical_path;
+use rusqlite::Connection;
+use tempfile::tempdir;
+
+fn cataloged_files(database: &std::path::Path) -> Vec<String> {
+    let conn = Connection::open(database).unwrap();
+    let mut stmt = conn.prepare("SELECT source_file FROM metadata ORDER BY source_file").unwrap();
+    stmt.query_map([], |row| row.get(0)).unwrap().map(|file| file.unwrap()).collect()
+}
+
+#[test]
+fn test_prune_from_another_working_directory() {
+    let library = tempdir().unwrap();
+    let elsewhere = tempdir().unwrap();
+    let root = canonical_path(library.path().to_str().unwrap()).unwrap();
+    std::fs::create_dir(root.join("trip")).unwrap();
+    std::fs::write(root.join("trip/kept.jpg"), "test").unwrap();
+    std::fs::write(elsewhere.path().join("notes.txt"), "test").unwrap();
+
+    let kept = root.join("trip/kept.jpg").display().to_string();
+    let deleted = root.join("trip/deleted.jpg").display().to_string();
+    let database = library.path().join("catalog.db");
+    {
+        let conn = open_database(&database).unwrap();
+        for file in [&kept, &deleted, &"./trip/kept.jpg".to_string()] {
+            insert_metadata(&conn, file, 1234567890.0, 0, &serde_json::json!({})).unwrap();
+        }
+    }
+
+    // Nothing is cataloged under the other directory, whatever relative rows say.
+    Command::new(assert_cmd::cargo::cargo_bin("photo-statistics"))
+        .current_dir(elsewhere.path())
+        .args(["prune", ".", "-d"])
+        .arg(&database)
+        .assert()
+        .success();
+    assert_eq!(cataloged_files(&database).len(), 3);
+
+    Command::new(assert_cmd::cargo::cargo_bin("photo-statistics"))
+        .current_dir(elsewhere.path())
+        .arg("prune")
+        .arg(library.path())
+        .arg("-d")
+        .arg(&database)
+        .assert()
+        .success();
+    assert_eq!(cataloged_files(&database), vec!["./trip/kept.jpg".to_string(), kept]);
+}